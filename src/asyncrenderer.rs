@@ -117,9 +117,7 @@ impl AsyncRenderer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use color::colormapper::{CONTROL_POINT_GENE_SIZE, NUM_COLOR_GENES};
-    use formulas::{FORMULA_GENE_SIZE, NUM_FORMULA_GENES};
-    use genetics::{Chromosome, Genome};
+    use genetics::Genome;
     use renderer::{Image, PlasmaRenderer};
     use settings::RenderingSettings;
     use std::thread::sleep;
@@ -129,24 +127,6 @@ mod tests {
      *  Helper functions
      */
 
-    fn dummy_settings() -> RenderingSettings {
-        RenderingSettings {
-            dithering: false,
-            frames_per_second: 16.0,
-            loop_duration: 60.0,
-            palette_size: None,
-            width: 32,
-            height: 32
-        }
-    }
-
-    fn rand_genome() -> Genome {
-        Genome {
-            pattern: Chromosome::rand(NUM_FORMULA_GENES, FORMULA_GENE_SIZE),
-            color: Chromosome::rand(NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE)
-        }
-    }
-
     fn wait_for_image(renderer: &mut AsyncRenderer) -> Image {
         for _ in 0..100 {
             if let Some(image) = renderer.get_image() {
@@ -164,8 +144,8 @@ mod tests {
     #[test]
     fn test_asyncrenderer_singlerender() {
         // Make a request
-        let genome = rand_genome();
-        let mut ar = AsyncRenderer::new(&dummy_settings());
+        let genome = Genome::rand();
+        let mut ar = AsyncRenderer::new(&RenderingSettings::for_test());
         ar.set_genome(&genome);
         ar.render(32, 32, 0.0);
 
@@ -174,7 +154,7 @@ mod tests {
         let image1 = wait_for_image(&mut ar);
 
         // Compare image with regular Renderer
-        let mut r = PlasmaRenderer::new(&genome, &dummy_settings());
+        let mut r = PlasmaRenderer::new(&genome, &RenderingSettings::for_test());
         let mut image2 = Image::new(32, 32);
         r.render(&mut image2, 0.0);
         assert_eq!(image1.pixel_data, image2.pixel_data);
@@ -183,8 +163,8 @@ mod tests {
     #[test]
     fn test_asyncrenderer_cancellation() {
         // Warm up the AsyncRenderer by making a small request and waiting for it to finish
-        let mut ar = AsyncRenderer::new(&dummy_settings());
-        ar.set_genome(&rand_genome());
+        let mut ar = AsyncRenderer::new(&RenderingSettings::for_test());
+        ar.set_genome(&Genome::rand());
         ar.render(2, 2, 0.0);
         wait_for_image(&mut ar);
 
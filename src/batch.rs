@@ -0,0 +1,166 @@
+use file::generate_gif_bytes;
+use genetics::Genome;
+use settings::{BatchSettings, RenderingSettings};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::thread;
+
+/*
+ * Rendering lots of genomes to GIFs at once.
+ *
+ * Every genome gets its own file in the output directory, named after a template. Files that
+ * already exist are skipped, so an interrupted batch can be resumed by running it again. Each
+ * file is written under a temporary name first, so an interrupted one is never mistaken for done.
+ */
+
+// Outcome of rendering one genome
+enum Outcome {
+    Rendered,
+    Skipped,
+    Failed(String)
+}
+
+// Renders each genome to its own file, several at a time. Returns an error if any of them failed.
+pub fn run_batch(genomes: &[Genome], rendering_settings: &RenderingSettings,
+                 batch_settings: &BatchSettings) -> Result<(), String> {
+    if genomes.is_empty() {
+        return Err("No genomes to render".to_string());
+    }
+
+    // Worker threads take jobs from a shared queue, and report back when each one is done
+    let jobs = plan_jobs(genomes, batch_settings)?;
+    let num_jobs = jobs.len();
+    let queue = Arc::new(Mutex::new(jobs.into_iter()));
+    let (outcome_tx, outcome_rx) = mpsc::channel();
+    for _ in 0..batch_settings.num_threads.min(num_jobs) {
+        let queue = queue.clone();
+        let outcome_tx = outcome_tx.clone();
+        let settings = rendering_settings.clone();
+        thread::spawn(move || {
+            loop {
+                let job = queue.lock().unwrap().next();
+                match job {
+                    Some((genome, path)) => {
+                        let outcome = render(&genome, &path, &settings);
+                        outcome_tx.send((path, outcome)).unwrap();
+                    },
+                    None => break
+                }
+            }
+        });
+    }
+    drop(outcome_tx);
+
+    // Report progress as files are finished, in whatever order that happens
+    let mut num_failed = 0;
+    let mut num_reported = 0;
+    for (i, (path, outcome)) in outcome_rx.iter().enumerate() {
+        num_reported += 1;
+        let status = match outcome {
+            Outcome::Rendered => "rendered".to_string(),
+            Outcome::Skipped => "skipped, already exists".to_string(),
+            Outcome::Failed(message) => {
+                num_failed += 1;
+                format!("failed: {}", message)
+            }
+        };
+        writeln!(&mut ::std::io::stderr(), "[{}/{}] {} ({})",
+                 i + 1, num_jobs, path.display(), status).unwrap();
+    }
+
+    // Jobs whose threads panicked never get reported, and neither do any left over if they all did
+    num_failed += num_jobs - num_reported;
+    if num_failed > 0 {
+        return Err(format!("Couldn't render {} of {} genomes", num_failed, num_jobs));
+    }
+    Ok(())
+}
+
+// Pairs each genome with the file it goes in. A genome given more than once only gets one job,
+// if its file name is the same each time.
+fn plan_jobs(genomes: &[Genome],
+             batch_settings: &BatchSettings) -> Result<Vec<(Genome, PathBuf)>, String> {
+    let mut jobs: Vec<(Genome, PathBuf)> = vec![];
+    for (i, genome) in genomes.iter().enumerate() {
+        let name = file_name(&batch_settings.name_template, genome, i);
+        let path = Path::new(&batch_settings.directory).join(name);
+        match jobs.iter().find(|&&(_, ref other_path)| *other_path == path) {
+            Some(&(ref other_genome, _)) if other_genome == genome => (),
+            Some(_) => {
+                return Err(format!("More than one genome would be written to {}", path.display()))
+            },
+            None => jobs.push((genome.clone(), path))
+        }
+    }
+    Ok(jobs)
+}
+
+// Fills in a template like "{genome}.gif" or "plasma-{index}.gif". Indexes start at 1.
+fn file_name(template: &str, genome: &Genome, index: usize) -> String {
+    template
+        .replace("{genome}", &genome.to_base64())
+        .replace("{index}", &(index + 1).to_string())
+}
+
+fn render(genome: &Genome, path: &Path, settings: &RenderingSettings) -> Outcome {
+    if path.exists() {
+        return Outcome::Skipped;
+    }
+    let gif_bytes = generate_gif_bytes(genome, settings);
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let written = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(&gif_bytes[..])
+    }).and_then(|_| {
+        fs::rename(&temp_path, path)
+    });
+    match written {
+        Ok(()) => Outcome::Rendered,
+        Err(e) => Outcome::Failed(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use commands;
+    use settings::BatchSettings;
+    use std::path::Path;
+    use super::{file_name, plan_jobs};
+
+    #[test]
+    fn test_file_name() {
+        let genome = commands::random(1).pop().unwrap();
+        let base64 = genome.to_base64();
+        assert_eq!(file_name("{genome}.gif", &genome, 0), format!("{}.gif", base64));
+        assert_eq!(file_name("plasma-{index}.gif", &genome, 4), "plasma-5.gif");
+        assert_eq!(file_name("{index}-{genome}.gif", &genome, 0), format!("1-{}.gif", base64));
+    }
+
+    #[test]
+    fn test_plan_jobs() {
+        let genomes = commands::random(2);
+        let mut settings = BatchSettings {
+            directory: "out".to_string(),
+            name_template: "{genome}.gif".to_string(),
+            num_threads: 1
+        };
+
+        // Repeated genomes are only rendered once
+        let repeated = [genomes[0].clone(), genomes[1].clone(), genomes[0].clone()];
+        let jobs = plan_jobs(&repeated, &settings).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[1].0, genomes[1]);
+        assert_eq!(jobs[1].1, Path::new("out").join(format!("{}.gif", genomes[1].to_base64())));
+
+        // Unless each one has a file of its own
+        settings.name_template = "{index}.gif".to_string();
+        assert_eq!(plan_jobs(&repeated, &settings).unwrap().len(), 3);
+
+        // Different genomes can't share a file
+        settings.name_template = "plasma.gif".to_string();
+        assert!(plan_jobs(&genomes, &settings).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use cgmath::Vector3;
+use cgmath::prelude::*;
 
+// Exponent of the simple power-law transfer function
 const GAMMA: f32 = 2.2;
 
 /**
@@ -26,14 +28,135 @@ pub struct LinearColor {
     pub b: u16
 }
 
+/**
+ * The space that colors are mixed and compared in.
+ *
+ * Linear RGB is how light physically mixes. OKLab is perceptually uniform: equal distances in it
+ * look about equally different, so gradients fade evenly and palettes don't waste entries on
+ * colors that are hard to tell apart.
+ */
+#[derive(Copy,Clone,Eq,PartialEq,Debug)]
+pub enum ColorSpace {
+    LinearRgb,
+    Oklab
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Result<ColorSpace, String> {
+        match name {
+            "linear" => Ok(ColorSpace::LinearRgb),
+            "oklab" => Ok(ColorSpace::Oklab),
+            _ => Err(format!("Unknown color space {} (try linear or oklab)", name))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ColorSpace::LinearRgb => "linear",
+            ColorSpace::Oklab => "oklab"
+        }
+    }
+}
+
+/**
+ * How colors are gamma encoded: the curve between linear light and 8-bit color values.
+ *
+ * A plain 2.2 power law is a close approximation of sRGB, which is what displays and GIF viewers
+ * actually expect. The real sRGB curve has a short linear segment near black, so dark colors come
+ * out slightly differently.
+ */
+#[derive(Copy,Clone,Eq,PartialEq,Debug)]
+pub enum TransferFunction {
+    Gamma22,
+    Srgb
+}
+
+impl TransferFunction {
+    pub fn from_name(name: &str) -> Result<TransferFunction, String> {
+        match name {
+            "gamma" => Ok(TransferFunction::Gamma22),
+            "srgb" => Ok(TransferFunction::Srgb),
+            _ => Err(format!("Unknown transfer function {} (try srgb or gamma)", name))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TransferFunction::Gamma22 => "gamma",
+            TransferFunction::Srgb => "srgb"
+        }
+    }
+
+    // Converts a gamma-encoded value in the range [0.0, 1.0] to linear
+    fn decode(&self, gamma_float: f32) -> f32 {
+        match *self {
+            TransferFunction::Gamma22 => gamma_float.powf(GAMMA),
+            TransferFunction::Srgb => if gamma_float <= 0.04045 {
+                gamma_float/12.92
+            } else {
+                ((gamma_float + 0.055)/1.055).powf(2.4)
+            }
+        }
+    }
+
+    // Runs f with the lookup tables for this transfer function, which are built on first use
+    fn with_tables<F, T>(&self, f: F) -> T where F: FnOnce(&TransferTables) -> T {
+        match *self {
+            TransferFunction::Gamma22 => GAMMA_22_TABLES.with(f),
+            TransferFunction::Srgb => SRGB_TABLES.with(f)
+        }
+    }
+}
+
+// Precomputed conversions in both directions, since calling powf() for every pixel is slow
+struct TransferTables {
+    to_linear: Vec<u16>, // Indexed by gamma-encoded component
+    to_gamma: Vec<u8>    // Indexed by linear component
+}
+
+impl TransferTables {
+    fn new(transfer_function: TransferFunction) -> TransferTables {
+        /*
+         * Hack to fit a linear color component in a u16, while allowing round-trip conversion
+         *
+         * If we called round() to get the nearest u16, inputs 0 and 1 would have the same output:
+         *      65535.0*(0.0/255.0)**2.2 = 0.0      (rounds to 0)
+         *      65535.0*(1.0/255.0)**2.2 = 0.3327   (also rounds to 0)
+         * This would result in loss of information: Color::new(1, 1, 1).to_linear() would return
+         * the same thing as Color::new(0, 0, 0).to_linear().
+         *
+         * To avoid that, we call ceil() to get the nearest u16. In reverse (linear to gamma), each
+         * linear value goes to the brightest gamma-encoded value that isn't brighter than it, which
+         * is the same as calling floor(). Every gamma-encoded value then converts back to itself.
+         */
+        let to_linear: Vec<u16> = (0..256).map(|c| {
+            let linear_float = transfer_function.decode(c as f32/255.0);
+            (linear_float*65535.0).ceil() as u16
+        }).collect();
+        let mut to_gamma = Vec::with_capacity(65536);
+        let mut gamma = 0;
+        for c in 0..65536 {
+            while gamma < 255 && to_linear[gamma + 1] as usize <= c {
+                gamma += 1;
+            }
+            to_gamma.push(gamma as u8);
+        }
+        TransferTables { to_linear: to_linear, to_gamma: to_gamma }
+    }
+}
+
+thread_local! {
+    static GAMMA_22_TABLES: TransferTables = TransferTables::new(TransferFunction::Gamma22);
+    static SRGB_TABLES: TransferTables = TransferTables::new(TransferFunction::Srgb);
+}
 
 impl Color {
     pub fn new(r: u8, g: u8, b: u8) -> Color {
         Color { r: r, g: g, b: b }
     }
 
-    pub fn to_linear(&self) -> LinearColor {
-        LinearColor::from_gamma(*self)
+    pub fn to_linear(&self, transfer_function: TransferFunction) -> LinearColor {
+        LinearColor::from_gamma(*self, transfer_function)
     }
 }
 
@@ -60,45 +183,20 @@ impl LinearColor {
         } / 65535.0
     }
 
-    fn component_to_linear(c: u8) -> u16 {
-        let gamma_float = (c as f32)/255.0;
-        let linear_float = gamma_float.powf(GAMMA);
-        /*
-         * Hack to fit a linear color component in a u16, while allowing round-trip conversion
-         *
-         * If we called round() to get the nearest u16, inputs 0 and 1 would have the same output:
-         *      65535.0*(0.0/255.0)**2.2 = 0.0      (rounds to 0)
-         *      65535.0*(1.0/255.0)**2.2 = 0.3327   (also rounds to 0)
-         * This would result in loss of information: Color::new(1, 1, 1).to_linear() would return
-         * the same thing as Color::new(0, 0, 0).to_linear().
-         *
-         * To avoid that, we call ceil() to get the nearest u16. Similarly, when we go in reverse
-         * (linear to gamma), we call floor(). With a gamma of 2.2, this nudging-of-the-numbers is
-         * just barely enough to avoid loss of information when doing round-trip conversions.
-         */
-        (linear_float*65535.0).ceil() as u16
+    pub fn to_gamma(&self, transfer_function: TransferFunction) -> Color {
+        transfer_function.with_tables(|tables| Color {
+            r: tables.to_gamma[self.r as usize],
+            g: tables.to_gamma[self.g as usize],
+            b: tables.to_gamma[self.b as usize]
+        })
     }
 
-    fn component_to_gamma(c: u16) -> u8 {
-        let linear_float = (c as f32)/65535.0;
-        let gamma_float = linear_float.powf(1.0/GAMMA);
-        (gamma_float*255.0).floor() as u8
-    }
-
-    pub fn to_gamma(&self) -> Color {
-        Color {
-            r: LinearColor::component_to_gamma(self.r),
-            g: LinearColor::component_to_gamma(self.g),
-            b: LinearColor::component_to_gamma(self.b)
-        }
-    }
-
-    pub fn from_gamma(c: Color) -> LinearColor {
-        LinearColor {
-            r: LinearColor::component_to_linear(c.r),
-            g: LinearColor::component_to_linear(c.g),
-            b: LinearColor::component_to_linear(c.b)
-        }
+    pub fn from_gamma(c: Color, transfer_function: TransferFunction) -> LinearColor {
+        transfer_function.with_tables(|tables| LinearColor {
+            r: tables.to_linear[c.r as usize],
+            g: tables.to_linear[c.g as usize],
+            b: tables.to_linear[c.b as usize]
+        })
     }
 
     pub fn lerp(&self, other: LinearColor, position: f32) -> LinearColor {
@@ -111,13 +209,74 @@ impl LinearColor {
             b: lerp(self.b, other.b),
         }
     }
-}
 
+    // Like lerp(), but mixes the colors in the given color space
+    pub fn mix(&self, other: LinearColor, position: f32, color_space: ColorSpace) -> LinearColor {
+        match color_space {
+            ColorSpace::LinearRgb => self.lerp(other, position),
+            ColorSpace::Oklab => {
+                assert!(position >= 0.0 && position <= 1.0);
+                let mixed = self.to_oklab()*(1.0 - position) + other.to_oklab()*position;
+                LinearColor::from_oklab(&mixed)
+            }
+        }
+    }
+
+    // OKLab coordinates (L, a, b), as described at https://bottosson.github.io/posts/oklab/
+    pub fn to_oklab(&self) -> Vector3<f32> {
+        let v = self.to_vec3();
+        let l = (0.4122214708*v.x + 0.5363325363*v.y + 0.0514459929*v.z).cbrt();
+        let m = (0.2119034982*v.x + 0.6806995451*v.y + 0.1073969566*v.z).cbrt();
+        let s = (0.0883024619*v.x + 0.2817188376*v.y + 0.6299787005*v.z).cbrt();
+        Vector3 {
+            x: 0.2104542553*l + 0.7936177850*m - 0.0040720468*s,
+            y: 1.9779984951*l - 2.4285922050*m + 0.4505937099*s,
+            z: 0.0259040371*l + 0.7827717662*m - 0.8086757660*s
+        }
+    }
+
+    // The inverse of to_oklab(). Colors outside of the RGB gamut are clipped.
+    pub fn from_oklab(lab: &Vector3<f32>) -> LinearColor {
+        let l = (lab.x + 0.3963377774*lab.y + 0.2158037573*lab.z).powi(3);
+        let m = (lab.x - 0.1055613458*lab.y - 0.0638541728*lab.z).powi(3);
+        let s = (lab.x - 0.0894841775*lab.y - 1.2914855480*lab.z).powi(3);
+        let clip = |c: f32| c.max(0.0).min(1.0);
+        LinearColor::new_vec3(&Vector3 {
+            x: clip(4.0767416621*l - 3.3077115913*m + 0.2309699292*s),
+            y: clip(-1.2684380046*l + 2.6097574011*m - 0.3413193965*s),
+            z: clip(-0.0041960863*l - 0.7034186147*m + 1.7076146010*s)
+        })
+    }
+
+    // Coordinates in a color space, for interpolating between more than two colors at once
+    pub fn to_coordinates(&self, color_space: ColorSpace) -> Vector3<f32> {
+        match color_space {
+            ColorSpace::LinearRgb => self.to_vec3(),
+            ColorSpace::Oklab => self.to_oklab()
+        }
+    }
+
+    // How different two colors are: the straight-line distance between them in a color space
+    pub fn distance(&self, other: LinearColor, color_space: ColorSpace) -> f32 {
+        (self.to_coordinates(color_space) - other.to_coordinates(color_space)).magnitude()
+    }
+
+    // The inverse of to_coordinates(). Colors outside of the RGB gamut are clipped.
+    pub fn from_coordinates(v: &Vector3<f32>, color_space: ColorSpace) -> LinearColor {
+        match color_space {
+            ColorSpace::LinearRgb => {
+                let clip = |c: f32| c.max(0.0).min(1.0);
+                LinearColor::new_f32(clip(v.x), clip(v.y), clip(v.z))
+            },
+            ColorSpace::Oklab => LinearColor::from_oklab(v)
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use std::u16;
-    use super::{Color, LinearColor};
+    use super::{Color, ColorSpace, LinearColor, TransferFunction};
 
     #[test]
     fn test_linear_color_lerp() {
@@ -152,6 +311,43 @@ mod tests {
         assert_eq!(black.lerp(dark_blue, 0.76), dark_blue);
     }
 
+    #[test]
+    fn test_linear_color_mix() {
+        let black = LinearColor::new_f32(0.0, 0.0, 0.0);
+        let white = LinearColor::new_f32(1.0, 1.0, 1.0);
+        assert_eq!(black.mix(white, 0.5, ColorSpace::LinearRgb), black.lerp(white, 0.5));
+        assert_eq!(black.mix(white, 0.0, ColorSpace::Oklab), black);
+        assert_eq!(black.mix(white, 1.0, ColorSpace::Oklab), white);
+
+        // Perceptually halfway from black to white is much darker than physically halfway
+        let gray = black.mix(white, 0.5, ColorSpace::Oklab);
+        assert!(gray.r == gray.g && gray.g == gray.b);
+        assert!(gray.r < black.lerp(white, 0.5).r/2);
+    }
+
+    #[test]
+    fn test_linear_color_oklab_conversion() {
+        for i in 0..256 {
+            let c = Color::new(i as u8, (i*7 % 256) as u8, 255 - i as u8);
+            let oklab = c.to_linear(TransferFunction::Srgb).to_oklab();
+            let round_trip = LinearColor::from_oklab(&oklab).to_gamma(TransferFunction::Srgb);
+            let difference = |a: u8, b: u8| (a as i32 - b as i32).abs();
+            assert!(difference(c.r, round_trip.r) <= 1 && difference(c.g, round_trip.g) <= 1 &&
+                    difference(c.b, round_trip.b) <= 1, "{:?} != {:?}", c, round_trip);
+        }
+        let white = LinearColor::new_f32(1.0, 1.0, 1.0).to_oklab();
+        assert!((white.x - 1.0).abs() < 0.001 && white.y.abs() < 0.001 && white.z.abs() < 0.001);
+    }
+
+    #[test]
+    fn test_linear_color_distance() {
+        let black = LinearColor::new(0, 0, 0);
+        let white = LinearColor::new(u16::MAX, u16::MAX, u16::MAX);
+        assert!((black.distance(white, ColorSpace::LinearRgb) - 3.0f32.sqrt()).abs() < 0.001);
+        assert!((black.distance(white, ColorSpace::Oklab) - 1.0).abs() < 0.001);
+        assert_eq!(white.distance(white, ColorSpace::Oklab), 0.0);
+    }
+
     #[test]
     fn test_linear_color_new_vec3() {
         let values = [0, 1, u16::MAX - 1, u16::MAX];
@@ -164,20 +360,43 @@ mod tests {
 
     #[test]
     fn test_color_linearcolor_conversion() {
-        // Test each channel
-        let c = Color::new(85, 170, 255);
-        assert_eq!(c, c.to_linear().to_gamma());
+        for &transfer_function in [TransferFunction::Gamma22, TransferFunction::Srgb].iter() {
+            // Test each channel
+            let c = Color::new(85, 170, 255);
+            assert_eq!(c, c.to_linear(transfer_function).to_gamma(transfer_function));
+
+            // Test all values for a single channel, make sure we can round-trip to linear and back
+            for i in 0..256 {
+                let c = Color::new(i as u8, 0, 0);
+                assert_eq!(c, c.to_linear(transfer_function).to_gamma(transfer_function));
+            }
+        }
 
         // Test gamma calculation for 50% gray
         assert_eq!(
-            LinearColor::new_f32(0.5, 0.5, 0.5).to_gamma(),
+            LinearColor::new_f32(0.5, 0.5, 0.5).to_gamma(TransferFunction::Gamma22),
             Color::new(186, 186, 186)
         );
+        assert_eq!(
+            LinearColor::new_f32(0.5, 0.5, 0.5).to_gamma(TransferFunction::Srgb),
+            Color::new(187, 187, 187)
+        );
 
-        // Test all values for a single channel, make sure we can round-trip to linear and back
-        for i in 0..256 {
-            let c = Color::new(i as u8, 0, 0);
-            assert_eq!(c, c.to_linear().to_gamma());
+        // The sRGB curve is linear near black, so dark colors are brighter than with a 2.2 gamma
+        let dark = Color::new(10, 10, 10);
+        let (srgb, gamma) = (TransferFunction::Srgb, TransferFunction::Gamma22);
+        assert!(dark.to_linear(srgb).r > dark.to_linear(gamma).r);
+        assert_eq!(Color::new(255, 255, 255).to_linear(TransferFunction::Srgb),
+                   LinearColor::new(65535, 65535, 65535));
+    }
+
+    #[test]
+    fn test_transfer_function_from_name() {
+        assert_eq!(TransferFunction::from_name("srgb"), Ok(TransferFunction::Srgb));
+        assert_eq!(TransferFunction::from_name("gamma"), Ok(TransferFunction::Gamma22));
+        assert!(TransferFunction::from_name("linear").is_err());
+        for &tf in [TransferFunction::Gamma22, TransferFunction::Srgb].iter() {
+            assert_eq!(TransferFunction::from_name(tf.name()), Ok(tf));
         }
     }
 }
@@ -1,16 +1,22 @@
-use color::{Color, LinearColor};
-use color::gradient::{ControlPoint, Gradient};
+use color::{Color, LinearColor, TransferFunction};
+use color::gradient::{ControlPoint, Gradient, Interpolation};
 use color::palette::Palette;
-use color::palette::dither::DitherPattern;
+use color::palette::dither::{diffuse_errors, DitherPattern, Ditherer};
+use color::stops::control_points;
 use fastmath::FastMath;
 use genetics::{Chromosome, Gene};
 use settings::RenderingSettings;
 use std::{f32, u16};
 
 const LOOKUP_TABLE_SIZE: usize = 512;
-pub const NUM_COLOR_GENES: usize = 8;
+pub const NUM_COLOR_GENES: usize = 9;
 pub const CONTROL_POINT_GENE_SIZE: usize = 5;
 
+// The first color genes are control points. The one after them chooses how the gradient is
+// interpolated; genomes from older versions don't have it, and are interpolated linearly.
+const NUM_CONTROL_POINT_GENES: usize = 8;
+const INTERPOLATION_GENE: usize = 8; // Index of the interpolation gene, right after the points
+
 impl LinearColor {
     fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> LinearColor {
         let h = hue.wrap();
@@ -92,66 +98,192 @@ impl ControlPoint {
     }
 }
 
+impl Interpolation {
+    fn from_gene(gene: &Gene) -> Interpolation {
+        match gene.data[0]/64 {
+            0 => Interpolation::Linear,
+            1 => Interpolation::Smooth,
+            2 => Interpolation::Stepped(2 + gene.data[1] as usize % 7), // 2 to 8 steps
+            _ => Interpolation::Eased
+        }
+    }
+}
+
 pub struct ColorMapper {
+    color_cycling: bool,
     gamma_palette: Vec<Color>,
+    ditherer: Option<Ditherer>,
     lookup_table_nearest: Vec<u16>,
-    lookup_table_dithered: Vec<DitherPattern>
+    lookup_table_dithered: Vec<DitherPattern>,
+    lookup_table_linear: Vec<LinearColor>, // For error diffusion and temporal dithering
+    palette: Palette,
+    temporal_threshold: Option<f32>
+}
+
+// Decodes a color chromosome into the gradient it describes. The transfer function only matters
+// for chromosomes without any control points, whose gradient is plain gray.
+pub fn gradient_from_chromosome(chromosome: &Chromosome,
+                                transfer_function: TransferFunction) -> Gradient {
+    let control_points = chromosome.genes.iter().take(NUM_CONTROL_POINT_GENES).
+        filter_map(|g| ControlPoint::from_gene(&g)).collect();
+    let mut gradient = Gradient::new(control_points, transfer_function);
+    if let Some(gene) = chromosome.genes.get(INTERPOLATION_GENE) {
+        gradient.set_interpolation(Interpolation::from_gene(gene));
+    }
+    gradient
+}
+
+// The gradient to render a chromosome with: its own, or the one from the settings if there is one
+pub fn build_gradient(chromosome: &Chromosome, settings: &RenderingSettings) -> Gradient {
+    let mut gradient = match settings.gradient {
+        Some(ref stops) => Gradient::new(control_points(stops, settings.transfer_function),
+                                         settings.transfer_function),
+        None => gradient_from_chromosome(chromosome, settings.transfer_function)
+    };
+    gradient.set_color_space(settings.color_space);
+    gradient
+}
+
+// Samples the gradient from build_gradient() evenly
+pub fn sample_gradient(chromosome: &Chromosome, settings: &RenderingSettings) -> Vec<LinearColor> {
+    let gradient = build_gradient(chromosome, settings);
+    let sample_step = 1.0/LOOKUP_TABLE_SIZE as f32;
+    let sample_positions = (0..LOOKUP_TABLE_SIZE).map(|i| i as f32*sample_step);
+    sample_positions.map(|p| gradient.get_color(p)).collect()
+}
+
+// Builds a palette that covers the given gradient samples, unless the settings give one
+pub fn build_palette(samples: &[LinearColor], settings: &RenderingSettings) -> Palette {
+    fixed_palette(settings).unwrap_or_else(|| {
+        let palette_size = settings.palette_size.unwrap_or(LOOKUP_TABLE_SIZE);
+        Palette::new(palette_size, samples, settings.dithering, settings.color_space)
+    })
+}
+
+// The palette from the settings, if there is one
+pub fn fixed_palette(settings: &RenderingSettings) -> Option<Palette> {
+    settings.palette.as_ref().map(|colors| {
+        let linear_colors: Vec<LinearColor> = colors.iter().map(|color|
+            color.to_linear(settings.transfer_function)
+        ).collect();
+        Palette::fixed(&linear_colors, settings.color_space)
+    })
+}
+
+// Which of the samples from sample_gradient() any of the given gradient positions land on
+pub fn shown_gradient_samples(positions: &[f32]) -> Vec<bool> {
+    let mut shown = vec![false; LOOKUP_TABLE_SIZE];
+    for &position in positions {
+        shown[lookup_index(position)] = true;
+    }
+    shown
+}
+
+// Where a gradient position is in the lookup tables, and among the samples from sample_gradient()
+pub fn lookup_index(position: f32) -> usize {
+    let float_index = (position.wrap()*(LOOKUP_TABLE_SIZE as f32)).floor();
+    (float_index as usize) % LOOKUP_TABLE_SIZE
 }
 
 impl ColorMapper {
     pub fn new(chromosome: &Chromosome, settings: &RenderingSettings) -> ColorMapper {
-        // Build gradient and sample it
-        let control_points = chromosome.genes.iter().
-            filter_map(|g| ControlPoint::from_gene(&g)).collect();
-        let gradient = Gradient::new(control_points);
-        let sample_step = 1.0/LOOKUP_TABLE_SIZE as f32;
-        let sample_positions = (0..LOOKUP_TABLE_SIZE).map(|i| i as f32*sample_step);
-        let gradient_samples: Vec<_> = sample_positions.map(|p| gradient.get_color(p)).collect();
-
-        // Build a palette from the gradient samples
-        let palette_size = settings.palette_size.unwrap_or(LOOKUP_TABLE_SIZE);
-        let palette = Palette::new(palette_size, &gradient_samples, settings.dithering);
+        let gradient_samples = sample_gradient(chromosome, settings);
+        let palette = build_palette(&gradient_samples, settings);
+        ColorMapper::with_palette(&gradient_samples, &palette, settings)
+    }
+
+    // Maps gradient samples (as returned by sample_gradient) onto an existing palette
+    pub fn with_palette(gradient_samples: &[LinearColor], palette: &Palette,
+                        settings: &RenderingSettings) -> ColorMapper {
+        assert!(gradient_samples.len() == LOOKUP_TABLE_SIZE);
+        let ditherer = if settings.dithering { Some(settings.ditherer) } else { None };
 
         // Use the samples and the palette to build lookup tables
         let mut lookup_table_nearest = vec![];
         let mut lookup_table_dithered = vec![];
-        if settings.dithering {
-            // Build gradient-position -> precomputed-dither-pattern lookup table
-            lookup_table_dithered = gradient_samples.iter().map(
-                |&color| palette.get_dither_pattern(color)
-            ).collect();
+        let diffuses_errors = ditherer.map_or(false, |d| d.diffuses_errors());
+        let lookup_table_linear = if diffuses_errors || settings.temporal_threshold.is_some() {
+            gradient_samples.to_vec()
         } else {
-            // Build gradient-position -> nearest-palette-index lookup table
-            lookup_table_nearest = gradient_samples.iter().map(
-                |&color| palette.get_nearest_index(color) as u16
-            ).collect();
+            vec![]
+        };
+        match ditherer {
+            Some(d) if d.diffuses_errors() => (), // This works from lookup_table_linear
+            Some(_) => {
+                // Build gradient-position -> precomputed-dither-pattern lookup table
+                lookup_table_dithered = gradient_samples.iter().map(
+                    |&color| palette.get_dither_pattern(color)
+                ).collect();
+            },
+            None => {
+                // Build gradient-position -> nearest-palette-index lookup table
+                lookup_table_nearest = gradient_samples.iter().map(
+                    |&color| palette.get_nearest_index(color) as u16
+                ).collect();
+            }
         }
 
         // Gamma-encode palette and return finished ColorMapper
         ColorMapper {
-            gamma_palette: palette.colors.iter().map(|color| color.to_gamma()).collect(),
+            color_cycling: settings.color_cycling,
+            gamma_palette: palette.colors.iter().map(|color|
+                color.to_gamma(settings.transfer_function)
+            ).collect(),
+            ditherer: ditherer,
             lookup_table_nearest: lookup_table_nearest,
-            lookup_table_dithered: lookup_table_dithered
+            lookup_table_dithered: lookup_table_dithered,
+            lookup_table_linear: lookup_table_linear,
+            palette: palette.clone(),
+            temporal_threshold: settings.temporal_threshold
         }
     }
 
     pub fn get_nearest_color(&self, position: f32) -> Color {
         assert!(!self.lookup_table_nearest.is_empty(), "ColorMapper created with dithering on");
-        let float_index = (position.wrap()*(LOOKUP_TABLE_SIZE as f32)).floor();
-        let index = (float_index as usize) % LOOKUP_TABLE_SIZE;
-        let palette_index = self.lookup_table_nearest[index];
+        let palette_index = self.lookup_table_nearest[lookup_index(position)];
         self.gamma_palette[palette_index as usize]
     }
 
     pub fn get_dithered_color(&self, position: f32, x: usize, y: usize) -> Color {
-        assert!(!self.lookup_table_dithered.is_empty(), "ColorMapper created with dithering off");
-        let float_index = (position.wrap()*(LOOKUP_TABLE_SIZE as f32)).floor();
-        let index = (float_index as usize) % LOOKUP_TABLE_SIZE;
-        let dither_info = self.lookup_table_dithered[index];
-        let palette_index = dither_info.get_palette_index(x, y);
+        assert!(!self.lookup_table_dithered.is_empty(),
+                "ColorMapper created without ordered dithering");
+        let dither_info = self.lookup_table_dithered[lookup_index(position)];
+        let palette_index = dither_info.get_palette_index(x, y, self.ditherer.unwrap());
         self.gamma_palette[palette_index]
     }
 
+    // Whether animations should move the colors instead of the pattern
+    pub fn cycles_colors(&self) -> bool {
+        self.color_cycling
+    }
+
+    // Whether frames need to be dithered all at once, with get_diffused_colors()
+    pub fn diffuses_errors(&self) -> bool {
+        self.ditherer.map_or(false, |d| d.diffuses_errors())
+    }
+
+    // Whether two gradient positions' colors are within the temporal threshold of each other
+    pub fn is_close(&self, position: f32, other_position: f32) -> bool {
+        match self.temporal_threshold {
+            Some(threshold) => {
+                let color = self.lookup_table_linear[lookup_index(position)];
+                let other_color = self.lookup_table_linear[lookup_index(other_position)];
+                color.distance(other_color, self.palette.color_space()) <= threshold
+            },
+            None => false
+        }
+    }
+
+    // Dithers a whole frame of gradient positions, given row by row
+    pub fn get_diffused_colors(&self, positions: &[f32], width: usize) -> Vec<Color> {
+        assert!(self.diffuses_errors(), "ColorMapper created without error diffusion");
+        let colors: Vec<LinearColor> = positions.iter().map(|&position|
+            self.lookup_table_linear[lookup_index(position)]
+        ).collect();
+        let palette_indexes = diffuse_errors(&colors, width, &self.palette, self.ditherer.unwrap());
+        palette_indexes.into_iter().map(|i| self.gamma_palette[i]).collect()
+    }
+
     pub fn get_palette(&self) -> Vec<Color> {
         self.gamma_palette.clone()
     }
@@ -159,15 +291,20 @@ impl ColorMapper {
 
 #[cfg(test)]
 mod tests {
-    use genetics::Gene;
+    use genetics::{Chromosome, Gene};
     use cgmath::Vector3;
     use cgmath::prelude::*;
-    use color::{Color, LinearColor as LC};
-    use color::gradient::ControlPoint;
+    use color::{Color, LinearColor as LC, TransferFunction};
+    use color::ColorSpace;
+    use color::gradient::{ControlPoint, Interpolation};
+    use color::palette::Palette;
+    use settings::RenderingSettings;
+    use super::{ColorMapper, gradient_from_chromosome, shown_gradient_samples};
+    use super::{LOOKUP_TABLE_SIZE, NUM_COLOR_GENES};
 
     // Create a LinearColor with gamma-encoded u8 values
     fn new_gamma(r: u8, g: u8, b: u8) -> LC {
-        Color::new(r, g, b).to_linear()
+        Color::new(r, g, b).to_linear(TransferFunction::Gamma22)
     }
 
     #[test]
@@ -277,4 +414,78 @@ mod tests {
         let cp2 = ControlPoint::from_gene(&g2).unwrap();
         assert!(cp1.position != cp2.position);
     }
+
+    #[test]
+    fn test_from_gene_interpolation() {
+        let interpolation = |byte0, byte1|
+            Interpolation::from_gene(&Gene { data: vec![byte0, byte1, 0, 0, 0] });
+        assert_eq!(interpolation(0, 0), Interpolation::Linear);
+        assert_eq!(interpolation(100, 0), Interpolation::Smooth);
+        assert_eq!(interpolation(150, 0), Interpolation::Stepped(2));
+        assert_eq!(interpolation(150, 6), Interpolation::Stepped(8));
+        assert_eq!(interpolation(255, 0), Interpolation::Eased);
+    }
+
+    // Genomes from before the interpolation gene have only control points, and stay linear
+    #[test]
+    fn test_gradient_from_old_chromosome() {
+        let srgb = TransferFunction::Srgb;
+        let mut genes: Vec<Gene> = (0..8).map(|i|
+            Gene { data: vec![255, (i*30) as u8, 0, 128, (i*32) as u8] }
+        ).collect();
+        let old = gradient_from_chromosome(&Chromosome { genes: genes.clone() }, srgb);
+        genes.push(Gene { data: vec![0, 0, 0, 0, 0] });
+        let linear = gradient_from_chromosome(&Chromosome { genes: genes.clone() }, srgb);
+        genes[8].data[0] = 255;
+        let eased = gradient_from_chromosome(&Chromosome { genes: genes }, srgb);
+        let positions = (0..64).map(|i| i as f32/64.0 + 0.01);
+        assert!(positions.clone().all(|p| old.get_color(p) == linear.get_color(p)));
+        assert!(positions.clone().any(|p| old.get_color(p) != eased.get_color(p)));
+    }
+
+    // A chromosome without control points renders plain gray, the same as it did before sRGB
+    // support when rendered with --transfer gamma
+    #[test]
+    fn test_gray_gradient_transfer_function() {
+        let chromosome = Chromosome { genes: vec![Gene { data: vec![0; 5] }; NUM_COLOR_GENES] };
+        for &transfer_function in [TransferFunction::Gamma22, TransferFunction::Srgb].iter() {
+            let mut settings = RenderingSettings::for_test();
+            settings.transfer_function = transfer_function;
+            let mapper = ColorMapper::new(&chromosome, &settings);
+            for i in 0..16 {
+                assert_eq!(mapper.get_nearest_color(i as f32/16.0), Color::new(128, 128, 128));
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_close() {
+        let mut settings = RenderingSettings::for_test();
+        settings.dithering = true;
+        settings.palette_size = Some(2);
+        let gray = |value: f32| LC::new_f32(value, value, value);
+        let samples: Vec<LC> = (0..LOOKUP_TABLE_SIZE).map(|i|
+            gray(i as f32/LOOKUP_TABLE_SIZE as f32)
+        ).collect();
+        let palette = Palette::new(2, &[gray(0.0), gray(1.0)], false, ColorSpace::LinearRgb);
+
+        // Without a temporal threshold, nothing is close
+        let mapper = ColorMapper::with_palette(&samples, &palette, &settings);
+        assert!(!mapper.is_close(0.5, 0.5));
+
+        settings.temporal_threshold = Some(0.1);
+        let mapper = ColorMapper::with_palette(&samples, &palette, &settings);
+        assert!(mapper.is_close(0.5, 0.52));
+        assert!(!mapper.is_close(0.5, 0.6));
+    }
+
+    #[test]
+    fn test_shown_gradient_samples() {
+        let shown = shown_gradient_samples(&[0.0, 0.5, 1.5, -0.5, 0.999]);
+        assert_eq!(shown.len(), LOOKUP_TABLE_SIZE);
+        assert!(shown[0]);
+        assert!(shown[LOOKUP_TABLE_SIZE/2]);
+        assert!(shown[LOOKUP_TABLE_SIZE - 1]);
+        assert_eq!(shown.iter().filter(|&&s| s).count(), 3);
+    }
 }
@@ -1,5 +1,6 @@
+use cgmath::Vector3;
 use fastmath::FastMath;
-use super::{Color, LinearColor};
+use super::{Color, ColorSpace, LinearColor, TransferFunction};
 
 #[derive(Copy,Clone,Debug)]
 pub struct ControlPoint {
@@ -7,14 +8,29 @@ pub struct ControlPoint {
     pub position: f32
 }
 
+// How colors change from one control point to the next
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Interpolation {
+    Linear,         // Straight lines, which show visible bands at the control points
+    Smooth,         // A monotone cubic curve through all of the points, without overshooting
+    Stepped(usize), // Flat bands of color, this many from one control point to the next
+    Eased           // Slows down into and out of each control point
+}
+
 pub struct Gradient {
-    points: Vec<ControlPoint>
+    points: Vec<ControlPoint>,
+    color_space: ColorSpace, // Where colors are mixed between control points
+    interpolation: Interpolation
 }
 
 #[derive(Debug)]
 struct Subgradient {
+    previous: ControlPoint, // The point before point1, which smooth interpolation looks at
     point1: ControlPoint,
-    point2: ControlPoint
+    point2: ControlPoint,
+    next: ControlPoint, // The point after point2
+    color_space: ColorSpace,
+    interpolation: Interpolation
 }
 
 struct GradientIterator<'a> {
@@ -23,27 +39,36 @@ struct GradientIterator<'a> {
 }
 
 impl ControlPoint {
-    fn new(r: u8, g: u8, b: u8, position: f32) -> ControlPoint {
+    fn new(r: u8, g: u8, b: u8, position: f32,
+           transfer_function: TransferFunction) -> ControlPoint {
         ControlPoint {
-            color: Color::new(r, g, b).to_linear(),
+            color: Color::new(r, g, b).to_linear(transfer_function),
             position: position.wrap()
         }
     }
 
-    fn lerp(&self, other: ControlPoint, position: f32) -> LinearColor {
+    // How far position is from self to other, from 0.0 to 1.0
+    fn progress(&self, other: ControlPoint, position: f32) -> f32 {
         // Calculate distance from self to other, moving in the positive direction
         let distance = (other.position - self.position).wrap();
         assert!(distance > 0.0);
-        let adj_position = (position - self.position).wrap()/distance;
-        self.color.lerp(other.color, adj_position)
+        (position - self.position).wrap()/distance
+    }
+
+    fn lerp(&self, other: ControlPoint, position: f32, color_space: ColorSpace) -> LinearColor {
+        self.color.mix(other.color, self.progress(other, position), color_space)
     }
 }
 
 impl Subgradient {
     fn new(point1: ControlPoint, point2: ControlPoint) -> Subgradient {
         Subgradient {
+            previous: point2,
             point1: point1,
-            point2: point2
+            point2: point2,
+            next: point1,
+            color_space: ColorSpace::LinearRgb,
+            interpolation: Interpolation::Linear
         }
     }
 
@@ -58,15 +83,76 @@ impl Subgradient {
 
     pub fn get_color(&self, position: f32) -> LinearColor {
         assert!(self.contains(position));
-        self.point1.lerp(self.point2, position)
+        let (point1, point2) = (self.point1, self.point2);
+        let progress = point1.progress(point2, position);
+        match self.interpolation {
+            Interpolation::Linear => point1.lerp(point2, position, self.color_space),
+            Interpolation::Smooth => self.get_smooth_color(progress),
+            Interpolation::Stepped(num_steps) => {
+                // The first step is point1's color, and the last one stops a step short of
+                // point2's, which starts the next subgradient. That keeps every band as wide.
+                let step = (progress*num_steps as f32).floor();
+                let amount = (step/num_steps as f32).min(1.0);
+                point1.color.mix(point2.color, amount, self.color_space)
+            },
+            Interpolation::Eased => {
+                let amount = progress*progress*(3.0 - 2.0*progress);
+                point1.color.mix(point2.color, amount, self.color_space)
+            }
+        }
+    }
+
+    /*
+     * Cubic Hermite interpolation, one color channel at a time.
+     *
+     * The curve's slope at each control point is chosen with the Fritsch-Butland method, which
+     * looks at the neighboring points. Where a channel is rising or falling on both sides of a
+     * point, the curve passes through it smoothly; where it peaks, the curve flattens out. This
+     * keeps each channel between the values at point1 and point2, so colors never overshoot.
+     */
+    fn get_smooth_color(&self, progress: f32) -> LinearColor {
+        let color_space = self.color_space;
+        let (c0, c1, c2, c3) = (self.previous.color.to_coordinates(color_space),
+                                self.point1.color.to_coordinates(color_space),
+                                self.point2.color.to_coordinates(color_space),
+                                self.next.color.to_coordinates(color_space));
+        let h0 = (self.point1.position - self.previous.position).wrap();
+        let h1 = (self.point2.position - self.point1.position).wrap();
+        let h2 = (self.next.position - self.point2.position).wrap();
+        let t = progress;
+        let (t2, t3) = (t*t, t*t*t);
+        let channel = |y0: f32, y1: f32, y2: f32, y3: f32| {
+            let slope1 = monotone_slope(h0, (y1 - y0)/h0, h1, (y2 - y1)/h1);
+            let slope2 = monotone_slope(h1, (y2 - y1)/h1, h2, (y3 - y2)/h2);
+            (2.0*t3 - 3.0*t2 + 1.0)*y1 + (t3 - 2.0*t2 + t)*h1*slope1 +
+                (3.0*t2 - 2.0*t3)*y2 + (t3 - t2)*h1*slope2
+        };
+        let v = Vector3 {
+            x: channel(c0.x, c1.x, c2.x, c3.x),
+            y: channel(c0.y, c1.y, c2.y, c3.y),
+            z: channel(c0.z, c1.z, c2.z, c3.z)
+        };
+        LinearColor::from_coordinates(&v, color_space)
     }
 }
 
+// The slope of a monotone curve at a point, given the slopes of the straight lines on either side
+// of it, and how long those lines are
+fn monotone_slope(length_before: f32, slope_before: f32,
+                  length_after: f32, slope_after: f32) -> f32 {
+    if length_before <= 0.0 || length_after <= 0.0 || slope_before*slope_after <= 0.0 {
+        return 0.0;
+    }
+    3.0*(length_before + length_after)/((2.0*length_after + length_before)/slope_before +
+                                        (length_after + 2.0*length_before)/slope_after)
+}
+
 impl Gradient {
-    pub fn new(control_points: Vec<ControlPoint>) -> Gradient {
+    // Without any control points, the gradient is plain gray, decoded with transfer_function
+    pub fn new(control_points: Vec<ControlPoint>, transfer_function: TransferFunction) -> Gradient {
         let mut points = control_points.clone();
         if points.len() == 0 {
-            points.push(ControlPoint::new(128, 128, 128, 0.0));
+            points.push(ControlPoint::new(128, 128, 128, 0.0, transfer_function));
         }
         if points.len() == 1 {
             let mut cp = points[0];
@@ -76,10 +162,29 @@ impl Gradient {
         points.sort_by(|a, b| (a.position).partial_cmp(&b.position).unwrap());
 
         Gradient {
-            points: points
+            points: points,
+            color_space: ColorSpace::LinearRgb,
+            interpolation: Interpolation::Linear
         }
     }
 
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = color_space;
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    // Control points, in order of position
+    pub fn points(&self) -> &[ControlPoint] {
+        &self.points
+    }
+
     pub fn get_color(&self, position: f32) -> LinearColor {
         let pos = position.wrap();
         let subgradient = self.iter().find(|subgradient| subgradient.contains(pos)).unwrap();
@@ -98,22 +203,29 @@ impl<'a> Iterator for GradientIterator<'a> {
     type Item = Subgradient;
 
     fn next(&mut self) -> Option<Subgradient> {
+        let points = &self.gradient.points;
         let index1 = self.index1;
-        let index2 = (self.index1 + 1) % self.gradient.points.len();
+        let index2 = (self.index1 + 1) % points.len();
         self.index1 = index2; // advance the iterator
-        Some(Subgradient::new(self.gradient.points[index1], self.gradient.points[index2]))
+        let mut subgradient = Subgradient::new(points[index1], points[index2]);
+        subgradient.previous = points[(index1 + points.len() - 1) % points.len()];
+        subgradient.next = points[(index2 + 1) % points.len()];
+        subgradient.color_space = self.gradient.color_space;
+        subgradient.interpolation = self.gradient.interpolation;
+        Some(subgradient)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{ControlPoint, LinearColor, Subgradient};
+    use super::{ColorSpace, ControlPoint, Gradient, Interpolation, LinearColor, Subgradient};
+    use super::TransferFunction;
 
     #[test]
     fn test_control_point_new() {
-        let a = ControlPoint::new(1, 2, 3, 0.25);
-        let b = ControlPoint::new(1, 2, 3, 1.25);
-        let c = ControlPoint::new(1, 2, 3, -0.75);
+        let a = ControlPoint::new(1, 2, 3, 0.25, TransferFunction::Srgb);
+        let b = ControlPoint::new(1, 2, 3, 1.25, TransferFunction::Srgb);
+        let c = ControlPoint::new(1, 2, 3, -0.75, TransferFunction::Srgb);
         assert_eq!(a.position, b.position);
         assert_eq!(b.position, c.position);
     }
@@ -133,33 +245,33 @@ mod tests {
         let c = ControlPoint { color: color_c, position: 0.7 };
 
         // Test interval starting at 0.0/1.0
-        assert_eq!(a.lerp(b, 0.0), color_a);
-        assert_eq!(a.lerp(b, 0.1), color_a.lerp(color_b, 0.5));
-        assert_eq!(a.lerp(b, 0.2), color_b);
+        assert_eq!(a.lerp(b, 0.0, ColorSpace::LinearRgb), color_a);
+        assert_eq!(a.lerp(b, 0.1, ColorSpace::LinearRgb), color_a.lerp(color_b, 0.5));
+        assert_eq!(a.lerp(b, 0.2, ColorSpace::LinearRgb), color_b);
 
         // Test middle interval
-        assert_eq!(b.lerp(c, 0.2), color_b);
-        assert_eq!(b.lerp(c, 0.3), color_b.lerp(color_c, 0.2));
-        assert_eq!(b.lerp(c, 0.7), color_c);
+        assert_eq!(b.lerp(c, 0.2, ColorSpace::LinearRgb), color_b);
+        assert_eq!(b.lerp(c, 0.3, ColorSpace::LinearRgb), color_b.lerp(color_c, 0.2));
+        assert_eq!(b.lerp(c, 0.7, ColorSpace::LinearRgb), color_c);
 
         // Test interval ending at 0.0/1.0
-        assert_eq!(c.lerp(a, 0.7), color_c);
-        assert_eq!(c.lerp(a, 0.8), color_c.lerp(color_a, 1.0/3.0));
-        assert_eq!(c.lerp(a, 1.0), color_a);
+        assert_eq!(c.lerp(a, 0.7, ColorSpace::LinearRgb), color_c);
+        assert_eq!(c.lerp(a, 0.8, ColorSpace::LinearRgb), color_c.lerp(color_a, 1.0/3.0));
+        assert_eq!(c.lerp(a, 1.0, ColorSpace::LinearRgb), color_a);
 
         // Test interval crossing 0.0/1.0
-        assert_eq!(c.lerp(b, 0.7), color_c);
-        assert_eq!(c.lerp(b, 0.8), color_c.lerp(color_b, 0.2));
-        assert_eq!(c.lerp(b, 0.0), color_c.lerp(color_b, 0.6));
-        assert_eq!(c.lerp(b, 0.1), color_c.lerp(color_b, 0.8));
-        assert_eq!(c.lerp(b, 0.2), color_b);
+        assert_eq!(c.lerp(b, 0.7, ColorSpace::LinearRgb), color_c);
+        assert_eq!(c.lerp(b, 0.8, ColorSpace::LinearRgb), color_c.lerp(color_b, 0.2));
+        assert_eq!(c.lerp(b, 0.0, ColorSpace::LinearRgb), color_c.lerp(color_b, 0.6));
+        assert_eq!(c.lerp(b, 0.1, ColorSpace::LinearRgb), color_c.lerp(color_b, 0.8));
+        assert_eq!(c.lerp(b, 0.2, ColorSpace::LinearRgb), color_b);
     }
 
     #[test]
     fn test_subgradient_contains() {
         let s = Subgradient::new(
-            ControlPoint::new(0, 0, 0, 0.25),
-            ControlPoint::new(0, 0, 0, 0.75)
+            ControlPoint::new(0, 0, 0, 0.25, TransferFunction::Srgb),
+            ControlPoint::new(0, 0, 0, 0.75, TransferFunction::Srgb)
         );
         assert!(!s.contains(0.24));
         assert!(s.contains(0.25));
@@ -171,8 +283,8 @@ mod tests {
     #[test]
     fn test_subgradient_contains_wraparound() {
         let s = Subgradient::new(
-            ControlPoint::new(0, 0, 0, 0.75),
-            ControlPoint::new(0, 0, 0, 0.25)
+            ControlPoint::new(0, 0, 0, 0.75, TransferFunction::Srgb),
+            ControlPoint::new(0, 0, 0, 0.25, TransferFunction::Srgb)
         );
         assert!(!s.contains(0.74));
         assert!(s.contains(0.75));
@@ -191,4 +303,56 @@ mod tests {
         );
         assert_eq!(s.get_color(0.1), c1.lerp(c2, 3.0/5.0));
     }
+
+    #[test]
+    fn test_gradient_interpolation() {
+        let black = LinearColor::new(0, 0, 0);
+        let white = LinearColor::new(60000, 60000, 60000);
+        let gray = LinearColor::new(30000, 30000, 30000);
+        let gradient_with = |interpolation| {
+            let mut gradient = Gradient::new(vec![
+                ControlPoint { color: black, position: 0.0 },
+                ControlPoint { color: gray, position: 0.25 },
+                ControlPoint { color: white, position: 0.5 }
+            ], TransferFunction::Srgb);
+            gradient.set_interpolation(interpolation);
+            gradient
+        };
+
+        // Every mode passes through the control points
+        for &interpolation in [Interpolation::Linear, Interpolation::Smooth,
+                               Interpolation::Stepped(4), Interpolation::Eased].iter() {
+            let gradient = gradient_with(interpolation);
+            assert_eq!(gradient.get_color(0.0), black);
+            assert_eq!(gradient.get_color(0.25), gray);
+            assert_eq!(gradient.get_color(0.5), white);
+        }
+
+        // Stepped gradients only use a few colors
+        let stepped = gradient_with(Interpolation::Stepped(4));
+        assert_eq!(stepped.get_color(0.05), black);
+        assert_eq!(stepped.get_color(0.07), black.lerp(gray, 0.25));
+        assert_eq!(stepped.get_color(0.24), black.lerp(gray, 0.75));
+
+        // Each control point's color gets one band, as wide as the others
+        assert_eq!(stepped.get_color(0.26), gray);
+        assert_eq!(stepped.get_color(0.31), gray);
+        assert_eq!(stepped.get_color(0.32), gray.lerp(white, 0.25));
+
+        // Eased gradients linger near the control points. Linear would be at 3000 here.
+        let eased = gradient_with(Interpolation::Eased);
+        assert!(eased.get_color(0.025).r < 1500);
+
+        // A smooth gradient doesn't change speed as it passes through a control point...
+        let smooth = gradient_with(Interpolation::Smooth);
+        let below = gray.r - smooth.get_color(0.24).r;
+        let above = smooth.get_color(0.26).r - gray.r;
+        assert!(below > 0 && (below as i32 - above as i32).abs() < 10, "{} != {}", below, above);
+
+        // ...but never overshoots, even where it turns around
+        for i in 0..100 {
+            let color = smooth.get_color(i as f32/100.0);
+            assert!(color.r <= white.r, "{:?} at {}", color, i);
+        }
+    }
 }
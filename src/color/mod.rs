@@ -4,3 +4,4 @@ pub mod color;
 pub mod colormapper;
 pub mod gradient;
 pub mod palette;
+pub mod stops;
@@ -1,5 +1,9 @@
+extern crate rand;
+
 use color::LinearColor;
 use color::palette::Palette;
+use ordered_float::OrderedFloat;
+use self::rand::{Rng, SeedableRng, XorShiftRng};
 use std::{cmp, u16};
 
 const BAYER_MATRIX: [[u8; 8]; 8] = [
@@ -13,6 +17,92 @@ const BAYER_MATRIX: [[u8; 8]; 8] = [
     [42, 26, 38, 22, 41, 25, 37, 21]
 ];
 
+// Width and height of the blue noise threshold matrix, which repeats like the Bayer matrix does
+const BLUE_NOISE_SIZE: usize = 32;
+
+// Error diffusion kernels: where each pixel's error goes, as (dx, dy, weight), and the total weight
+const FLOYD_STEINBERG: (&'static [(isize, usize, i32)], i32) = (&[
+    (1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)
+], 16);
+const ATKINSON: (&'static [(isize, usize, i32)], i32) = (&[ // Only passes on 3/4 of the error
+    (1, 0, 1), (2, 0, 1), (-1, 1, 1), (0, 1, 1), (1, 1, 1), (0, 2, 1)
+], 8);
+const SIERRA: (&'static [(isize, usize, i32)], i32) = (&[
+    (1, 0, 5), (2, 0, 3),
+    (-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+    (-1, 2, 2), (0, 2, 3), (1, 2, 2)
+], 32);
+
+// Error diffusion never passes on more than this much error in any component. Otherwise, colors
+// the palette can't reach pile up error that smears across the frame, and shifts from frame to
+// frame as the plasma moves.
+const MAX_DIFFUSED_ERROR: i32 = u16::MAX as i32/4;
+
+thread_local! {
+    static BLUE_NOISE_MATRIX: Vec<u8> = blue_noise_matrix();
+}
+
+/*
+ * Ways of mixing palette colors to approximate the colors in between.
+ *
+ * Ordered ditherers compare each pixel's mix of colors against a repeating matrix of thresholds,
+ * so a pixel only changes when its own color does. Error diffusion looks smoother in still images,
+ * but a change anywhere can ripple through the rest of the frame, so it's done the same way every
+ * frame: rows alternate direction, starting from the top left, and the error is capped.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ditherer {
+    Bayer,
+    BlueNoise,
+    FloydSteinberg,
+    Atkinson,
+    Sierra
+}
+
+impl Ditherer {
+    pub fn from_name(name: &str) -> Result<Ditherer, String> {
+        match name {
+            "bayer" => Ok(Ditherer::Bayer),
+            "blue-noise" => Ok(Ditherer::BlueNoise),
+            "floyd-steinberg" => Ok(Ditherer::FloydSteinberg),
+            "atkinson" => Ok(Ditherer::Atkinson),
+            "sierra" => Ok(Ditherer::Sierra),
+            _ => Err(format!("Unknown ditherer {} (try bayer, blue-noise, floyd-steinberg, \
+                              atkinson or sierra)", name))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Ditherer::Bayer => "bayer",
+            Ditherer::BlueNoise => "blue-noise",
+            Ditherer::FloydSteinberg => "floyd-steinberg",
+            Ditherer::Atkinson => "atkinson",
+            Ditherer::Sierra => "sierra"
+        }
+    }
+
+    // Whether this ditherer works on whole frames with diffuse_errors(), instead of on each pixel
+    // with a DitherPattern
+    pub fn diffuses_errors(&self) -> bool {
+        match *self {
+            Ditherer::Bayer | Ditherer::BlueNoise => false,
+            Ditherer::FloydSteinberg | Ditherer::Atkinson | Ditherer::Sierra => true
+        }
+    }
+
+    // The threshold (from 0 to 63) for a pixel, for ordered ditherers
+    pub fn threshold(&self, x: usize, y: usize) -> u8 {
+        match *self {
+            Ditherer::Bayer => BAYER_MATRIX[y % 8][x % 8],
+            Ditherer::BlueNoise => BLUE_NOISE_MATRIX.with(|matrix|
+                matrix[(x % BLUE_NOISE_SIZE) + (y % BLUE_NOISE_SIZE)*BLUE_NOISE_SIZE]
+            ),
+            _ => panic!("{} dithering doesn't use thresholds", self.name())
+        }
+    }
+}
+
 // A dithering pattern that approximates a specific color
 #[derive(Clone, Copy)]
 pub struct DitherPattern {
@@ -26,9 +116,7 @@ impl DitherPattern {
         // This is based off of Yliluoma's work: http://bisqwit.iki.fi/story/howto/dither/jy/
         let max_colors = 4;
         let max_new_color_iters = 16;
-        let mut subpalette = Palette {
-            colors: Vec::with_capacity(max_colors)
-        };
+        let mut subpalette = Palette::empty(max_colors, palette.color_space());
         let mut palette_indexes = Vec::with_capacity(max_colors);
         let mut counts = Vec::with_capacity(max_colors);
         let mut errors: [i32; 3] = [0, 0, 0];
@@ -62,7 +150,7 @@ impl DitherPattern {
                 counts[subpalette_index] += 1;
             } else {
                 // We've found a new color, so add it to our data structures
-                subpalette.colors.push(palette[nearest_palette_index]);
+                subpalette.push(palette[nearest_palette_index]);
                 palette_indexes.push(nearest_palette_index);
                 counts.push(1);
             }
@@ -94,11 +182,11 @@ impl DitherPattern {
         retval
     }
 
-    pub fn get_palette_index(&self, x: usize, y: usize) -> usize {
-        let bayer_value = BAYER_MATRIX[y % 8][x % 8];
+    pub fn get_palette_index(&self, x: usize, y: usize, ditherer: Ditherer) -> usize {
+        let threshold = ditherer.threshold(x, y);
         let mut cumulative_proportion = self.palette_proportions[0];
         let mut dither_index = 0;
-        while cumulative_proportion <= bayer_value {
+        while cumulative_proportion <= threshold {
             dither_index += 1;
             cumulative_proportion += self.palette_proportions[dither_index];
         }
@@ -106,17 +194,143 @@ impl DitherPattern {
     }
 }
 
+// Dithers a frame of colors, row by row, with one of the error diffusion ditherers. Returns the
+// palette index for each pixel.
+pub fn diffuse_errors(colors: &[LinearColor], width: usize, palette: &Palette,
+                      ditherer: Ditherer) -> Vec<usize> {
+    let (kernel, total_weight) = match ditherer {
+        Ditherer::FloydSteinberg => FLOYD_STEINBERG,
+        Ditherer::Atkinson => ATKINSON,
+        Ditherer::Sierra => SIERRA,
+        _ => panic!("{} dithering doesn't diffuse errors", ditherer.name())
+    };
+    let height = colors.len()/width;
+    let mut errors = vec![[0i32; 3]; colors.len()];
+    let mut indexes = vec![0; colors.len()];
+    for y in 0..height {
+        // Serpentine order: every other row goes from right to left, and so does its error
+        let reversed = y % 2 == 1;
+        for step in 0..width {
+            let x = if reversed { width - 1 - step } else { step };
+            let i = x + y*width;
+            let component = |value: u16, error: i32|
+                cmp::min(cmp::max(0, value as i32 + error), u16::MAX as i32) as u16;
+            let target = LinearColor {
+                r: component(colors[i].r, errors[i][0]),
+                g: component(colors[i].g, errors[i][1]),
+                b: component(colors[i].b, errors[i][2])
+            };
+            let index = palette.get_nearest_index(target);
+            indexes[i] = index;
+
+            let chosen = palette[index];
+            let clamp = |error: i32|
+                cmp::min(cmp::max(-MAX_DIFFUSED_ERROR, error), MAX_DIFFUSED_ERROR);
+            let error = [
+                clamp(target.r as i32 - chosen.r as i32),
+                clamp(target.g as i32 - chosen.g as i32),
+                clamp(target.b as i32 - chosen.b as i32)
+            ];
+            for &(dx, dy, weight) in kernel {
+                let neighbor_x = x as isize + if reversed { -dx } else { dx };
+                let neighbor_y = y + dy;
+                if neighbor_x < 0 || neighbor_x >= width as isize || neighbor_y >= height {
+                    continue;
+                }
+                let neighbor = &mut errors[neighbor_x as usize + neighbor_y*width];
+                for c in 0..3 {
+                    neighbor[c] += error[c]*weight/total_weight;
+                }
+            }
+        }
+    }
+    indexes
+}
+
+/*
+ * Builds a threshold matrix for blue noise dithering, which is as even as Bayer dithering but
+ * doesn't have its grid pattern.
+ *
+ * This is Ulichney's void-and-cluster method. Every pixel has an energy: how crowded it is by the
+ * pixels that are on around it. A few scattered pixels are turned on and then spread out evenly,
+ * by moving the most crowded one to the emptiest spot until that doesn't change anything. Then the
+ * pixels are ranked: the initial ones by taking the most crowded away one at a time, and the rest
+ * by filling in the emptiest spot one at a time.
+ */
+fn blue_noise_matrix() -> Vec<u8> {
+    let num_pixels = BLUE_NOISE_SIZE*BLUE_NOISE_SIZE;
+    let num_initial_pixels = num_pixels/10;
+    let sigma = 1.5;
+
+    // How much one pixel crowds another, by their offset. The matrix repeats, so offsets wrap.
+    let kernel: Vec<f32> = (0..num_pixels).map(|i| {
+        let wrap = |d: usize| cmp::min(d, BLUE_NOISE_SIZE - d) as f32;
+        let (dx, dy) = (wrap(i % BLUE_NOISE_SIZE), wrap(i / BLUE_NOISE_SIZE));
+        (-(dx*dx + dy*dy)/(2.0*sigma*sigma)).exp()
+    }).collect();
+    let toggle = |pixel: usize, on: &mut [bool], energy: &mut [f32]| {
+        on[pixel] = !on[pixel];
+        let sign = if on[pixel] { 1.0 } else { -1.0 };
+        let (x, y) = (pixel % BLUE_NOISE_SIZE, pixel / BLUE_NOISE_SIZE);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % BLUE_NOISE_SIZE + BLUE_NOISE_SIZE - x) % BLUE_NOISE_SIZE;
+            let dy = (i / BLUE_NOISE_SIZE + BLUE_NOISE_SIZE - y) % BLUE_NOISE_SIZE;
+            *e += sign*kernel[dx + dy*BLUE_NOISE_SIZE];
+        }
+    };
+    let tightest_cluster = |on: &[bool], energy: &[f32]| (0..num_pixels).filter(|&i| on[i])
+        .max_by_key(|&i| OrderedFloat(energy[i])).unwrap();
+    let largest_void = |on: &[bool], energy: &[f32]| (0..num_pixels).filter(|&i| !on[i])
+        .min_by_key(|&i| OrderedFloat(energy[i])).unwrap();
+
+    // Scatter the initial pixels the same way every time, so the matrix never changes
+    let mut rng: XorShiftRng = SeedableRng::from_seed([0x706c6173, 0x6d61, 0x626c7565, 0x6e6f6973]);
+    let mut on = vec![false; num_pixels];
+    let mut energy = vec![0.0; num_pixels];
+    let mut num_on = 0;
+    while num_on < num_initial_pixels {
+        let pixel = rng.gen_range(0, num_pixels);
+        if !on[pixel] {
+            toggle(pixel, &mut on, &mut energy);
+            num_on += 1;
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&on, &energy);
+        toggle(cluster, &mut on, &mut energy);
+        let void = largest_void(&on, &energy);
+        toggle(void, &mut on, &mut energy);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; num_pixels];
+    let (mut removing_on, mut removing_energy) = (on.clone(), energy.clone());
+    for rank in (0..num_initial_pixels).rev() {
+        let cluster = tightest_cluster(&removing_on, &removing_energy);
+        toggle(cluster, &mut removing_on, &mut removing_energy);
+        ranks[cluster] = rank;
+    }
+    for rank in num_initial_pixels..num_pixels {
+        let void = largest_void(&on, &energy);
+        toggle(void, &mut on, &mut energy);
+        ranks[void] = rank;
+    }
+    ranks.iter().map(|&rank| (rank*64/num_pixels) as u8).collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use color::LinearColor;
+    use color::{ColorSpace, LinearColor};
     use color::palette::Palette;
-    use super::DitherPattern;
+    use super::{BLUE_NOISE_SIZE, diffuse_errors, DitherPattern, Ditherer};
 
     #[test]
     fn test_dither_pattern_new() {
         let black = LinearColor::new_f32(0.0, 0.0, 0.0);
         let white = LinearColor::new_f32(1.0, 1.0, 1.0);
-        let palette = Palette::new(2, &[black, white], false);
+        let palette = Palette::new(2, &[black, white], false, ColorSpace::LinearRgb);
         let d = DitherPattern::new(LinearColor::new_f32(0.5, 0.5, 0.5), &palette);
         assert_eq!(d.palette_indexes, [0, 1, 0, 0]);
         assert_eq!(d.palette_proportions, [32, 32, 0, 0]);
@@ -132,10 +346,21 @@ mod tests {
             let mut counts = [0; 4];
             for x in 0..8 {
                 for y in 0..8 {
-                    counts[d.get_palette_index(x, y)] += 1;
+                    counts[d.get_palette_index(x, y, Ditherer::Bayer)] += 1;
                 }
             }
             assert_eq!(proportions, counts, "Dithering did not produce expected proportions");
+
+            // The blue noise matrix is bigger, and has every threshold the same number of times
+            let mut counts = [0; 4];
+            for x in 0..BLUE_NOISE_SIZE {
+                for y in 0..BLUE_NOISE_SIZE {
+                    counts[d.get_palette_index(x, y, Ditherer::BlueNoise)] += 1;
+                }
+            }
+            let repeats = BLUE_NOISE_SIZE*BLUE_NOISE_SIZE/64;
+            let expected: Vec<usize> = proportions.iter().map(|&p| p as usize*repeats).collect();
+            assert_eq!(&expected[..], &counts[..], "Blue noise proportions were wrong");
         }
 
         // Basic cases
@@ -153,4 +378,32 @@ mod tests {
         test_proportions([63, 1, 0, 0]);
         test_proportions([63, 0, 1, 0]);
     }
+
+    #[test]
+    fn test_diffuse_errors() {
+        let black = LinearColor::new_f32(0.0, 0.0, 0.0);
+        let white = LinearColor::new_f32(1.0, 1.0, 1.0);
+        let palette = Palette::new(2, &[black, white], false, ColorSpace::LinearRgb);
+        for &ditherer in &[Ditherer::FloydSteinberg, Ditherer::Atkinson, Ditherer::Sierra] {
+            // Colors in the palette don't get dithered
+            let indexes = diffuse_errors(&vec![white; 64], 8, &palette, ditherer);
+            assert!(indexes.iter().all(|&i| i == 1));
+
+            // Gray is a mix of black and white, and comes out the same every time
+            let gray = vec![LinearColor::new_f32(0.5, 0.5, 0.5); 256];
+            let indexes = diffuse_errors(&gray, 16, &palette, ditherer);
+            let num_white = indexes.iter().filter(|&&i| i == 1).count();
+            assert!(num_white > 112 && num_white < 144, "{:?}: {} white", ditherer, num_white);
+            assert_eq!(indexes, diffuse_errors(&gray, 16, &palette, ditherer));
+        }
+    }
+
+    #[test]
+    fn test_ditherer_from_name() {
+        for &ditherer in &[Ditherer::Bayer, Ditherer::BlueNoise, Ditherer::FloydSteinberg,
+                           Ditherer::Atkinson, Ditherer::Sierra] {
+            assert_eq!(Ditherer::from_name(ditherer.name()), Ok(ditherer));
+        }
+        assert!(Ditherer::from_name("random").is_err());
+    }
 }
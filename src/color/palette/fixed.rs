@@ -0,0 +1,180 @@
+use color::Color;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/*
+ * Fixed palettes, for rendering in a brand's colors or a retro look.
+ *
+ * Instead of choosing colors to suit each plasma, every plasma is drawn with the same ones. They
+ * can be loaded from a file, or one of the classic palettes below can be picked by name.
+ */
+
+// GIFs can't have more colors than this
+const MAX_COLORS: usize = 256;
+
+const BUILT_IN_PALETTES: [(&'static str, &'static [u32]); 4] = [
+    ("pico-8", &[
+        0x000000, 0x1D2B53, 0x7E2553, 0x008751, 0xAB5236, 0x5F574F, 0xC2C3C7, 0xFFF1E8,
+        0xFF004D, 0xFFA300, 0xFFEC27, 0x00E436, 0x29ADFF, 0x83769C, 0xFF77A8, 0xFFCCAA
+    ]),
+    ("c64", &[
+        0x000000, 0xFFFFFF, 0x880000, 0xAAFFEE, 0xCC44CC, 0x00CC55, 0x0000AA, 0xEEEE77,
+        0xDD8855, 0x664400, 0xFF7777, 0x333333, 0x777777, 0xAAFF66, 0x0088FF, 0xBBBBBB
+    ]),
+    ("ega", &[
+        0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+        0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF
+    ]),
+    ("gameboy", &[0x0F380F, 0x306230, 0x8BAC0F, 0x9BBC0F])
+];
+
+// Loads a palette from a file, or looks up a built-in one if there's no file by that name
+pub fn load(name_or_path: &str) -> Result<Vec<Color>, String> {
+    let path = Path::new(name_or_path);
+    if !path.exists() {
+        if let Some(&(_, values)) = BUILT_IN_PALETTES.iter().find(|&&(n, _)| n == name_or_path) {
+            return Ok(values.iter().map(|&v| from_u32(v)).collect());
+        }
+    }
+    let mut bytes = vec![];
+    File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)).map_err(|e|
+        format!("Couldn't read palette {}: {} (built-in palettes are {})",
+                name_or_path, e, built_in_names().join(", "))
+    )?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let colors = match extension.as_str() {
+        "act" => parse_act(&bytes),
+        _ => match String::from_utf8(bytes) {
+            Ok(text) => match extension.as_str() {
+                "gpl" => parse_gpl(&text),
+                "pal" => parse_jasc_pal(&text),
+                _ => parse_hex(&text)
+            },
+            Err(_) => Err("not a text file".to_string())
+        }
+    }.map_err(|e| format!("Couldn't read palette {}: {}", name_or_path, e))?;
+    if colors.len() < 2 || colors.len() > MAX_COLORS {
+        return Err(format!("Palette {} has {} colors, but it needs from 2 to {}",
+                           name_or_path, colors.len(), MAX_COLORS));
+    }
+    Ok(colors)
+}
+
+pub fn built_in_names() -> Vec<&'static str> {
+    BUILT_IN_PALETTES.iter().map(|&(name, _)| name).collect()
+}
+
+fn from_u32(value: u32) -> Color {
+    Color::new((value >> 16) as u8, (value >> 8) as u8, value as u8)
+}
+
+// Parses whitespace-separated decimal components, like "255 0 77"
+fn parse_rgb<'a, I: Iterator<Item=&'a str>>(mut words: I, line: &str) -> Result<Color, String> {
+    let mut component = || words.next().and_then(|w| w.parse::<u8>().ok()).ok_or(
+        format!("expected red, green and blue from 0 to 255: {}", line)
+    );
+    Ok(Color::new(component()?, component()?, component()?))
+}
+
+// GIMP palettes: a header, then one color per line, optionally followed by a name
+fn parse_gpl(text: &str) -> Result<Vec<Color>, String> {
+    let mut lines = text.lines();
+    if lines.next().map(|l| l.trim()) != Some("GIMP Palette") {
+        return Err("missing GIMP Palette header".to_string());
+    }
+    lines.map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.contains(':'))
+        .map(|l| parse_rgb(l.split_whitespace(), l))
+        .collect()
+}
+
+// JASC palettes (Paint Shop Pro): a header, a version, the number of colors, and then the colors
+fn parse_jasc_pal(text: &str) -> Result<Vec<Color>, String> {
+    let mut lines = text.lines().map(|l| l.trim());
+    if lines.next() != Some("JASC-PAL") {
+        return Err("missing JASC-PAL header".to_string());
+    }
+    lines.next(); // Version
+    let num_colors = lines.next().and_then(|l| l.parse::<usize>().ok()).ok_or(
+        "missing number of colors".to_string()
+    )?;
+    let colors = lines.filter(|l| !l.is_empty()).map(|l| parse_rgb(l.split_whitespace(), l))
+        .collect::<Result<Vec<Color>, String>>()?;
+    if colors.len() != num_colors {
+        return Err(format!("expected {} colors, found {}", num_colors, colors.len()));
+    }
+    Ok(colors)
+}
+
+// Adobe color tables: 256 RGB triples, optionally followed by the number of colors actually used
+// and the index of the transparent color, both 16-bit big-endian
+fn parse_act(bytes: &[u8]) -> Result<Vec<Color>, String> {
+    let num_colors = match bytes.len() {
+        768 => 256,
+        772 => ((bytes[768] as usize) << 8 | bytes[769] as usize).min(256),
+        n => return Err(format!("expected 768 or 772 bytes, found {}", n))
+    };
+    Ok(bytes[..num_colors*3].chunks(3).map(|c| Color::new(c[0], c[1], c[2])).collect())
+}
+
+// Plain lists of hex colors, one per line, with or without a leading #
+fn parse_hex(text: &str) -> Result<Vec<Color>, String> {
+    text.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).map(color_from_hex).collect()
+}
+
+// Parses a color like "ff004d" or "#FF004D"
+pub fn color_from_hex(text: &str) -> Result<Color, String> {
+    let digits = text.trim_start_matches('#');
+    if digits.len() == 6 && digits.chars().all(|c| c.is_digit(16)) {
+        Ok(from_u32(u32::from_str_radix(digits, 16).unwrap()))
+    } else {
+        Err(format!("not a hex color: {}", text))
+    }
+}
+
+// Formats a color like "ff004d"
+pub fn color_to_hex(color: Color) -> String {
+    format!("{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use color::Color;
+    use super::{color_from_hex, color_to_hex, load};
+    use super::{parse_act, parse_gpl, parse_hex, parse_jasc_pal};
+
+    #[test]
+    fn test_parse_palette_formats() {
+        let expected = vec![Color::new(255, 0, 77), Color::new(0, 135, 81)];
+
+        let gpl = "GIMP Palette\nName: Test\nColumns: 2\n#\n255   0  77\tRed\n  0 135  81\tGreen\n";
+        assert_eq!(parse_gpl(gpl), Ok(expected.clone()));
+        assert!(parse_gpl("255 0 77\n").is_err());
+        assert!(parse_gpl("GIMP Palette\n255 0\n").is_err());
+
+        let jasc = "JASC-PAL\r\n0100\r\n2\r\n255 0 77\r\n0 135 81\r\n";
+        assert_eq!(parse_jasc_pal(jasc), Ok(expected.clone()));
+        assert!(parse_jasc_pal("JASC-PAL\n0100\n3\n255 0 77\n0 135 81\n").is_err());
+
+        assert_eq!(parse_hex("ff004d\n#008751\n\n"), Ok(expected.clone()));
+        assert!(parse_hex("ff004d\nf00\n").is_err());
+        assert_eq!(color_to_hex(expected[0]), "ff004d");
+        assert_eq!(color_from_hex("#FF004D"), Ok(expected[0]));
+        assert!(color_from_hex("+ff004").is_err());
+
+        let mut act = vec![0; 772];
+        act[..6].copy_from_slice(&[255, 0, 77, 0, 135, 81]);
+        act[769] = 2;
+        assert_eq!(parse_act(&act), Ok(expected.clone()));
+        assert_eq!(parse_act(&act[..768]).unwrap().len(), 256);
+        assert!(parse_act(&act[..10]).is_err());
+    }
+
+    #[test]
+    fn test_load_built_in() {
+        assert_eq!(load("pico-8").unwrap().len(), 16);
+        assert_eq!(load("gameboy").unwrap()[0], Color::new(0x0F, 0x38, 0x0F));
+        assert!(load("no-such-palette").is_err());
+    }
+}
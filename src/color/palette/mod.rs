@@ -1,4 +1,5 @@
 pub use self::palette::*;
 
 pub mod dither;
+pub mod fixed;
 pub mod palette;
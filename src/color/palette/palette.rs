@@ -1,14 +1,21 @@
 use cgmath::Vector3;
 use cgmath::prelude::*;
-use color::LinearColor;
+use color::{ColorSpace, LinearColor};
 use color::palette::dither::DitherPattern;
 use ordered_float::OrderedFloat;
 use std::u16;
 use std::collections::HashSet;
 use std::ops::Index;
 
+// Clustering normally settles down quickly, but rounding can make it go back and forth forever
+const MAX_CLUSTERING_ITERATIONS: usize = 1000;
+
+#[derive(Clone)]
 pub struct Palette {
-    pub colors: Vec<LinearColor>
+    pub colors: Vec<LinearColor>,
+    color_space: ColorSpace, // Where colors are compared and averaged
+    oklab_colors: Vec<Vector3<f32>>, // The same colors in OKLab, if that's the color space
+    pinned_indexes: HashSet<usize> // Colors that clustering leaves at the edges of the color space
 }
 
 // Private helpers for working with LinearColors
@@ -21,6 +28,16 @@ impl LinearColor {
         partial(self.r, other.r) + partial(self.g, other.g) + partial(self.b, other.b)
     }
 
+    fn average_in(colors: &[LinearColor], color_space: ColorSpace) -> LinearColor {
+        match color_space {
+            ColorSpace::LinearRgb => LinearColor::average(colors),
+            ColorSpace::Oklab => {
+                let total = colors.iter().fold(Vector3::zero(), |total, c| total + c.to_oklab());
+                LinearColor::from_oklab(&(total/colors.len() as f32))
+            }
+        }
+    }
+
     fn average(colors: &[LinearColor]) -> LinearColor {
         let mut totals = [0.0, 0.0, 0.0];
         for color in colors {
@@ -40,32 +57,33 @@ impl LinearColor {
 
 impl Palette {
     // Generate an optimized palette based on the provided color samples
-    pub fn new(palette_size: usize, samples: &[LinearColor], maximize_range: bool) -> Palette {
+    pub fn new(palette_size: usize, samples: &[LinearColor], maximize_range: bool,
+               color_space: ColorSpace) -> Palette {
         assert!(palette_size >= 2);
         assert!(palette_size <= u16::MAX as usize);
 
         // Shortcut: if we're not reducing the number of colors, just use samples as our colors
         if samples.len() <= palette_size {
-            let mut colors = Vec::with_capacity(palette_size);
-            colors.extend_from_slice(samples);
-            while colors.len() < palette_size {
-                colors.push(LinearColor::new(0, 0, 0));
+            let mut palette = Palette::empty(palette_size, color_space);
+            for &sample in samples {
+                palette.push(sample);
             }
-            return Palette { colors: colors };
+            while palette.colors.len() < palette_size {
+                palette.push(LinearColor::new(0, 0, 0));
+            }
+            return palette;
         }
 
         // Create an initial palette by subsampling the provided samples
-        let mut palette = Palette {
-            colors: Vec::with_capacity(palette_size)
-        };
+        let mut palette = Palette::empty(palette_size, color_space);
         let subsample_distance = samples.len() as f32/palette_size as f32;
         for i in 0..palette_size {
             let subsample_index = (i as f32 * subsample_distance) as usize;
-            palette.colors.push(samples[subsample_index]);
+            palette.push(samples[subsample_index]);
         }
 
         // Pin the outermost palette entries to the edges of the color space
-        let pinned_palette_indexes: HashSet<usize> = if maximize_range {
+        palette.pinned_indexes = if maximize_range {
             // Calculate repelling forces among palette entries
             let palette_vectors: Vec<_> = palette.colors.iter().map(|c| c.to_vec3()).collect();
             let repelling_forces: Vec<Vector3<f32>> = palette_vectors.iter().map(|color| {
@@ -95,9 +113,10 @@ impl Palette {
             // Update the outside palette entries to be the most extreme sample
             for &palette_index in outside_palette_indexes.iter() {
                 let force = repelling_forces[palette_index];
-                palette.colors[palette_index] = *samples.iter().max_by_key(|sample| {
+                let extreme_sample = *samples.iter().max_by_key(|sample| {
                     OrderedFloat(sample.to_vec3().dot(force))
                 }).unwrap();
+                palette.set(palette_index, extreme_sample);
             }
 
             outside_palette_indexes
@@ -105,38 +124,124 @@ impl Palette {
             HashSet::new()
         };
 
-        // Optimize the palette by doing k-means clustering on the samples.
-        // Each of the k means will become a color in the optimized palette.
+        palette.cluster(samples);
+        palette
+    }
+
+    // Fits a palette to new samples, starting from its current colors, so that each color only
+    // moves as far as the samples have. Pinned colors stay where they are.
+    pub fn adjusted(&self, samples: &[LinearColor]) -> Palette {
+        let mut palette = self.clone();
+
+        // Colors that no sample is nearest to would go to waste, so move them to the samples
+        // furthest from any other color
+        let mut used = vec![false; palette.colors.len()];
+        let mut distances: Vec<f32> = samples.iter().map(|&sample| {
+            let index = palette.get_nearest_index(sample);
+            used[index] = true;
+            sample.distance(palette.colors[index], palette.color_space)
+        }).collect();
+        for index in 0..palette.colors.len() {
+            if used[index] || palette.pinned_indexes.contains(&index) {
+                continue;
+            }
+            let furthest = distances.iter().enumerate().max_by_key(|&(_, &d)| OrderedFloat(d));
+            match furthest {
+                Some((sample_index, &distance)) if distance > 0.0 => {
+                    let color = samples[sample_index];
+                    palette.set(index, color);
+                    for (distance, &sample) in distances.iter_mut().zip(samples) {
+                        *distance = distance.min(sample.distance(color, palette.color_space));
+                    }
+                },
+                _ => break
+            }
+        }
+
+        palette.cluster(samples);
+        palette
+    }
+
+    // Optimize the palette by doing k-means clustering on the samples.
+    // Each of the k means will become a color in the optimized palette.
+    fn cluster(&mut self, samples: &[LinearColor]) {
         let mut palette_updated = true;
-        while palette_updated {
+        let mut num_iterations = 0;
+        while palette_updated && num_iterations < MAX_CLUSTERING_ITERATIONS {
+            num_iterations += 1;
+
             // Group samples by each one's closest palette color
-            let mut palette_index_to_samples = vec![vec![]; palette_size];
+            let mut palette_index_to_samples = vec![vec![]; self.colors.len()];
             for &sample in samples {
-                let palette_index = palette.get_nearest_index(sample);
+                let palette_index = self.get_nearest_index(sample);
                 palette_index_to_samples[palette_index].push(sample);
             }
 
             // Replace each palette color with the average of its corresponding sample group
             palette_updated = false;
             for (palette_index, nearest_samples) in palette_index_to_samples.iter().enumerate() {
-                if nearest_samples.len() > 0 && !pinned_palette_indexes.contains(&palette_index) {
-                    let average = LinearColor::average(nearest_samples);
-                    if palette.colors[palette_index] != average {
-                        palette.colors[palette_index] = average;
+                if nearest_samples.len() > 0 && !self.pinned_indexes.contains(&palette_index) {
+                    let average = LinearColor::average_in(nearest_samples, self.color_space);
+                    if self.colors[palette_index] != average {
+                        self.set(palette_index, average);
                         palette_updated = true;
                     }
                 }
             }
         }
+    }
+
+    // A palette of exactly the given colors
+    pub fn fixed(colors: &[LinearColor], color_space: ColorSpace) -> Palette {
+        let mut palette = Palette::empty(colors.len(), color_space);
+        for &color in colors {
+            palette.push(color);
+        }
         palette
     }
 
+    // A palette with no colors yet
+    pub fn empty(capacity: usize, color_space: ColorSpace) -> Palette {
+        Palette {
+            colors: Vec::with_capacity(capacity),
+            color_space: color_space,
+            oklab_colors: vec![],
+            pinned_indexes: HashSet::new()
+        }
+    }
+
+    pub fn push(&mut self, color: LinearColor) {
+        self.colors.push(color);
+        if self.color_space == ColorSpace::Oklab {
+            self.oklab_colors.push(color.to_oklab());
+        }
+    }
+
+    fn set(&mut self, index: usize, color: LinearColor) {
+        self.colors[index] = color;
+        if self.color_space == ColorSpace::Oklab {
+            self.oklab_colors[index] = color.to_oklab();
+        }
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
     // Given an arbitrary color, returns the index of the nearest palette color
     pub fn get_nearest_index(&self, color: LinearColor) -> usize {
-        let index_color = self.colors.iter().enumerate().min_by_key(|&(_, palette_color)|
-            color.squared_distance(*palette_color)
-        );
-        index_color.expect("Palette has no colors").0
+        let index = match self.color_space {
+            ColorSpace::LinearRgb => self.colors.iter().enumerate().min_by_key(|&(_, palette_color)|
+                color.squared_distance(*palette_color)
+            ).map(|(i, _)| i),
+            ColorSpace::Oklab => {
+                let oklab_color = color.to_oklab();
+                self.oklab_colors.iter().enumerate().min_by_key(|&(_, palette_color)|
+                    OrderedFloat((palette_color - oklab_color).magnitude2())
+                ).map(|(i, _)| i)
+            }
+        };
+        index.expect("Palette has no colors")
     }
 
     // Given an arbitrary color, returns a DitherPattern that approximates that color
@@ -155,7 +260,7 @@ impl Index<usize> for Palette {
 
 #[cfg(test)]
 mod tests {
-    use color::LinearColor as LC;
+    use color::{ColorSpace, LinearColor as LC};
     use super::Palette;
     use std::u16;
 
@@ -177,7 +282,7 @@ mod tests {
 
     #[test]
     fn test_palette_new() {
-        let palette = Palette::new(2, &[BLACK, BLACK, WHITE, WHITE], false);
+        let palette = Palette::new(2, &[BLACK, BLACK, WHITE, WHITE], false, ColorSpace::LinearRgb);
         assert_eq!(palette.colors.len(), 2);
         assert!(palette.colors.contains(&BLACK));
         assert!(palette.colors.contains(&WHITE));
@@ -185,9 +290,46 @@ mod tests {
 
     #[test]
     fn test_palette_new_few_samples() {
-        let palette = Palette::new(4, &[BLACK, WHITE], false);
+        let palette = Palette::new(4, &[BLACK, WHITE], false, ColorSpace::LinearRgb);
         assert_eq!(palette.colors.len(), 4);
         assert_eq!(palette.colors.iter().filter(|&&c| c == BLACK).count(), 3);
         assert_eq!(palette.colors.iter().filter(|&&c| c == WHITE).count(), 1);
     }
+
+    #[test]
+    fn test_palette_oklab() {
+        // Dark colors look more different from each other than linear RGB suggests
+        let samples: Vec<LC> = (0..64).map(|i| LC::new_f32(i as f32/63.0, 0.0, 0.0)).collect();
+        let linear_palette = Palette::new(4, &samples, false, ColorSpace::LinearRgb);
+        let oklab_palette = Palette::new(4, &samples, false, ColorSpace::Oklab);
+        let darkest = |palette: &Palette| palette.colors.iter().map(|c| c.r).min().unwrap();
+        assert!(darkest(&oklab_palette) < darkest(&linear_palette));
+
+        // Nearest colors are found in OKLab too
+        let dark_gray = LC::new_f32(0.2, 0.2, 0.2);
+        let palette = Palette::new(2, &[BLACK, WHITE], false, ColorSpace::Oklab);
+        assert_eq!(palette.get_nearest_index(dark_gray), 1);
+        let palette = Palette::new(2, &[BLACK, WHITE], false, ColorSpace::LinearRgb);
+        assert_eq!(palette.get_nearest_index(dark_gray), 0);
+    }
+
+    #[test]
+    fn test_palette_adjusted() {
+        let gray = BLACK.lerp(WHITE, 0.5);
+        let palette = Palette::new(2, &[BLACK, BLACK, gray, gray], false, ColorSpace::LinearRgb);
+        let adjusted = palette.adjusted(&[BLACK, WHITE, WHITE]);
+        assert_eq!(adjusted.colors.len(), 2);
+        assert!(adjusted.colors.contains(&BLACK));
+        assert!(adjusted.colors.contains(&WHITE));
+
+        // Colors keep their places in the palette
+        let index_of = |palette: &Palette, color| palette.colors.iter().position(|&c| c == color);
+        assert_eq!(index_of(&adjusted, WHITE), index_of(&palette, gray));
+
+        // Colors that aren't needed any more move to where they are
+        let dark_gray = BLACK.lerp(WHITE, 0.25);
+        let palette = Palette::fixed(&[BLACK, WHITE], ColorSpace::LinearRgb);
+        let adjusted = palette.adjusted(&[BLACK, BLACK, dark_gray]);
+        assert_eq!(adjusted.colors, vec![BLACK, dark_gray]);
+    }
 }
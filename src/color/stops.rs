@@ -0,0 +1,349 @@
+use color::{Color, TransferFunction};
+use color::gradient::ControlPoint;
+use color::palette::fixed::color_from_hex;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/*
+ * Gradients given by the user, instead of evolved ones.
+ *
+ * A gradient is a list of color stops. They can be written out like "ff0000@0,0000ff@0.5", read
+ * from a file with one stop per line, or imported from GIMP gradients (.ggr) and CSS
+ * linear-gradient() strings. Either way, positions that are left out are spread evenly between
+ * the ones around them, the way CSS does it.
+ */
+
+// Stops at the same position make a sharp edge. They're nudged apart by this much, since control
+// points can't share a position.
+const HARD_STOP_WIDTH: f32 = 0.001;
+
+// Colors that CSS gradients can use by name
+const CSS_COLOR_NAMES: [(&'static str, u32); 18] = [
+    ("black", 0x000000), ("silver", 0xC0C0C0), ("gray", 0x808080), ("grey", 0x808080),
+    ("white", 0xFFFFFF), ("maroon", 0x800000), ("red", 0xFF0000), ("purple", 0x800080),
+    ("fuchsia", 0xFF00FF), ("magenta", 0xFF00FF), ("green", 0x008000), ("lime", 0x00FF00),
+    ("olive", 0x808000), ("yellow", 0xFFFF00), ("navy", 0x000080), ("blue", 0x0000FF),
+    ("teal", 0x008080), ("aqua", 0x00FFFF)
+];
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct ColorStop {
+    pub color: Color, // Gamma encoded, like colors usually are in files
+    pub position: f32 // From 0.0 to 1.0
+}
+
+// Reads a gradient from a file, or from the text itself if there's no file by that name
+pub fn load(text_or_path: &str) -> Result<Vec<ColorStop>, String> {
+    let path = Path::new(text_or_path);
+    if !path.is_file() {
+        return parse(text_or_path).map_err(|e|
+            format!("Couldn't read gradient {} ({}), and there's no file by that name",
+                    text_or_path, e)
+        );
+    }
+    let mut text = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut text)).map_err(|e|
+        format!("Couldn't read gradient {}: {}", text_or_path, e)
+    )?;
+    let stops = if path.extension().and_then(|e| e.to_str()) == Some("ggr") {
+        parse_ggr(&text)
+    } else {
+        parse(&text)
+    };
+    stops.map_err(|e| format!("Couldn't read gradient {}: {}", text_or_path, e))
+}
+
+// Parses either a CSS linear-gradient() or a list of stops
+pub fn parse(text: &str) -> Result<Vec<ColorStop>, String> {
+    let text = text.trim();
+    if text.starts_with("linear-gradient") {
+        return parse_css(text);
+    }
+    let stops = text.split(|c| c == ',' || c == '\n').map(|s| s.trim()).filter(|s| !s.is_empty())
+        .map(|s| {
+            let mut parts = s.splitn(2, '@');
+            let color = color_from_hex(parts.next().unwrap())?;
+            let position = match parts.next() {
+                Some(position_str) => Some(parse_position(position_str)?),
+                None => None
+            };
+            Ok((color, position))
+        }).collect::<Result<Vec<_>, String>>()?;
+    fill_in_positions(stops)
+}
+
+// Formats a stop the way parse() reads it
+pub fn format_stop(stop: &ColorStop) -> String {
+    format!("{:02x}{:02x}{:02x}@{}", stop.color.r, stop.color.g, stop.color.b, stop.position)
+}
+
+// A position from 0 to 1, or a percentage
+fn parse_position(text: &str) -> Result<f32, String> {
+    let text = text.trim();
+    let position = if text.ends_with('%') {
+        text[..text.len() - 1].parse::<f32>().ok().map(|p| p/100.0)
+    } else {
+        text.parse::<f32>().ok()
+    };
+    match position {
+        Some(p) if 0.0 <= p && p <= 1.0 => Ok(p),
+        _ => Err(format!("Not a position from 0 to 1, or from 0% to 100%: {}", text))
+    }
+}
+
+// Gives positions to stops that don't have them. The first and last stops default to the ends of
+// the gradient, and the rest are spread evenly between the closest stops that have positions.
+fn fill_in_positions(stops: Vec<(Color, Option<f32>)>) -> Result<Vec<ColorStop>, String> {
+    if stops.is_empty() {
+        return Err("A gradient needs at least one color".to_string());
+    }
+    let mut positions: Vec<Option<f32>> = stops.iter().map(|&(_, p)| p).collect();
+    let last = positions.len() - 1;
+    positions[0] = positions[0].or(Some(0.0));
+    positions[last] = positions[last].or(Some(1.0));
+    let mut start = 0;
+    for i in 1..positions.len() {
+        if let Some(end_position) = positions[i] {
+            // A stop can't come before the one in front of it
+            let start_position = positions[start].unwrap();
+            let end_position = end_position.max(start_position);
+            positions[i] = Some(end_position);
+            for j in start + 1..i {
+                let amount = (j - start) as f32/(i - start) as f32;
+                positions[j] = Some(start_position + (end_position - start_position)*amount);
+            }
+            start = i;
+        }
+    }
+    Ok(stops.iter().zip(positions.iter()).map(|(&(color, _), position)|
+        ColorStop { color: color, position: position.unwrap() }
+    ).collect())
+}
+
+// Parses a CSS linear-gradient(), like "linear-gradient(90deg, #ff0000, rgb(0, 0, 255) 50%)".
+// The direction doesn't matter, since the plasma decides where each color goes.
+fn parse_css(text: &str) -> Result<Vec<ColorStop>, String> {
+    let inner = text.trim_end_matches(';').trim();
+    if !inner.starts_with("linear-gradient(") || !inner.ends_with(')') {
+        return Err(format!("Not a CSS linear-gradient(): {}", text));
+    }
+    let inner = &inner["linear-gradient(".len()..inner.len() - 1];
+
+    // Split on commas, but not the ones inside rgb()
+    let mut args = vec![];
+    let (mut depth, mut arg_start) = (0, 0);
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(inner[arg_start..i].trim());
+                arg_start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    args.push(inner[arg_start..].trim());
+
+    let is_direction = |arg: &str| arg.starts_with("to ") ||
+        ["deg", "turn", "rad", "grad"].iter().any(|unit| arg.ends_with(unit));
+    let mut stops = vec![];
+    for (i, arg) in args.iter().enumerate() {
+        if i == 0 && is_direction(arg) {
+            continue;
+        }
+        // A color, then up to two positions
+        let (color_str, positions_str) = match arg.rfind(')') {
+            Some(end) => arg.split_at(end + 1),
+            None => arg.split_at(arg.find(' ').unwrap_or(arg.len()))
+        };
+        let color = parse_css_color(color_str.trim())?;
+        let positions: Vec<&str> = positions_str.split_whitespace().collect();
+        if positions.is_empty() {
+            stops.push((color, None));
+        }
+        if positions.len() > 2 {
+            return Err(format!("Too many positions for one color: {}", arg));
+        }
+        for position in positions {
+            stops.push((color, Some(parse_position(position)?)));
+        }
+    }
+    fill_in_positions(stops)
+}
+
+// Parses a color like "#f00", "#ff0000", "rgb(255, 0, 0)" or "red". Alpha is ignored.
+fn parse_css_color(text: &str) -> Result<Color, String> {
+    let lowercase = text.to_lowercase();
+    if let Some(&(_, value)) = CSS_COLOR_NAMES.iter().find(|&&(name, _)| name == lowercase) {
+        return Ok(Color::new((value >> 16) as u8, (value >> 8) as u8, value as u8));
+    }
+    if lowercase.starts_with("rgb(") || lowercase.starts_with("rgba(") {
+        let start = lowercase.find('(').unwrap() + 1;
+        let components: Vec<&str> = lowercase[start..].trim_end_matches(')')
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|s| !s.is_empty()).collect();
+        if components.len() == 3 || components.len() == 4 {
+            let component = |s: &str| if s.ends_with('%') {
+                s[..s.len() - 1].parse::<f32>().ok().map(|p| p/100.0*255.0)
+            } else {
+                s.parse::<f32>().ok()
+            }.filter(|&c| 0.0 <= c && c <= 255.0).map(|c| c.round() as u8);
+            if let (Some(r), Some(g), Some(b)) =
+                (component(components[0]), component(components[1]), component(components[2])) {
+                return Ok(Color::new(r, g, b));
+            }
+        }
+    } else if lowercase.starts_with('#') {
+        let digits = &lowercase[1..];
+        match digits.len() {
+            3 | 4 => {
+                let doubled: String = digits[..3].chars().flat_map(|c| vec![c, c]).collect();
+                return color_from_hex(&doubled);
+            },
+            6 | 8 => return color_from_hex(&digits[..6]),
+            _ => {}
+        }
+    }
+    Err(format!("Unsupported CSS color: {}", text))
+}
+
+/*
+ * Parses a GIMP gradient.
+ *
+ * Each segment has a left, middle and right position, and colors at the left and right ends.
+ * Halfway between the two colors is at the middle position. GIMP can also blend along curves or
+ * around the HSV color wheel; those are approximated with straight lines through the middle.
+ */
+pub fn parse_ggr(text: &str) -> Result<Vec<ColorStop>, String> {
+    let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+    if lines.next() != Some("GIMP Gradient") {
+        return Err("missing GIMP Gradient header".to_string());
+    }
+    let mut lines = lines.skip_while(|l| l.starts_with("Name:"));
+    let num_segments = lines.next().and_then(|l| l.parse::<usize>().ok()).ok_or(
+        "missing number of segments".to_string()
+    )?;
+    let mut stops: Vec<ColorStop> = vec![];
+    for line in lines.take(num_segments) {
+        let values = line.split_whitespace().take(11).map(|v| v.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>().map_err(|_| format!("bad segment: {}", line))?;
+        if values.len() < 11 {
+            return Err(format!("bad segment: {}", line));
+        }
+        let to_color = |rgb: &[f32]| {
+            let component = |c: f32| (c.max(0.0).min(1.0)*255.0).round() as u8;
+            Color::new(component(rgb[0]), component(rgb[1]), component(rgb[2]))
+        };
+        let (left, right) = (to_color(&values[3..6]), to_color(&values[7..10]));
+        let middle = Color::new(((left.r as u16 + right.r as u16)/2) as u8,
+                                ((left.g as u16 + right.g as u16)/2) as u8,
+                                ((left.b as u16 + right.b as u16)/2) as u8);
+        for &(color, position) in [(left, values[0]), (middle, values[1]), (right, values[2])].iter() {
+            let stop = ColorStop { color: color, position: position.max(0.0).min(1.0) };
+            if stops.last() != Some(&stop) {
+                stops.push(stop);
+            }
+        }
+    }
+    if stops.is_empty() {
+        return Err("no segments".to_string());
+    }
+    Ok(stops)
+}
+
+/*
+ * Converts stops to gradient control points.
+ *
+ * Plasmas loop through their gradients, so position 1.0 is the same as 0.0. A gradient that ends
+ * with the color it started with loops seamlessly; one that doesn't has a sharp edge there.
+ */
+pub fn control_points(stops: &[ColorStop], transfer_function: TransferFunction)
+                      -> Vec<ControlPoint> {
+    let mut sorted = stops.to_vec();
+    sorted.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap());
+    let mut points: Vec<ControlPoint> = vec![];
+    for stop in sorted {
+        let position = match points.last() {
+            Some(point) => stop.position.max(point.position + HARD_STOP_WIDTH),
+            None => stop.position
+        }.min(1.0 - HARD_STOP_WIDTH);
+        if points.last().map_or(false, |point| point.position >= position) {
+            continue; // Too many stops at the end to fit
+        }
+        points.push(ControlPoint {
+            color: stop.color.to_linear(transfer_function),
+            position: position
+        });
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use color::{Color, TransferFunction};
+    use super::{control_points, format_stop, parse, parse_css_color, parse_ggr, ColorStop};
+
+    fn stop(r: u8, g: u8, b: u8, position: f32) -> ColorStop {
+        ColorStop { color: Color::new(r, g, b), position: position }
+    }
+
+    #[test]
+    fn test_parse_stops() {
+        assert_eq!(parse("ff0000@0, 00ff00@25%, #0000ff@1"),
+                   Ok(vec![stop(255, 0, 0, 0.0), stop(0, 255, 0, 0.25), stop(0, 0, 255, 1.0)]));
+
+        // Missing positions are spread out evenly
+        assert_eq!(parse("ff0000\n00ff00\n0000ff\nffffff@0.9\n"), Ok(vec![
+            stop(255, 0, 0, 0.0), stop(0, 255, 0, 0.3), stop(0, 0, 255, 0.6),
+            stop(255, 255, 255, 0.9)
+        ]));
+
+        assert!(parse("").is_err());
+        assert!(parse("ff0000@2").is_err());
+        assert!(parse("red@0").is_err());
+
+        let s = stop(1, 2, 3, 0.5);
+        assert_eq!(parse(&format_stop(&s)), Ok(vec![s]));
+    }
+
+    #[test]
+    fn test_parse_css() {
+        assert_eq!(parse("linear-gradient(to right, red, #00f 40%, rgb(0, 128, 0) 60% 80%);"),
+                   Ok(vec![stop(255, 0, 0, 0.0), stop(0, 0, 255, 0.4), stop(0, 128, 0, 0.6),
+                           stop(0, 128, 0, 0.8)]));
+        assert_eq!(parse("linear-gradient(45deg, #ff000080, white)"),
+                   Ok(vec![stop(255, 0, 0, 0.0), stop(255, 255, 255, 1.0)]));
+        assert!(parse("linear-gradient(red 10px, blue)").is_err());
+
+        assert_eq!(parse_css_color("rgba(255, 0, 0, 0.5)"), Ok(Color::new(255, 0, 0)));
+        assert_eq!(parse_css_color("rgb(100% 50% 0%)"), Ok(Color::new(255, 128, 0)));
+        assert!(parse_css_color("hsl(0, 100%, 50%)").is_err());
+    }
+
+    #[test]
+    fn test_parse_ggr() {
+        let ggr = "GIMP Gradient\nName: Test\n2\n\
+                   0.0 0.25 0.5 1 0 0 1 0 0 1 1 0 0\n\
+                   0.5 0.75 1.0 0 0 1 1 0 0 0 1 0 0\n";
+        assert_eq!(parse_ggr(ggr), Ok(vec![
+            stop(255, 0, 0, 0.0), stop(127, 0, 127, 0.25), stop(0, 0, 255, 0.5),
+            stop(0, 0, 127, 0.75), stop(0, 0, 0, 1.0)
+        ]));
+        assert!(parse_ggr("GIMP Gradient\n1\n0 0.5 1\n").is_err());
+    }
+
+    #[test]
+    fn test_control_points() {
+        let srgb = TransferFunction::Srgb;
+        let stops = vec![stop(0, 0, 0, 1.0), stop(255, 0, 0, 0.5), stop(0, 0, 255, 0.5),
+                         stop(0, 0, 0, 0.0)];
+        let positions: Vec<f32> = control_points(&stops, srgb).iter().map(|p| p.position).collect();
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[0], 0.0);
+        assert_eq!(positions[1], 0.5);
+        assert!(positions[2] > 0.5 && positions[2] < 0.51);
+        assert!(positions[3] < 1.0);
+    }
+}
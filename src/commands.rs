@@ -0,0 +1,85 @@
+use color::colormapper::{NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE};
+use formulas::{NUM_FORMULA_GENES, FORMULA_GENE_SIZE};
+use genetics::{BreedRecord, Chromosome, GeneLocks, Genome};
+
+/*
+ * Breeding from the command line, without rendering anything.
+ *
+ * These make new genomes from old ones, so that breeding can be scripted: their output can be
+ * fed back in with --input, or rendered in bulk.
+ */
+
+// Mutation is random, so a mutant can come out identical to its parent. Try this many times
+// to get one that's different.
+const MAX_MUTATION_ATTEMPTS: usize = 100;
+
+pub fn breed(a: &Genome, b: &Genome, locks: &GeneLocks, count: usize) -> Vec<Genome> {
+    breed_recorded(a, b, locks, count).into_iter().map(|(child, _)| child).collect()
+}
+
+// Like breed(), but also says how each child was put together
+fn breed_recorded(a: &Genome, b: &Genome, locks: &GeneLocks,
+                  count: usize) -> Vec<(Genome, BreedRecord)> {
+    (0..count).map(|_| a.breed_locked(b, locks)).collect()
+}
+
+pub fn mutate(genome: &Genome, locks: &GeneLocks, count: usize) -> Vec<Genome> {
+    (0..count).map(|_| {
+        let mut mutant = genome.breed_locked(genome, locks).0;
+        for _ in 1..MAX_MUTATION_ATTEMPTS {
+            if mutant != *genome {
+                break;
+            }
+            mutant = genome.breed_locked(genome, locks).0;
+        }
+        mutant
+    }).collect()
+}
+
+pub fn random(count: usize) -> Vec<Genome> {
+    (0..count).map(|_| Genome {
+        pattern: Chromosome::rand(NUM_FORMULA_GENES, FORMULA_GENE_SIZE),
+        color: Chromosome::rand(NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use genetics::GeneLocks;
+    use super::{breed, breed_recorded, mutate, random};
+
+    #[test]
+    fn test_breed() {
+        let parents = random(2);
+        let (a, b) = (&parents[0], &parents[1]);
+        assert_eq!(breed(a, b, &GeneLocks::default(), 5).len(), 5);
+
+        // Every gene comes from one parent or the other, except for the recorded mutations
+        for (child, record) in breed_recorded(a, b, &GeneLocks::default(), 5) {
+            assert_eq!(record.parents, (a.clone(), b.clone()));
+            assert!(record.replays(&child));
+        }
+
+        // Locked genes always come from the first genome
+        let mut locks = GeneLocks::default();
+        locks.add_spec("pattern").unwrap();
+        for child in breed(a, b, &locks, 5) {
+            assert_eq!(child.pattern, a.pattern);
+        }
+    }
+
+    #[test]
+    fn test_mutate() {
+        let genome = random(1).pop().unwrap();
+        let mutants = mutate(&genome, &GeneLocks::default(), 10);
+        assert_eq!(mutants.len(), 10);
+        assert!(mutants.iter().all(|m| *m != genome));
+        assert!(mutants.iter().all(|m| m.to_base64().len() == genome.to_base64().len()));
+
+        // With everything locked, there's nothing to mutate
+        let mut locks = GeneLocks::default();
+        locks.add_spec("pattern").unwrap();
+        locks.add_spec("color").unwrap();
+        assert_eq!(mutate(&genome, &locks, 1), vec![genome]);
+    }
+}
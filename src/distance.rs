@@ -0,0 +1,116 @@
+use cgmath::Vector3;
+use cgmath::prelude::*;
+use color::TransferFunction;
+use color::colormapper::gradient_from_chromosome;
+use formulas::{PlasmaFormulas, FORMULA_GENE_SIZE, NUM_FORMULA_GENES};
+use genetics::Genome;
+
+/*
+ * How different two plasmas look.
+ *
+ * Comparing raw genome bytes would be misleading: a byte can matter a lot or not at all,
+ * depending on which gene it's in and what the other genes are doing. Instead, we compare
+ * what the genes decode to: formula parameters for the pattern, and gradient colors for the
+ * color scheme. Distances range from 0.0 (identical) to 1.0, and two random plasmas are
+ * typically about 0.3 apart.
+ */
+
+const NUM_GRADIENT_SAMPLES: usize = 32;
+
+pub struct Features {
+    pub formulas: [[f32; FORMULA_GENE_SIZE]; NUM_FORMULA_GENES],
+    pub colors: Vec<Vector3<f32>> // Linear RGB, sampled at evenly spaced gradient positions
+}
+
+impl Features {
+    pub fn new(genome: &Genome) -> Features {
+        // How colors get displayed doesn't change how different two gradients are
+        let gradient = gradient_from_chromosome(&genome.color, TransferFunction::Srgb);
+        let sample_step = 1.0/NUM_GRADIENT_SAMPLES as f32;
+        Features {
+            formulas: PlasmaFormulas::from_chromosome(&genome.pattern).parameters(),
+            colors: (0..NUM_GRADIENT_SAMPLES).map(|i|
+                gradient.get_color(i as f32*sample_step).to_vec3()
+            ).collect()
+        }
+    }
+
+    pub fn distance(&self, other: &Features) -> f32 {
+        (self.pattern_distance(other) + self.color_distance(other))/2.0
+    }
+
+    // A formula's parameters only matter as much as its amplitude does.
+    // If both formulas are flat, they look the same no matter what the other parameters are.
+    fn pattern_distance(&self, other: &Features) -> f32 {
+        let total: f32 = self.formulas.iter().zip(other.formulas.iter()).map(|(a, b)| {
+            let amplitude_distance = (a[0] - b[0]).abs();
+            let mean_amplitude = (a[0] + b[0])/2.0;
+            let squared_error: f32 = a[1..].iter().zip(b[1..].iter()).map(|(x, y)|
+                (x - y)*(x - y)/4.0 // Parameters range from -1.0 to 1.0
+            ).sum();
+            let parameter_distance = (squared_error/(FORMULA_GENE_SIZE - 1) as f32).sqrt();
+            (amplitude_distance + mean_amplitude*parameter_distance)/2.0
+        }).sum();
+        total/NUM_FORMULA_GENES as f32
+    }
+
+    fn color_distance(&self, other: &Features) -> f32 {
+        let max_distance = 3.0f32.sqrt(); // From black to white
+        let total: f32 = self.colors.iter().zip(other.colors.iter()).map(|(a, b)|
+            (a - b).magnitude()/max_distance
+        ).sum();
+        total/NUM_GRADIENT_SAMPLES as f32
+    }
+}
+
+pub fn genome_distance(a: &Genome, b: &Genome) -> f32 {
+    Features::new(a).distance(&Features::new(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use genetics::{Gene, Genome};
+    use super::genome_distance;
+
+    #[test]
+    fn test_genome_distance_identity() {
+        let a = Genome::rand();
+        let b = Genome::rand();
+        assert_eq!(genome_distance(&a, &a), 0.0);
+        assert_eq!(genome_distance(&a, &b), genome_distance(&b, &a));
+        assert!(genome_distance(&a, &b) <= 1.0);
+    }
+
+    #[test]
+    fn test_genome_distance_ignores_silent_genes() {
+        // With zero amplitude, the rest of a formula gene doesn't affect the picture
+        let mut a = Genome::rand();
+        for gene in a.pattern.genes.iter_mut() {
+            gene.data[0] = 0;
+        }
+        let mut b = a.clone();
+        b.pattern.genes[1] = Gene { data: vec![0, 1, 2, 3, 4] };
+        assert_eq!(genome_distance(&a, &b), 0.0);
+
+        // Likewise, inactive control points don't affect the gradient
+        let mut c = a.clone();
+        c.color.genes[0].data[0] = 0;
+        let mut d = c.clone();
+        d.color.genes[0].data[1] = c.color.genes[0].data[1].wrapping_add(100);
+        assert_eq!(genome_distance(&c, &d), 0.0);
+    }
+
+    #[test]
+    fn test_genome_distance_ordering() {
+        // A small change in color should be closer than a big one
+        let mut a = Genome::rand();
+        for (i, gene) in a.color.genes.iter_mut().enumerate() {
+            *gene = Gene { data: vec![255, 128, 128, 128, (i*32) as u8] }; // All gray
+        }
+        let mut near = a.clone();
+        near.color.genes[0].data[3] = 140;
+        let mut far = a.clone();
+        far.color.genes[0].data[3] = 255;
+        assert!(genome_distance(&a, &near) < genome_distance(&a, &far));
+    }
+}
@@ -0,0 +1,237 @@
+use file::generate_gif_bytes;
+use genetics::{Genome, MAX_RATING, MIN_RATING};
+use renderer::{Image, PlasmaRenderer};
+use settings::{EvolveSettings, PlasmaSettings, RenderingSettings};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::Write;
+
+/*
+ * Evolution without a human in the loop.
+ *
+ * Instead of asking the user what looks good, each genome is scored by one or more fitness
+ * functions, which look at a few rendered frames (or at how big the GIF would be). Scores range
+ * from 0.0 (worst) to 1.0 (best), and become ratings in the usual Population machinery.
+ */
+
+// How many frames of each plasma to look at, spread evenly over the loop
+const NUM_EVALUATION_FRAMES: usize = 16;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Fitness {
+    Variety,        // Use as many different colors as possible
+    TargetHue(f32), // Be mostly one hue, in degrees
+    Smoothness,     // Change as little as possible from one frame to the next
+    SmallGif        // Compress well
+}
+
+impl Fitness {
+    // Parses names like "variety" or "hue=200"
+    pub fn from_spec(spec: &str) -> Result<Fitness, String> {
+        let mut parts = spec.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("variety"), None) => Ok(Fitness::Variety),
+            (Some("smoothness"), None) => Ok(Fitness::Smoothness),
+            (Some("gif-size"), None) => Ok(Fitness::SmallGif),
+            (Some("hue"), Some(degrees_str)) => match degrees_str.parse::<f32>() {
+                Ok(degrees) if 0.0 <= degrees && degrees < 360.0 => Ok(Fitness::TargetHue(degrees)),
+                _ => Err(format!("Hue must be a number of degrees from 0 to 360: {}", degrees_str))
+            },
+            _ => Err(format!("Unknown fitness function {} \
+                              (try variety, hue=DEGREES, smoothness or gif-size)", spec))
+        }
+    }
+
+    fn score(&self, genome: &Genome, frames: &[Image], settings: &RenderingSettings) -> f32 {
+        match *self {
+            Fitness::Variety => color_variety(frames, settings.palette_size),
+            Fitness::TargetHue(degrees) => hue_closeness(frames, degrees),
+            Fitness::Smoothness => smoothness(frames),
+            Fitness::SmallGif => {
+                let gif_size = generate_gif_bytes(genome, settings).len();
+                let raw_size = settings.width*settings.height*frames.len();
+                1.0 - (gif_size as f32/raw_size as f32).min(1.0)
+            }
+        }
+    }
+}
+
+// Breeds the population for a number of generations, then writes out the fittest genomes
+pub fn run_evolve(settings: PlasmaSettings, evolve_settings: &EvolveSettings) -> Result<(), String> {
+    // Render just enough frames to judge each plasma by
+    let mut evaluation_settings = settings.rendering.clone();
+    evaluation_settings.frames_per_second =
+        NUM_EVALUATION_FRAMES as f32/evaluation_settings.loop_duration;
+    let rate = |genome: &Genome| {
+        let score = evaluate(genome, &evolve_settings.fitness, &evaluation_settings);
+        MIN_RATING as f32 + score*(MAX_RATING - MIN_RATING) as f32
+    };
+
+    // Starting genomes haven't been scored yet
+    let mut population = settings.genetics.population;
+    let starting_genomes: Vec<Genome> = population.iter().map(|(g, _)| g.clone()).collect();
+    for genome in starting_genomes {
+        let fitness = rate(&genome);
+        population.add_with_fitness(genome, fitness);
+    }
+
+    for generation in 0..evolve_settings.generations {
+        for _ in 0..population.len() {
+            let offspring = population.breed();
+            if population.fitness(&offspring).is_none() {
+                let fitness = rate(&offspring);
+                population.add_with_fitness(offspring, fitness);
+            }
+        }
+        let fitnesses: Vec<f32> = population.iter().map(|(_, f)| f).collect();
+        let best = fitnesses.iter().cloned().fold(MIN_RATING as f32, f32::max);
+        let mean = fitnesses.iter().sum::<f32>()/fitnesses.len() as f32;
+        writeln!(&mut ::std::io::stderr(), "Generation {}/{}: best {:.2}, mean {:.2}",
+                 generation + 1, evolve_settings.generations, best, mean).unwrap();
+    }
+
+    // Write the fittest genomes, best first
+    let mut ranked: Vec<(&Genome, f32)> = population.iter().collect();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    let lines: Vec<String> = ranked.iter().take(evolve_settings.num_top).map(|&(g, _)|
+        g.to_base64()
+    ).collect();
+    let output = format!("{}\n", lines.join("\n"));
+    match evolve_settings.path {
+        Some(ref path) => File::create(path).and_then(|mut file|
+            file.write_all(output.as_bytes())
+        ).map_err(|e| format!("Couldn't write to {}: {}", path, e)),
+        None => {
+            print!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+// The average of all the fitness functions' scores
+fn evaluate(genome: &Genome, fitness: &[Fitness], settings: &RenderingSettings) -> f32 {
+    let mut renderer = PlasmaRenderer::new(genome, settings);
+    let frames: Vec<Image> = (0..NUM_EVALUATION_FRAMES).map(|i| {
+        let mut image = Image::new(settings.width, settings.height);
+        renderer.render(&mut image, i as f32/NUM_EVALUATION_FRAMES as f32);
+        image
+    }).collect();
+    let total: f32 = fitness.iter().map(|f| f.score(genome, &frames, settings)).sum();
+    total/fitness.len() as f32
+}
+
+// The fraction of available colors that get used
+fn color_variety(frames: &[Image], palette_size: Option<usize>) -> f32 {
+    let mut colors: Vec<&[u8]> = frames.iter().flat_map(|f| f.pixel_data.chunks(3)).collect();
+    let num_pixels = colors.len();
+    colors.sort();
+    colors.dedup();
+    let max_colors = palette_size.unwrap_or(256).min(num_pixels);
+    (colors.len() as f32/max_colors as f32).min(1.0)
+}
+
+// How close the average hue is to a target hue. Grayish pixels count for less, since their hue
+// is barely visible.
+fn hue_closeness(frames: &[Image], target_degrees: f32) -> f32 {
+    let (mut x, mut y) = (0.0, 0.0);
+    for pixel in frames.iter().flat_map(|f| f.pixel_data.chunks(3)) {
+        let (hue, saturation) = hue_and_saturation(pixel[0], pixel[1], pixel[2]);
+        x += saturation*hue.cos();
+        y += saturation*hue.sin();
+    }
+    if x == 0.0 && y == 0.0 {
+        return 0.0; // No hue at all
+    }
+    let mean_hue = y.atan2(x);
+    let difference = (mean_hue - target_degrees.to_radians()).abs() % (2.0*PI);
+    1.0 - difference.min(2.0*PI - difference)/PI
+}
+
+// Hue in radians, and saturation from 0.0 to 1.0
+fn hue_and_saturation(r: u8, g: u8, b: u8) -> (f32, f32) {
+    let (r, g, b) = (r as f32/255.0, g as f32/255.0, b as f32/255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+    if chroma == 0.0 {
+        return (0.0, 0.0);
+    }
+    let sector = if max == r {
+        ((g - b)/chroma + 6.0) % 6.0
+    } else if max == g {
+        (b - r)/chroma + 2.0
+    } else {
+        (r - g)/chroma + 4.0
+    };
+    (sector*PI/3.0, chroma/max)
+}
+
+// One minus the average change per color channel between consecutive frames, wrapping around
+// from the last frame to the first, since the animation loops
+fn smoothness(frames: &[Image]) -> f32 {
+    let mut total_change = 0.0;
+    let mut num_values = 0;
+    for (i, frame) in frames.iter().enumerate() {
+        let next_frame = &frames[(i + 1) % frames.len()];
+        for (a, b) in frame.pixel_data.iter().zip(next_frame.pixel_data.iter()) {
+            total_change += (*a as f32 - *b as f32).abs()/255.0;
+            num_values += 1;
+        }
+    }
+    1.0 - total_change/num_values.max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use renderer::Image;
+    use super::{color_variety, hue_closeness, smoothness, Fitness};
+
+    fn solid_image(r: u8, g: u8, b: u8) -> Image {
+        let mut image = Image::new(4, 4);
+        for pixel in image.pixel_data.chunks_mut(3) {
+            pixel.copy_from_slice(&[r, g, b]);
+        }
+        image
+    }
+
+    #[test]
+    fn test_fitness_from_spec() {
+        assert_eq!(Fitness::from_spec("variety"), Ok(Fitness::Variety));
+        assert_eq!(Fitness::from_spec("hue=200"), Ok(Fitness::TargetHue(200.0)));
+        assert_eq!(Fitness::from_spec("smoothness"), Ok(Fitness::Smoothness));
+        assert_eq!(Fitness::from_spec("gif-size"), Ok(Fitness::SmallGif));
+        assert!(Fitness::from_spec("hue").is_err());
+        assert!(Fitness::from_spec("hue=400").is_err());
+        assert!(Fitness::from_spec("beauty").is_err());
+    }
+
+    #[test]
+    fn test_color_variety() {
+        let plain = vec![solid_image(10, 20, 30), solid_image(10, 20, 30)];
+        let mut varied = vec![solid_image(10, 20, 30), solid_image(10, 20, 30)];
+        for (i, value) in varied[0].pixel_data.iter_mut().enumerate() {
+            *value = i as u8;
+        }
+        assert!(color_variety(&varied, Some(16)) > color_variety(&plain, Some(16)));
+        assert_eq!(color_variety(&varied, Some(16)), 1.0);
+    }
+
+    #[test]
+    fn test_hue_closeness() {
+        let red = vec![solid_image(255, 0, 0)];
+        assert!(hue_closeness(&red, 0.0) > 0.99);
+        assert!(hue_closeness(&red, 350.0) > 0.9); // Hues wrap around
+        assert!(hue_closeness(&red, 180.0) < 0.01);
+        let blue = vec![solid_image(0, 0, 255)];
+        assert!(hue_closeness(&blue, 240.0) > 0.99);
+        assert_eq!(hue_closeness(&vec![solid_image(128, 128, 128)], 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_smoothness() {
+        let still: Vec<_> = (0..4).map(|_| solid_image(100, 100, 100)).collect();
+        assert_eq!(smoothness(&still), 1.0);
+        let flashing = vec![solid_image(0, 0, 0), solid_image(255, 255, 255)];
+        assert_eq!(smoothness(&flashing), 0.0);
+    }
+}
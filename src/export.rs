@@ -0,0 +1,190 @@
+use color::{Color, ColorSpace};
+use color::colormapper::{build_gradient, ColorMapper};
+use color::gradient::{Gradient, Interpolation};
+use color::palette::fixed::color_to_hex;
+use color::stops::ColorStop;
+use genetics::Genome;
+use png::encode_png;
+use renderer::Image;
+use settings::RenderingSettings;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+/*
+ * Exporting a genome's colors, for matching them elsewhere.
+ *
+ * The gradient is written as CSS and as a GIMP gradient, and the palette that a GIF of the genome
+ * would use is written as CSS, as a GIMP palette, and as a strip of color swatches.
+ */
+
+// Gradients that aren't interpolated linearly are sampled this many times instead
+const NUM_SAMPLED_STOPS: usize = 64;
+
+// Width and height of each color in the swatch strip, in pixels
+const SWATCH_SIZE: usize = 32;
+
+// Writes path.css, path.ggr, path.gpl and path.png
+pub fn run_export(genome: &Genome, settings: &RenderingSettings, path: &str) -> Result<(), String> {
+    let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or("plasma");
+    let stops = gradient_stops(&build_gradient(&genome.color, settings), settings);
+    let palette = ColorMapper::new(&genome.color, settings).get_palette();
+    let files = vec![
+        ("css", css_text(&stops, &palette, settings.color_space).into_bytes()),
+        ("ggr", ggr_text(&stops, name).into_bytes()),
+        ("gpl", gpl_text(&palette, name).into_bytes()),
+        ("png", encode_png(&swatches(&palette)))
+    ];
+    for (extension, bytes) in files {
+        let file_path = format!("{}.{}", path, extension);
+        File::create(&file_path).and_then(|mut file| file.write_all(&bytes[..])).map_err(|e|
+            format!("Couldn't write to {}: {}", file_path, e)
+        )?;
+    }
+    Ok(())
+}
+
+/*
+ * Color stops that describe a gradient from 0.0 to 1.0.
+ *
+ * Plasmas loop through their gradients, so the first and last stops are the same color. Linear
+ * gradients become one stop per control point; other kinds are sampled evenly.
+ */
+fn gradient_stops(gradient: &Gradient, settings: &RenderingSettings) -> Vec<ColorStop> {
+    let positions: Vec<f32> = match gradient.interpolation() {
+        Interpolation::Linear => gradient.points().iter().map(|p| p.position).collect(),
+        _ => (0..NUM_SAMPLED_STOPS).map(|i| i as f32/NUM_SAMPLED_STOPS as f32).collect()
+    };
+    let stop = |position: f32| ColorStop {
+        color: gradient.get_color(position).to_gamma(settings.transfer_function),
+        position: position
+    };
+    let mut stops = vec![stop(0.0)];
+    stops.extend(positions.into_iter().filter(|&p| p > 0.0).map(|p| stop(p)));
+    stops.push(ColorStop { position: 1.0, ..stops[0] });
+    stops
+}
+
+fn css_text(stops: &[ColorStop], palette: &[Color], color_space: ColorSpace) -> String {
+    let mut text = ":root {\n".to_string();
+    for (i, stop) in stops.iter().enumerate() {
+        text.push_str(&format!("  --plasma-stop-{}: #{}; /* {:.1}% */\n",
+                               i + 1, color_to_hex(stop.color), stop.position*100.0));
+    }
+    for (i, &color) in palette.iter().enumerate() {
+        text.push_str(&format!("  --plasma-color-{}: #{};\n", i + 1, color_to_hex(color)));
+    }
+
+    // Browsers can mix colors the same way the plasma does
+    let interpolation_space = match color_space {
+        ColorSpace::LinearRgb => "srgb-linear",
+        ColorSpace::Oklab => "oklab"
+    };
+    let css_stops: Vec<String> = stops.iter().map(|stop|
+        format!("#{} {:.1}%", color_to_hex(stop.color), stop.position*100.0)
+    ).collect();
+    text.push_str(&format!("  --plasma-gradient: linear-gradient(to right in {}, {});\n}}\n",
+                           interpolation_space, css_stops.join(", ")));
+    text
+}
+
+// A GIMP gradient, with a linear segment between each pair of stops
+fn ggr_text(stops: &[ColorStop], name: &str) -> String {
+    let mut text = format!("GIMP Gradient\nName: {}\n{}\n", name, stops.len() - 1);
+    let rgb = |color: Color| format!("{:.6} {:.6} {:.6}", color.r as f32/255.0,
+                                     color.g as f32/255.0, color.b as f32/255.0);
+    for pair in stops.windows(2) {
+        let (left, right) = (pair[0], pair[1]);
+        text.push_str(&format!("{:.6} {:.6} {:.6} {} 1.000000 {} 1.000000 0 0\n",
+                               left.position, (left.position + right.position)/2.0,
+                               right.position, rgb(left.color), rgb(right.color)));
+    }
+    text
+}
+
+fn gpl_text(palette: &[Color], name: &str) -> String {
+    let mut text = format!("GIMP Palette\nName: {}\nColumns: 16\n#\n", name);
+    for &color in palette {
+        text.push_str(&format!("{:3} {:3} {:3}\t#{}\n",
+                               color.r, color.g, color.b, color_to_hex(color)));
+    }
+    text
+}
+
+// A row of squares, one for each palette color
+fn swatches(palette: &[Color]) -> Image {
+    let mut image = Image::new(SWATCH_SIZE*palette.len(), SWATCH_SIZE);
+    for (i, &color) in palette.iter().enumerate() {
+        for x in i*SWATCH_SIZE..(i + 1)*SWATCH_SIZE {
+            for y in 0..SWATCH_SIZE {
+                image.plot(x, y, color);
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use color::{Color, ColorSpace, TransferFunction};
+    use color::gradient::{ControlPoint, Gradient, Interpolation};
+    use color::stops::{self, ColorStop};
+    use settings::RenderingSettings;
+    use super::{css_text, ggr_text, gradient_stops, gpl_text, SWATCH_SIZE, swatches};
+
+    fn test_gradient() -> Gradient {
+        let point = |r, g, b, position| ControlPoint {
+            color: Color::new(r, g, b).to_linear(TransferFunction::Srgb),
+            position: position
+        };
+        let points = vec![point(255, 0, 0, 0.25), point(0, 0, 255, 0.75)];
+        Gradient::new(points, TransferFunction::Srgb)
+    }
+
+    #[test]
+    fn test_gradient_stops() {
+        let settings = RenderingSettings::for_test();
+        let mut gradient = test_gradient();
+        let linear_stops = gradient_stops(&gradient, &settings);
+        let positions: Vec<f32> = linear_stops.iter().map(|s| s.position).collect();
+        assert_eq!(positions, vec![0.0, 0.25, 0.75, 1.0]);
+        assert_eq!(linear_stops[1].color, Color::new(255, 0, 0));
+        assert_eq!(linear_stops[0].color, linear_stops[3].color);
+
+        gradient.set_interpolation(Interpolation::Smooth);
+        let smooth_stops = gradient_stops(&gradient, &settings);
+        assert_eq!(smooth_stops.len(), 65);
+        assert_eq!(smooth_stops[0].color, smooth_stops[64].color);
+    }
+
+    // The CSS gradient and the GIMP gradient can be read back in as the same stops
+    #[test]
+    fn test_export_round_trip() {
+        let stops = gradient_stops(&test_gradient(), &RenderingSettings::for_test());
+        let palette = vec![Color::new(255, 0, 77), Color::new(0, 135, 81)];
+
+        let css = css_text(&stops, &palette, ColorSpace::Oklab);
+        assert!(css.contains("--plasma-color-2: #008751;"));
+        let gradient_line = css.lines().find(|l| l.contains("--plasma-gradient")).unwrap();
+        let value = gradient_line.splitn(2, ": ").nth(1).unwrap();
+        assert!(value.starts_with("linear-gradient(to right in oklab, "));
+        assert_eq!(stops::parse(value).unwrap(), stops);
+
+        let from_ggr = stops::parse_ggr(&ggr_text(&stops, "test")).unwrap();
+        let colors = |stops: &[ColorStop]| stops.iter().map(|s| s.color).collect::<Vec<_>>();
+        let midpoints: Vec<_> = from_ggr.iter().step_by(2).cloned().collect();
+        assert_eq!(colors(&midpoints), colors(&stops));
+    }
+
+    #[test]
+    fn test_gpl_text_and_swatches() {
+        let palette = vec![Color::new(255, 0, 77), Color::new(0, 135, 81)];
+        assert_eq!(gpl_text(&palette, "test"),
+                   "GIMP Palette\nName: test\nColumns: 16\n#\n\
+                    255   0  77\t#ff004d\n  0 135  81\t#008751\n");
+        let image = swatches(&palette);
+        assert_eq!((image.width, image.height), (2*SWATCH_SIZE, SWATCH_SIZE));
+        assert_eq!(&image.pixel_data[..3], &[255, 0, 77]);
+        assert_eq!(&image.pixel_data[image.pixel_data.len() - 3..], &[0, 135, 81]);
+    }
+}
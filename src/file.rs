@@ -1,26 +1,197 @@
 use gif::{Encoder, Frame, SetParameter, Repeat};
-use color::Color;
-use renderer::{Image, PlasmaRenderer};
-use settings::PlasmaSettings;
+use color::{Color, LinearColor};
+use color::colormapper::{build_gradient, build_palette, sample_gradient};
+use color::colormapper::{shown_gradient_samples, ColorMapper};
+use color::palette::Palette;
+use color::palette::dither::Ditherer;
+use fastmath::FastMath;
+use formulas::PlasmaFormulas;
+use renderer::{get_positions, render_frame, HeldPixels, Image, PlasmaRenderer};
+use genetics::Genome;
+use settings::RenderingSettings;
 use std::borrow::Cow;
+use std::cmp;
 use std::collections::BTreeMap;
 use std::ops::Range;
 
-pub fn generate_gif_bytes(settings: PlasmaSettings) -> Vec<u8> {
+// GIF palettes can't be any bigger
+const MAX_PALETTE_SIZE: usize = 256;
+
+// How many frames on either side of each frame its local palette is chosen for
+const LOCAL_PALETTE_WINDOW: usize = 2;
+
+pub fn generate_gif_bytes(genome: &Genome, settings: &RenderingSettings) -> Vec<u8> {
+    // Color cycling only needs new palettes, unless the palette has to stay the same
+    if settings.color_cycling && settings.palette.is_none() {
+        return generate_color_cycling_gif_bytes(genome, settings);
+    }
+    if settings.local_palettes && settings.palette.is_none() {
+        return generate_local_palette_gif_bytes(genome, settings);
+    }
+
     // Render all the frames at once
-    let mut renderer = PlasmaRenderer::new(&settings.genetics.genome, &settings.rendering);
-    let num_frames = (settings.rendering.frames_per_second*settings.rendering.loop_duration).
-        round() as usize;
+    let mut renderer = PlasmaRenderer::new(genome, settings);
+    let num_frames = (settings.frames_per_second*settings.loop_duration).round() as usize;
     let times = (0..num_frames).map(|i| i as f32/num_frames as f32);
+
+    // With temporal dithering, each frame depends on the one before it. Start from the last
+    // frame, so that the first one does too, and the loop doesn't flicker where it starts over.
+    if settings.temporal_threshold.is_some() && num_frames > 1 {
+        let mut image = Image::new(settings.width, settings.height);
+        renderer.render(&mut image, (num_frames - 1) as f32/num_frames as f32);
+    }
     let frames: Vec<Image> = times.map(|time| {
-        let mut image = Image::new(settings.rendering.width, settings.rendering.height);
+        let mut image = Image::new(settings.width, settings.height);
         renderer.render(&mut image, time);
         image
     }).collect();
+    encode_frames(&frames[..], renderer.get_palette(), settings)
+}
+
+/*
+ * Color cycling GIFs, where every frame has the same pixels but its own palette.
+ *
+ * Each palette index stands for a position along the gradient, and each frame's palette is the
+ * gradient shifted a little further, so the colors flow through the still pattern. Dithering
+ * mixes neighboring positions, which stay neighbors as the palette turns. Error diffusion can't
+ * be done this way, so those ditherers use blue noise instead.
+ */
+fn generate_color_cycling_gif_bytes(genome: &Genome, settings: &RenderingSettings) -> Vec<u8> {
+    let num_frames = (settings.frames_per_second*settings.loop_duration).round() as usize;
+    let num_colors = settings.palette_size.unwrap_or(MAX_PALETTE_SIZE).min(MAX_PALETTE_SIZE);
+    let mut formulas = PlasmaFormulas::from_chromosome(&genome.pattern);
+    formulas.set_time(0.0);
+    let ditherer = if settings.ditherer.diffuses_errors() {
+        Ditherer::BlueNoise
+    } else {
+        settings.ditherer
+    };
+    let positions = get_positions(&formulas, settings.width, settings.height);
+    let indexed_frame: Vec<u8> = positions.iter().enumerate().map(|(i, &position)| {
+        let threshold = if settings.dithering {
+            (ditherer.threshold(i % settings.width, i / settings.width) as f32 + 0.5)/64.0
+        } else {
+            0.5
+        };
+        position_to_index(position, threshold, num_colors)
+    }).collect();
+
+    let gradient = build_gradient(&genome.color, settings);
+    let palettes: Vec<Vec<Color>> = (0..num_frames).map(|frame| {
+        let offset = frame as f32/num_frames as f32;
+        (0..num_colors).map(|index| {
+            let position = (index as f32/num_colors as f32 + offset).wrap();
+            gradient.get_color(position).to_gamma(settings.transfer_function)
+        }).collect()
+    }).collect();
+
+    encode_indexed_frames(vec![indexed_frame; num_frames], palettes, settings)
+}
+
+// The palette index for a gradient position, when each index stands for an evenly spaced position.
+// A threshold of 0.5 rounds to the nearest one, and dithering varies the threshold between 0 and 1.
+fn position_to_index(position: f32, threshold: f32, num_colors: usize) -> u8 {
+    ((position.wrap()*num_colors as f32 + threshold) as usize % num_colors) as u8
+}
+
+/*
+ * GIFs where every frame has its own palette, chosen for the colors it actually shows.
+ *
+ * Each palette covers the gradient colors shown in a window of frames around its own, starting
+ * from the palette of the frame before, so the colors drift instead of jumping from one frame to
+ * the next. The windows wrap around the end of the loop, and the palettes go around it twice, so
+ * the last one leads smoothly into the first one too. Frames that show the whole gradient end up
+ * with the same palette as without this.
+ */
+fn generate_local_palette_gif_bytes(genome: &Genome, settings: &RenderingSettings) -> Vec<u8> {
+    let num_frames = (settings.frames_per_second*settings.loop_duration).round() as usize;
+    let times: Vec<f32> = (0..num_frames).map(|i| i as f32/num_frames as f32).collect();
+    let gradient_samples = sample_gradient(&genome.color, settings);
+    let mut formulas = PlasmaFormulas::from_chromosome(&genome.pattern);
+
+    // Find which gradient samples each frame shows
+    let frames_shown: Vec<Vec<bool>> = times.iter().map(|&time| {
+        formulas.set_time(time);
+        shown_gradient_samples(&get_positions(&formulas, settings.width, settings.height))
+    }).collect();
+    let palettes = fit_local_palettes(&gradient_samples, &frames_shown,
+                                      build_palette(&gradient_samples, settings));
+
+    // Render and index each frame with its own palette. As in generate_gif_bytes(), temporal
+    // dithering starts from the last frame.
+    let mut held_pixels = settings.temporal_threshold.map(|_| HeldPixels::new());
+    let mut image = Image::new(settings.width, settings.height);
+    if held_pixels.is_some() && num_frames > 1 {
+        let color_mapper = ColorMapper::with_palette(&gradient_samples, &palettes[num_frames - 1],
+                                                     settings);
+        render_frame(&mut image, times[num_frames - 1], &mut formulas, &color_mapper,
+                     settings.dithering, held_pixels.as_mut());
+    }
+    let mut indexed_frames = vec![];
+    let mut gamma_palettes = vec![];
+    let mut color_mapper = ColorMapper::with_palette(&gradient_samples, &palettes[0], settings);
+    for (frame, (&time, palette)) in times.iter().zip(palettes.iter()).enumerate() {
+        if frame > 0 && palette.colors != palettes[frame - 1].colors {
+            color_mapper = ColorMapper::with_palette(&gradient_samples, palette, settings);
+        }
+        render_frame(&mut image, time, &mut formulas, &color_mapper, settings.dithering,
+                     held_pixels.as_mut());
+        let gamma_palette = color_mapper.get_palette();
+        indexed_frames.push(index_image(&image, &gamma_palette, palette, settings));
+        gamma_palettes.push(gamma_palette);
+    }
+    encode_indexed_frames(indexed_frames, gamma_palettes, settings)
+}
+
+// Fits a palette to each frame, given which gradient samples it shows, starting from one that
+// covers the whole gradient
+fn fit_local_palettes(gradient_samples: &[LinearColor], frames_shown: &[Vec<bool>],
+                      mut palette: Palette) -> Vec<Palette> {
+    let num_frames = frames_shown.len();
+    let window_samples = |frame: usize| -> Vec<LinearColor> {
+        let window_frames = (0..2*LOCAL_PALETTE_WINDOW + 1).map(|offset|
+            (frame + offset + num_frames*LOCAL_PALETTE_WINDOW - LOCAL_PALETTE_WINDOW) % num_frames
+        ).collect::<Vec<usize>>();
+        gradient_samples.iter().enumerate().filter(|&(i, _)|
+            window_frames.iter().any(|&window_frame| frames_shown[window_frame][i])
+        ).map(|(_, &color)| color).collect()
+    };
 
+    // Keep the palettes from the second time around the loop
+    let mut palettes = vec![];
+    for lap in 0..2 {
+        for frame in 0..num_frames {
+            palette = palette.adjusted(&window_samples(frame));
+            if lap == 1 {
+                palettes.push(palette.clone());
+            }
+        }
+    }
+    palettes
+}
+
+// Converts an image to indexes into a palette, given both gamma-encoded and linear. Colors that
+// aren't in it, like pixels held over from a frame with another palette, get the nearest one.
+fn index_image(image: &Image, gamma_palette: &[Color], palette: &Palette,
+               settings: &RenderingSettings) -> Vec<u8> {
+    let mut palette_map = BTreeMap::new();
+    for (index, color) in gamma_palette.iter().enumerate() {
+        palette_map.insert((color.r, color.g, color.b), index as u8);
+    }
+    image.pixel_data.chunks(3).map(|slice| {
+        let rgb = (slice[0], slice[1], slice[2]);
+        *palette_map.entry(rgb).or_insert_with(|| {
+            let color = Color::new(rgb.0, rgb.1, rgb.2).to_linear(settings.transfer_function);
+            palette.get_nearest_index(color) as u8
+        })
+    }).collect()
+}
+
+// Encodes frames as a looping GIF. Every pixel must be one of the palette's colors.
+pub fn encode_frames(frames: &[Image], palette: Vec<Color>,
+                     settings: &RenderingSettings) -> Vec<u8> {
     // Convert frames to indexed
-    let mut palette = renderer.get_palette();
-    let mut indexed_frames: Vec<Vec<u8>> = {
+    let indexed_frames: Vec<Vec<u8>> = {
         let mut palette_map = BTreeMap::new();
         for (index, color) in palette.iter().enumerate() {
             palette_map.insert((color.r, color.g, color.b), index as u8);
@@ -32,29 +203,42 @@ pub fn generate_gif_bytes(settings: PlasmaSettings) -> Vec<u8> {
             }).collect()
         ).collect()
     };
+    encode_indexed_frames(indexed_frames, vec![palette], settings)
+}
 
+// Encodes frames of palette indexes as a looping GIF. Given one palette, every frame uses it;
+// otherwise, each frame has its own.
+pub fn encode_indexed_frames(mut indexed_frames: Vec<Vec<u8>>, mut palettes: Vec<Vec<Color>>,
+                             settings: &RenderingSettings) -> Vec<u8> {
     // Encode a GIF as-is (no transparent pixels)
-    let mut gif_bytes = encode_gif(&indexed_frames[..], &palette[..], &settings, false);
+    let mut gif_bytes = encode_gif(&indexed_frames[..], &palettes[..], settings, false);
 
     // Encode the GIF again, but this time try to optimize it by using transparent pixels
-    if palette.len() < 256 {
+    if palettes.iter().all(|palette| palette.len() < 256) {
         // Add transparency to the frames
-        palette.insert(0, Color::new(0, 0, 0)); // Add transparent palette entry
+        for palette in palettes.iter_mut() {
+            palette.insert(0, Color::new(0, 0, 0)); // Add transparent palette entry
+        }
         for indexed_frame in indexed_frames.iter_mut() {
             for index in indexed_frame.iter_mut() {
                 *index += 1; // Adjust existing indexes to accommodate transparency
             }
         }
 
-        // Optimize pixels
-        let mut previous_indexed_frame = indexed_frames[0].clone();
-        for i in 1..indexed_frames.len() {
-            let original_indexed_frame = indexed_frames[i].clone();
+        // Optimize pixels, working backwards so each frame is compared to the original one before.
+        // Pixels are unchanged if their color is, even if their palette index isn't.
+        let palette_of = |frame: usize| &palettes[cmp::min(frame, palettes.len() - 1)];
+        for i in (1..indexed_frames.len()).rev() {
+            let previous_indexed_frame: Vec<u8> = {
+                let (previous_palette, palette) = (palette_of(i - 1), palette_of(i));
+                indexed_frames[i - 1].iter().zip(indexed_frames[i].iter()).map(|(&a, &b)|
+                    if previous_palette[a as usize] == palette[b as usize] { b } else { 0 }
+                ).collect()
+            };
             optimize_pixels(&previous_indexed_frame[..], &mut indexed_frames[i][..]);
-            previous_indexed_frame = original_indexed_frame;
         }
 
-        let new_gif_bytes = encode_gif(&indexed_frames[..], &palette[..], &settings, true);
+        let new_gif_bytes = encode_gif(&indexed_frames[..], &palettes[..], settings, true);
         if new_gif_bytes.len() < gif_bytes.len() {
             // Only use transparency if it results in a smaller file
             gif_bytes = new_gif_bytes;
@@ -65,39 +249,54 @@ pub fn generate_gif_bytes(settings: PlasmaSettings) -> Vec<u8> {
     gif_bytes
 }
 
-fn encode_gif(indexed_frames: &[Vec<u8>], palette: &[Color],
-              settings: &PlasmaSettings, transparent_index_zero: bool) -> Vec<u8> {
-    // Calculate frame delay
-    let frame_delay_seconds = settings.rendering.loop_duration/(indexed_frames.len() as f32);
-    let frame_delay_centiseconds = (frame_delay_seconds*100.0).round() as u16;
-
+fn encode_gif(indexed_frames: &[Vec<u8>], palettes: &[Vec<Color>],
+              settings: &RenderingSettings, transparent_index_zero: bool) -> Vec<u8> {
     // Output GIF byte stream
     let mut output = vec![];
     {
-        let palette_bytes: Vec<u8> = palette.iter().flat_map(|c| vec![c.r, c.g, c.b]).collect();
-        let mut encoder = Encoder::new(
-            &mut output,
-            settings.rendering.width as u16,
-            settings.rendering.height as u16,
-            &palette_bytes[..]
-        ).unwrap();
-        encoder.set(Repeat::Infinite).unwrap();
-
-        for indexed_frame in indexed_frames.iter() {
+        let mut encoder = start_gif(&mut output, &palettes[0], settings);
+        for (i, indexed_frame) in indexed_frames.iter().enumerate() {
             let mut frame = Frame::default();
-            frame.width = settings.rendering.width as u16;
-            frame.height = settings.rendering.height as u16;
-            frame.delay = frame_delay_centiseconds;
+            frame.width = settings.width as u16;
+            frame.height = settings.height as u16;
+            frame.delay = frame_delay(indexed_frames.len(), settings);
             frame.buffer = Cow::Borrowed(indexed_frame);
             if transparent_index_zero {
                 frame.transparent = Some(0);
             }
+            if palettes.len() > 1 && palettes[i] != palettes[0] {
+                // The first frame's palette is the global one
+                frame.palette = Some(palette_bytes(&palettes[i]));
+            }
             encoder.write_frame(&frame).unwrap();
         }
     }
     output
 }
 
+// Starts a looping GIF with a global palette
+fn start_gif<'a>(output: &'a mut Vec<u8>, palette: &[Color],
+                 settings: &RenderingSettings) -> Encoder<&'a mut Vec<u8>> {
+    let mut encoder = Encoder::new(
+        output,
+        settings.width as u16,
+        settings.height as u16,
+        &palette_bytes(palette)[..]
+    ).unwrap();
+    encoder.set(Repeat::Infinite).unwrap();
+    encoder
+}
+
+fn palette_bytes(palette: &[Color]) -> Vec<u8> {
+    palette.iter().flat_map(|c| vec![c.r, c.g, c.b]).collect()
+}
+
+// How long each frame is shown, in hundredths of a second
+fn frame_delay(num_frames: usize, settings: &RenderingSettings) -> u16 {
+    let frame_delay_seconds = settings.loop_duration/(num_frames as f32);
+    (frame_delay_seconds*100.0).round() as u16
+}
+
 fn optimize_pixels(previous_pixels: &[u8], pixels: &mut [u8]) {
     // Find runs of pixels that didn't change from one frame to the next.
     // These runs are candidates to be made transparent.
@@ -150,7 +349,9 @@ fn optimize_pixels(previous_pixels: &[u8], pixels: &mut [u8]) {
 
 #[cfg(test)]
 mod tests {
-    use super::optimize_pixels;
+    use color::{ColorSpace, LinearColor};
+    use color::palette::Palette;
+    use super::{fit_local_palettes, optimize_pixels, position_to_index};
 
     fn assert_optimize(previous_pixels: &[u8], pixels: &mut [u8], expected_optimization: &[u8]) {
         optimize_pixels(previous_pixels, pixels);
@@ -175,4 +376,42 @@ mod tests {
         assert_optimize(&[1,1,2,2,2,2], &mut [2,2,2,2,2,2], &[2,2,2,2,2,2]);
         assert_optimize(&[2,2,2,2,1,1], &mut [2,2,2,2,2,2], &[2,2,2,2,2,2]);
     }
+
+    #[test]
+    fn test_position_to_index() {
+        // Positions round to the nearest index, and wrap around
+        assert_eq!(position_to_index(0.0, 0.5, 4), 0);
+        assert_eq!(position_to_index(0.3, 0.5, 4), 1);
+        assert_eq!(position_to_index(0.9, 0.5, 4), 0);
+        assert_eq!(position_to_index(-0.25, 0.5, 4), 3);
+
+        // Dithering mixes neighboring indexes in proportion
+        let thresholds = (0..64).map(|t| (t as f32 + 0.5)/64.0);
+        let indexes: Vec<u8> = thresholds.map(|t| position_to_index(0.3125, t, 4)).collect();
+        assert_eq!(indexes.iter().filter(|&&i| i == 1).count(), 48);
+        assert_eq!(indexes.iter().filter(|&&i| i == 2).count(), 16);
+    }
+
+    #[test]
+    fn test_fit_local_palettes() {
+        let samples: Vec<LinearColor> = (0..64).map(|i| LinearColor::new(i*1024, 0, 0)).collect();
+        let palette = Palette::new(4, &samples, false, ColorSpace::LinearRgb);
+        let error = |palette: &Palette, samples: &[LinearColor]| samples.iter().map(|&sample| {
+            let nearest = palette.colors[palette.get_nearest_index(sample)];
+            nearest.distance(sample, ColorSpace::LinearRgb)
+        }).sum::<f32>();
+
+        // Frames that show the whole gradient keep the palette that covers it
+        let palettes = fit_local_palettes(&samples, &vec![vec![true; 64]; 4], palette.clone());
+        assert_eq!(palettes.len(), 4);
+        assert!(palettes.iter().all(|p| p.colors == palette.colors));
+
+        // Frames that only show part of it get palettes that fit that part better
+        let frames_shown: Vec<Vec<bool>> = (0..12).map(|frame|
+            (0..64).map(|i| (i < 32) == (frame < 6)).collect()
+        ).collect();
+        let palettes = fit_local_palettes(&samples, &frames_shown, palette.clone());
+        assert!(error(&palettes[3], &samples[..32]) < error(&palette, &samples[..32]));
+        assert!(error(&palettes[9], &samples[32..]) < error(&palette, &samples[32..]));
+    }
 }
@@ -4,10 +4,25 @@ use fastmath::FastMath;
 pub const FORMULA_GENE_SIZE: usize = 5;
 pub const NUM_FORMULA_GENES: usize = 3;
 
+// The largest magnitude that ByteFloat::to_ifloat() can return
+const MAX_IFLOAT: f32 = 8.0;
+
 trait Formula {
     fn from_gene(gene: &Gene) -> Self;
     fn set_time(&mut self, time: f32);
     fn get_value(&self, x: f32, y: f32) -> f32;
+
+    // Decoded parameters, scaled to [-1.0, 1.0]. Amplitude always comes first.
+    fn parameters(&self) -> [f32; FORMULA_GENE_SIZE];
+
+    // Blends the decoded parameters of two formulas. An amount of 0.0 gives self, and 1.0 other.
+    fn lerp(&self, other: &Self, amount: f32) -> Self;
+}
+
+// Interpolates between two angles (in turns) the short way around the circle
+fn lerp_angle(from: f32, to: f32, amount: f32) -> f32 {
+    let difference = (to - from + 0.5).wrap() - 0.5;
+    from + difference*amount
 }
 
 struct WaveFormula {
@@ -61,7 +76,7 @@ impl ByteFloat for u8 {
 
     fn to_ifloat(&self) -> f32 {
         let adj = (-1.0).lerp(1.0, self.to_float());
-        let distance = (8.0 as f32 + 1.0).powf(adj.abs()).round() - 1.0;
+        let distance = (MAX_IFLOAT + 1.0).powf(adj.abs()).round() - 1.0;
         adj.signum()*distance
     }
 }
@@ -91,6 +106,23 @@ impl Formula for WaveFormula {
     fn get_value(&self, x: f32, y: f32) -> f32 {
         (self.x_factor*x + self.y_factor*y + self.wave_position).wave()*self.amplitude
     }
+
+    fn parameters(&self) -> [f32; FORMULA_GENE_SIZE] {
+        [self.amplitude, self.x_scale, self.y_scale, self.scale, self.wave_speed/MAX_IFLOAT]
+    }
+
+    fn lerp(&self, other: &WaveFormula, amount: f32) -> WaveFormula {
+        WaveFormula {
+            amplitude: self.amplitude.lerp(other.amplitude, amount),
+            x_scale: lerp_angle(self.x_scale, other.x_scale, amount),
+            y_scale: lerp_angle(self.y_scale, other.y_scale, amount),
+            scale: self.scale.lerp(other.scale, amount),
+            wave_speed: self.wave_speed.lerp(other.wave_speed, amount),
+            x_factor: 0.0,
+            y_factor: 0.0,
+            wave_position: 0.0
+        }
+    }
 }
 
 impl Formula for RotatingWaveFormula {
@@ -118,6 +150,24 @@ impl Formula for RotatingWaveFormula {
     fn get_value(&self, x: f32, y: f32) -> f32 {
         (self.x_factor*x + self.y_factor*y + self.wave_position).wave()*self.amplitude
     }
+
+    fn parameters(&self) -> [f32; FORMULA_GENE_SIZE] {
+        [self.amplitude, self.x_time/MAX_IFLOAT, self.y_time/MAX_IFLOAT, self.scale,
+         self.wave_speed/MAX_IFLOAT]
+    }
+
+    fn lerp(&self, other: &RotatingWaveFormula, amount: f32) -> RotatingWaveFormula {
+        RotatingWaveFormula {
+            amplitude: self.amplitude.lerp(other.amplitude, amount),
+            x_time: self.x_time.lerp(other.x_time, amount),
+            y_time: self.y_time.lerp(other.y_time, amount),
+            scale: self.scale.lerp(other.scale, amount),
+            wave_speed: self.wave_speed.lerp(other.wave_speed, amount),
+            x_factor: 0.0,
+            y_factor: 0.0,
+            wave_position: 0.0
+        }
+    }
 }
 
 impl Formula for CircularWaveFormula {
@@ -147,6 +197,24 @@ impl Formula for CircularWaveFormula {
         let dy = y - self.center_y;
         (self.scale*(dx*dx + dy*dy + 0.1).sqrt() + self.wave_position).wave()*self.amplitude
     }
+
+    fn parameters(&self) -> [f32; FORMULA_GENE_SIZE] {
+        [self.amplitude, self.x_time/MAX_IFLOAT, self.y_time/MAX_IFLOAT, self.scale,
+         self.wave_speed/MAX_IFLOAT]
+    }
+
+    fn lerp(&self, other: &CircularWaveFormula, amount: f32) -> CircularWaveFormula {
+        CircularWaveFormula {
+            amplitude: self.amplitude.lerp(other.amplitude, amount),
+            x_time: self.x_time.lerp(other.x_time, amount),
+            y_time: self.y_time.lerp(other.y_time, amount),
+            scale: self.scale.lerp(other.scale, amount),
+            wave_speed: self.wave_speed.lerp(other.wave_speed, amount),
+            center_x: 0.0,
+            center_y: 0.0,
+            wave_position: 0.0
+        }
+    }
 }
 
 impl PlasmaFormulas {
@@ -170,6 +238,20 @@ impl PlasmaFormulas {
             self.rotating_wave.get_value(x, y) +
             self.circular_wave.get_value(x, y)
     }
+
+    // Decoded parameters of each formula, scaled to [-1.0, 1.0], with amplitude first
+    pub fn parameters(&self) -> [[f32; FORMULA_GENE_SIZE]; NUM_FORMULA_GENES] {
+        [self.wave.parameters(), self.rotating_wave.parameters(), self.circular_wave.parameters()]
+    }
+
+    // Formulas partway between two others, by decoded parameters rather than by genes
+    pub fn lerp(&self, other: &PlasmaFormulas, amount: f32) -> PlasmaFormulas {
+        PlasmaFormulas {
+            wave: self.wave.lerp(&other.wave, amount),
+            rotating_wave: self.rotating_wave.lerp(&other.rotating_wave, amount),
+            circular_wave: self.circular_wave.lerp(&other.circular_wave, amount)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -178,6 +260,7 @@ mod tests {
     use genetics::Gene;
     use super::FORMULA_GENE_SIZE;
     use super::{ByteFloat, Formula, CircularWaveFormula, RotatingWaveFormula, WaveFormula};
+    use super::lerp_angle;
 
     #[test]
     fn test_bytefloat_float() {
@@ -185,6 +268,33 @@ mod tests {
         assert_eq!(255.to_float(), 1.0);
     }
 
+    #[test]
+    fn test_formula_parameters() {
+        let g = Gene { data: vec![255, 0, 127, 255, 0] };
+        assert_eq!(WaveFormula::from_gene(&g).parameters(), [1.0, 0.0, 127.0/255.0, 1.0, -1.0]);
+        assert_eq!(RotatingWaveFormula::from_gene(&g).parameters(), [1.0, -1.0, 0.0, 1.0, -1.0]);
+        assert_eq!(CircularWaveFormula::from_gene(&g).parameters(), [1.0, -1.0, 0.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn test_formula_lerp() {
+        let a = WaveFormula::from_gene(&Gene { data: vec![0, 0, 0, 0, 0] });
+        let b = WaveFormula::from_gene(&Gene { data: vec![255, 50, 100, 255, 255] });
+        let assert_parameters = |f: WaveFormula, expected: [f32; FORMULA_GENE_SIZE]| {
+            for (x, y) in f.parameters().iter().zip(expected.iter()) {
+                assert!((x - y).abs() < 0.001, "{:?} != {:?}", f.parameters(), expected);
+            }
+        };
+        assert_parameters(a.lerp(&b, 0.0), a.parameters());
+        assert_parameters(a.lerp(&b, 1.0), b.parameters());
+        assert_parameters(a.lerp(&b, 0.5), [0.5, 25.0/255.0, 50.0/255.0, 0.5, 0.0]);
+
+        // Angles take the short way around
+        assert!((lerp_angle(0.25, 0.5, 0.5) - 0.375).abs() < 0.001);
+        assert!((lerp_angle(0.9, 0.1, 0.5) - 1.0).abs() < 0.001);
+        assert!((lerp_angle(0.1, 0.9, 0.5) - 0.0).abs() < 0.001);
+    }
+
     #[test]
     fn test_bytefloat_ifloat() {
         assert_eq!(0.to_ifloat(), -8.0);
@@ -1,6 +1,8 @@
 extern crate rand;
 extern crate rustc_serialize;
 
+use color::colormapper::NUM_COLOR_GENES;
+use formulas::NUM_FORMULA_GENES;
 use std::collections::VecDeque;
 use self::rand::Rng;
 use self::rand::distributions::{Exp, IndependentSample, Normal};
@@ -22,6 +24,38 @@ use self::rustc_serialize::base64::{ToBase64, FromBase64, URL_SAFE};
 const MUTATION_RATE: f64 = 0.03;
 const MUTATION_STD_DEV: f64 = 32.0;
 
+// Ratings are on a 1-5 scale, where 1 is "barely tolerable" and 5 is "loved it"
+pub const MIN_RATING: u8 = 1;
+pub const MAX_RATING: u8 = 5;
+pub const DEFAULT_RATING: u8 = 3;
+
+// Parent selection picks the fittest of this many randomly chosen members
+const TOURNAMENT_SIZE: usize = 3;
+
+// The fittest members of a population are never evicted
+const ELITE_SIZE: usize = 4;
+
+// The most that a single pairwise preference can change a fitness by
+const PREFERENCE_STEP: f32 = 0.5;
+
+// Offspring this close to an existing member are considered duplicates (see DistanceMetric)
+const DUPLICATE_DISTANCE: f32 = 0.01;
+
+// How many times to try breeding an offspring that isn't a duplicate, before giving up
+const MAX_BREED_ATTEMPTS: usize = 10;
+
+// Evicted members at least this fit are remembered in the hall of fame, as future immigrants
+const HALL_OF_FAME_MIN_FITNESS: f32 = 4.0;
+const HALL_OF_FAME_SIZE: usize = 8;
+
+// In novelty mode, breed this many candidates and keep the one least like anything seen so far
+const NUM_NOVELTY_CANDIDATES: usize = 8;
+const MAX_ARCHIVE_SIZE: usize = 256;
+
+// How different two genomes look, from 0.0 (identical) to 1.0.
+// Since this module doesn't know what genes mean, the metric has to be provided from outside.
+pub type DistanceMetric = fn(&Genome, &Genome) -> f32;
+
 #[derive(Clone,Debug,Eq,PartialEq)]
 pub struct Gene {
     pub data: Vec<u8>
@@ -38,9 +72,50 @@ pub struct Genome {
     pub color: Chromosome
 }
 
+// Describes how a chromosome was bred: which parent each gene came from, and what mutated
+#[derive(Clone,Debug,PartialEq)]
+pub struct ChromosomeRecord {
+    pub from_first_parent: Vec<bool>, // One entry per gene
+    pub mutations: Vec<(usize, usize)> // (gene index, byte index) of each mutated byte
+}
+
+// Describes how a genome was bred, for tracing its lineage
+#[derive(Clone,Debug,PartialEq)]
+pub struct BreedRecord {
+    pub parents: (Genome, Genome),
+    pub pattern: ChromosomeRecord,
+    pub color: ChromosomeRecord
+}
+
+// Genes of one chromosome that breeding must leave alone
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct ChromosomeLocks {
+    pub whole: bool, // If set, every gene is locked
+    pub genes: Vec<usize>
+}
+
+// Parts of a genome that breeding must leave alone
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct GeneLocks {
+    pub pattern: ChromosomeLocks,
+    pub color: ChromosomeLocks
+}
+
+#[derive(Clone,Debug)]
+struct Member {
+    genome: Genome,
+    fitness: f32
+}
+
+#[derive(Clone)]
 pub struct Population {
-    genomes: VecDeque<Genome>,
-    max_size: usize
+    members: VecDeque<Member>, // Ordered from oldest to newest
+    max_size: usize,
+    distance_metric: Option<DistanceMetric>,
+    hall_of_fame: Vec<Member>, // Ordered from fittest to least fit
+    immigration_rate: f64,
+    novelty_search: bool,
+    archive: VecDeque<Genome> // Genomes that have been seen, ordered from oldest to newest
 }
 
 trait Mutate {
@@ -88,12 +163,19 @@ impl Gene {
         Gene { data: bytes.to_vec() }
     }
 
+    #[cfg(test)]
     fn mutating_clone(&self) -> Gene {
+        self.mutating_clone_recorded().0
+    }
+
+    // Like mutating_clone(), but also returns the positions of the mutated bytes
+    fn mutating_clone_recorded(&self) -> (Gene, Vec<usize>) {
         let mut rng = rand::thread_rng();
         let exp = Exp::new(MUTATION_RATE);
         let mut mutation_position = 0.0;
         // Start with a non-mutated version of self
         let mut gene = self.clone();
+        let mut mutations = vec![];
         loop {
             // Calculate distance to next mutation
             mutation_position += exp.ind_sample(&mut rng);
@@ -103,8 +185,13 @@ impl Gene {
             }
             // Replace one byte of the gene
             gene.data[index] = gene.data[index].mutate();
+            if mutations.last() != Some(&index) {
+                mutations.push(index);
+            }
         }
-        gene
+        // A byte mutated more than once can end up back where it started
+        mutations.retain(|&index| gene.data[index] != self.data[index]);
+        (gene, mutations)
     }
 }
 
@@ -155,30 +242,92 @@ impl Chromosome {
         Ok(Chromosome { genes: genes })
     }
 
+    #[cfg(test)]
     fn breed(&self, other: &Chromosome) -> Chromosome {
-        assert!(self.genes.len() == other.genes.len());
+        self.breed_recorded(other, &ChromosomeLocks::default()).0
+    }
+
+    // Locked genes are copied from self, without mutation.
+    //
+    // Genomes from older versions can have fewer genes. Genes that only one parent has are
+    // inherited from that parent, so the child has as many genes as the longer chromosome.
+    fn breed_recorded(&self, other: &Chromosome, locks: &ChromosomeLocks)
+                      -> (Chromosome, ChromosomeRecord) {
         let mut rng = rand::thread_rng();
         let mut child = Chromosome { genes: vec![] };
-        for i in 0..self.genes.len() {
-            let gene = if rng.gen() {
-                self.genes[i].mutating_clone()
+        let mut record = ChromosomeRecord { from_first_parent: vec![], mutations: vec![] };
+        for i in 0..self.genes.len().max(other.genes.len()) {
+            if locks.is_locked(i) && i < self.genes.len() {
+                child.genes.push(self.genes[i].clone());
+                record.from_first_parent.push(true);
+                continue;
+            }
+            let from_first_parent = if i >= other.genes.len() {
+                true
+            } else if i >= self.genes.len() {
+                false
+            } else {
+                rng.gen()
+            };
+            let (gene, mutations) = if from_first_parent {
+                self.genes[i].mutating_clone_recorded()
             } else {
-                other.genes[i].mutating_clone()
+                other.genes[i].mutating_clone_recorded()
             };
             child.genes.push(gene);
+            record.from_first_parent.push(from_first_parent);
+            record.mutations.extend(mutations.into_iter().map(|byte_index| (i, byte_index)));
         }
-        child
+        (child, record)
     }
 }
 
 impl Genome {
-    pub fn breed(&self, other: &Genome) -> Genome {
+    // A random genome with as many genes as a plasma's, for tests in any module
+    #[cfg(test)]
+    pub fn rand() -> Genome {
+        use color::colormapper::CONTROL_POINT_GENE_SIZE;
+        use formulas::FORMULA_GENE_SIZE;
         Genome {
-            pattern: self.pattern.breed(&other.pattern),
-            color: self.color.breed(&other.color)
+            pattern: Chromosome::rand(NUM_FORMULA_GENES, FORMULA_GENE_SIZE),
+            color: Chromosome::rand(NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE)
         }
     }
 
+    // A random genome with the same number and sizes of genes as self
+    pub fn rand_like(&self) -> Genome {
+        let rand_chromosome = |c: &Chromosome| Chromosome {
+            genes: c.genes.iter().map(|g| Gene::rand(g.data.len())).collect()
+        };
+        Genome {
+            pattern: rand_chromosome(&self.pattern),
+            color: rand_chromosome(&self.color)
+        }
+    }
+
+    #[cfg(test)]
+    pub fn breed(&self, other: &Genome) -> Genome {
+        self.breed_recorded(other).0
+    }
+
+    // Like breed(), but also describes how the child was put together
+    pub fn breed_recorded(&self, other: &Genome) -> (Genome, BreedRecord) {
+        self.breed_locked(other, &GeneLocks::default())
+    }
+
+    // Like breed_recorded(), but the child gets self's locked genes exactly as they are
+    pub fn breed_locked(&self, other: &Genome, locks: &GeneLocks) -> (Genome, BreedRecord) {
+        let (pattern, pattern_record) = self.pattern.breed_recorded(&other.pattern, &locks.pattern);
+        let (color, color_record) = self.color.breed_recorded(&other.color, &locks.color);
+        let child = Genome { pattern: pattern, color: color };
+        let record = BreedRecord {
+            parents: (self.clone(), other.clone()),
+            pattern: pattern_record,
+            color: color_record
+        };
+        (child, record)
+    }
+
     fn to_bytes(&self) -> Vec<u8> {
         let mut result = self.pattern.to_bytes();
         result.append(&mut self.color.to_bytes());
@@ -209,41 +358,405 @@ impl Genome {
     }
 }
 
+impl ChromosomeRecord {
+    // Whether every byte of child came from the parent named in the record, except for the
+    // bytes that the record says were mutated
+    #[cfg(test)]
+    pub fn replays(&self, a: &Chromosome, b: &Chromosome, child: &Chromosome) -> bool {
+        self.from_first_parent.iter().enumerate().all(|(i, &from_a)| {
+            let parent = if from_a { a } else { b };
+            child.genes[i].data.iter().enumerate().all(|(j, &byte)|
+                self.mutations.contains(&(i, j)) || byte == parent.genes[i].data[j]
+            )
+        })
+    }
+}
+
+impl BreedRecord {
+    // Whether child is what the record's parents would have made, according to the record
+    #[cfg(test)]
+    pub fn replays(&self, child: &Genome) -> bool {
+        let (ref a, ref b) = self.parents;
+        self.pattern.replays(&a.pattern, &b.pattern, &child.pattern) &&
+            self.color.replays(&a.color, &b.color, &child.color)
+    }
+}
+
+impl ChromosomeLocks {
+    pub fn is_locked(&self, gene_index: usize) -> bool {
+        self.whole || self.genes.contains(&gene_index)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.whole && self.genes.is_empty()
+    }
+
+    pub fn toggle_gene(&mut self, gene_index: usize) {
+        if let Some(i) = self.genes.iter().position(|&g| g == gene_index) {
+            self.genes.remove(i);
+        } else {
+            self.genes.push(gene_index);
+            self.genes.sort();
+        }
+    }
+}
+
+impl GeneLocks {
+    pub fn is_empty(&self) -> bool {
+        self.pattern.is_empty() && self.color.is_empty()
+    }
+
+    // Adds locks from a spec like "pattern" (a whole chromosome) or "color:0,3" (single genes)
+    pub fn add_spec(&mut self, spec: &str) -> Result<(), String> {
+        let mut parts = spec.splitn(2, ':');
+        let name = parts.next().unwrap().trim();
+        let (locks, num_genes) = match name {
+            "pattern" => (&mut self.pattern, NUM_FORMULA_GENES),
+            "color" => (&mut self.color, NUM_COLOR_GENES),
+            _ => return Err(format!("Lock must start with \"pattern\" or \"color\": {}", spec))
+        };
+        match parts.next() {
+            None => locks.whole = true,
+            Some(gene_list) => for index_str in gene_list.split(',') {
+                match index_str.trim().parse() {
+                    Ok(i) if i >= num_genes => return Err(format!(
+                        "{} genes are numbered from 0 to {}: {}", name, num_genes - 1, index_str
+                    )),
+                    Ok(i) if !locks.genes.contains(&i) => locks.genes.push(i),
+                    Ok(_) => (),
+                    Err(_) => return Err(format!("Not a gene number: {}", index_str))
+                }
+            }
+        }
+        locks.genes.sort();
+        Ok(())
+    }
+
+    // Describes the locks in the same format that add_spec() accepts
+    pub fn describe(&self) -> String {
+        let mut specs = vec![];
+        for &(name, locks) in [("pattern", &self.pattern), ("color", &self.color)].iter() {
+            if locks.whole {
+                specs.push(name.to_string());
+            } else if !locks.genes.is_empty() {
+                let genes: Vec<_> = locks.genes.iter().map(|g| g.to_string()).collect();
+                specs.push(format!("{}:{}", name, genes.join(",")));
+            }
+        }
+        specs.join(" ")
+    }
+}
+
 impl Population {
     pub fn new(max_size: usize) -> Population {
         Population {
-            genomes: VecDeque::with_capacity(max_size),
-            max_size: max_size
+            members: VecDeque::with_capacity(max_size),
+            max_size: max_size,
+            distance_metric: None,
+            hall_of_fame: vec![],
+            immigration_rate: 0.0,
+            novelty_search: false,
+            archive: VecDeque::new()
         }
     }
 
+    // The probability that one of the parents is an immigrant from outside the population:
+    // either a random genome, or a fit genome that was evicted earlier
+    pub fn set_immigration_rate(&mut self, rate: f64) {
+        self.immigration_rate = rate.max(0.0).min(1.0);
+    }
+
+    // In novelty mode, offspring are chosen to be unlike anything seen so far (see remember()).
+    // This needs a distance metric.
+    pub fn set_novelty_search(&mut self, enabled: bool) {
+        self.novelty_search = enabled;
+    }
+
+    pub fn novelty_search(&self) -> bool {
+        self.novelty_search
+    }
+
+    // Makes a note of a genome that was shown to the user, e.g., so that novelty mode can avoid it
+    pub fn remember(&mut self, genome: Genome) {
+        if !self.archive.contains(&genome) {
+            self.archive.push_back(genome);
+            if self.archive.len() > MAX_ARCHIVE_SIZE {
+                self.archive.pop_front();
+            }
+        }
+    }
+
+    // With a distance metric, parents are chosen to be dissimilar, and near-duplicate
+    // offspring are thrown away
+    pub fn set_distance_metric(&mut self, metric: DistanceMetric) {
+        self.distance_metric = Some(metric);
+    }
+
+    // Average distance between pairs of members, or None without a distance metric.
+    // A population of clones has a diversity of 0.0.
+    pub fn diversity(&self) -> Option<f32> {
+        let metric = match self.distance_metric {
+            Some(m) => m,
+            None => return None
+        };
+        let mut total = 0.0;
+        let mut num_pairs = 0;
+        for i in 0..self.members.len() {
+            for j in (i + 1)..self.members.len() {
+                total += metric(&self.members[i].genome, &self.members[j].genome);
+                num_pairs += 1;
+            }
+        }
+        Some(if num_pairs > 0 { total/num_pairs as f32 } else { 0.0 })
+    }
+
+    fn is_duplicate(&self, genome: &Genome) -> bool {
+        match self.distance_metric {
+            Some(metric) => self.members.iter().any(|m|
+                metric(&m.genome, genome) < DUPLICATE_DISTANCE
+            ),
+            None => false
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    // Adds a genome with the default rating, or keeps its current rating if that's higher
     pub fn add(&mut self, genome: Genome) {
-        self.genomes.push_back(genome);
-        if self.genomes.len() > self.max_size {
-            self.genomes.pop_front();
+        let fitness = self.fitness(&genome).map_or(DEFAULT_RATING as f32, |f|
+            f.max(DEFAULT_RATING as f32)
+        );
+        self.add_with_fitness(genome, fitness);
+    }
+
+    // Adds a genome with a 1-5 rating. Re-adding an existing genome replaces its rating.
+    pub fn add_rated(&mut self, genome: Genome, rating: u8) {
+        self.add_with_fitness(genome, rating as f32);
+    }
+
+    // Like add_rated(), but with a fractional rating, e.g., one restored from a saved session
+    pub fn add_with_fitness(&mut self, genome: Genome, fitness: f32) {
+        let fitness = fitness.max(MIN_RATING as f32).min(MAX_RATING as f32);
+        if let Some(index) = self.position(&genome) {
+            self.members.remove(index);
+        }
+        self.members.push_back(Member { genome: genome, fitness: fitness });
+        if self.members.len() > self.max_size {
+            self.evict();
+        }
+    }
+
+    pub fn fitness(&self, genome: &Genome) -> Option<f32> {
+        self.position(genome).map(|i| self.members[i].fitness)
+    }
+
+    // Nudges the fitness of a member, keeping it within the rating scale.
+    // Used for implicit feedback, like when a member's offspring gets rejected.
+    pub fn adjust_fitness(&mut self, genome: &Genome, delta: f32) {
+        if let Some(index) = self.position(genome) {
+            let member = &mut self.members[index];
+            member.fitness = (member.fitness + delta).max(MIN_RATING as f32).min(MAX_RATING as f32);
+        }
+    }
+
+    // Records that winner was preferred over loser in a side-by-side comparison, adding winner
+    // to the population if necessary. This is an Elo-style update: an upset, where the less fit
+    // genome wins, changes fitness more than an expected result.
+    pub fn add_preference(&mut self, winner: Genome, loser: &Genome) {
+        let winner_fitness = self.fitness(&winner).unwrap_or(DEFAULT_RATING as f32);
+        let loser_fitness = self.fitness(loser).unwrap_or(DEFAULT_RATING as f32);
+        let expected_win = 1.0/(1.0 + (loser_fitness - winner_fitness).exp());
+        let delta = PREFERENCE_STEP*(1.0 - expected_win);
+        self.add_with_fitness(winner, winner_fitness + delta);
+        self.adjust_fitness(loser, -delta);
+    }
+
+    // Iterates over members and their fitnesses, from oldest to newest
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(&'a Genome, f32)> + 'a {
+        self.members.iter().map(|m| (&m.genome, m.fitness))
+    }
+
+    // Genomes in the hall of fame, with their fitness, from fittest to least fit
+    pub fn hall_of_fame<'a>(&'a self) -> impl Iterator<Item=(&'a Genome, f32)> + 'a {
+        self.hall_of_fame.iter().map(|m| (&m.genome, m.fitness))
+    }
+
+    // Genomes passed to remember(), from oldest to newest
+    pub fn remembered<'a>(&'a self) -> impl Iterator<Item=&'a Genome> + 'a {
+        self.archive.iter()
+    }
+
+    fn position(&self, genome: &Genome) -> Option<usize> {
+        self.members.iter().position(|m| m.genome == *genome)
+    }
+
+    // Removes the least fit member, sparing the elite. Ties go to the oldest member.
+    fn evict(&mut self) {
+        let mut ranked: Vec<usize> = (0..self.members.len()).collect();
+        ranked.sort_by(|&a, &b|
+            self.members[b].fitness.partial_cmp(&self.members[a].fitness).unwrap().then(b.cmp(&a))
+        );
+        let num_elite = ELITE_SIZE.min(self.members.len() - 1);
+        if let Some(&index) = ranked[num_elite..].last() {
+            let evicted = self.members.remove(index).unwrap();
+            if evicted.fitness >= HALL_OF_FAME_MIN_FITNESS {
+                self.add_to_hall_of_fame(evicted.genome, evicted.fitness);
+            }
+        }
+    }
+
+    // Keeps a well-liked genome around as a potential immigrant, e.g., one restored from a saved
+    // session. Normally, members go in here when they're evicted.
+    pub fn add_to_hall_of_fame(&mut self, genome: Genome, fitness: f32) {
+        let member = Member { genome: genome, fitness: fitness };
+        self.hall_of_fame.retain(|m| m.genome != member.genome);
+        let index = self.hall_of_fame.iter().position(|m| m.fitness < member.fitness);
+        self.hall_of_fame.insert(index.unwrap_or(self.hall_of_fame.len()), member);
+        self.hall_of_fame.truncate(HALL_OF_FAME_SIZE);
+    }
+
+    // A genome from outside the population: from the hall of fame half the time, if it has
+    // anything in it, and otherwise random
+    fn immigrant<R: Rng>(&self, rng: &mut R) -> Genome {
+        if !self.hall_of_fame.is_empty() && rng.gen() {
+            let index = rng.gen_range(0, self.hall_of_fame.len());
+            self.hall_of_fame[index].genome.clone()
+        } else {
+            self.members[0].genome.rand_like()
+        }
+    }
+
+    // Tournament selection: the highest scoring of a few randomly chosen members wins
+    fn tournament<R: Rng, F: Fn(usize) -> f32>(&self, rng: &mut R, excluded: Option<usize>,
+                                                score: F) -> usize {
+        let num_candidates = self.members.len() - if excluded.is_some() { 1 } else { 0 };
+        let mut winner = None;
+        for _ in 0..TOURNAMENT_SIZE {
+            let raw_index = rng.gen_range(0, num_candidates);
+            let index = match excluded {
+                Some(e) if raw_index >= e => raw_index + 1,
+                _ => raw_index
+            };
+            let index_score = score(index);
+            winner = match winner {
+                Some((w, w_score)) if w_score >= index_score => Some((w, w_score)),
+                _ => Some((index, index_score))
+            };
         }
+        winner.unwrap().0
     }
 
     pub fn get_pair(&self) -> Option<(&Genome, &Genome)> {
-        let num_genomes = self.genomes.len();
-        if num_genomes == 0 {
+        if self.is_empty() {
             None
-        } else if num_genomes == 1 {
+        } else if self.len() == 1 {
             // Only one genome: return it twice
-            Some((self.genomes.get(0).unwrap(), self.genomes.get(0).unwrap()))
+            let genome = &self.members[0].genome;
+            Some((genome, genome))
         } else {
-            // Pick two different genomes
+            // Pick two different genomes, favoring the fittest.
+            // If we can tell how different genomes are, the second one should also be unlike
+            // the first, so that the population doesn't collapse into a bunch of clones.
             let mut rng = rand::thread_rng();
-            let index1 = rng.gen_range(0, num_genomes);
-            let index2_raw = rng.gen_range(0, num_genomes - 1);
-            let index2 = if index2_raw >= index1 { index2_raw + 1 } else { index2_raw };
-            Some((self.genomes.get(index1).unwrap(), self.genomes.get(index2).unwrap()))
+            let index1 = self.tournament(&mut rng, None, |i| self.members[i].fitness);
+            let genome1 = &self.members[index1].genome;
+            let index2 = self.tournament(&mut rng, Some(index1), |i|
+                self.partner_score(genome1, i)
+            );
+            Some((genome1, &self.members[index2].genome))
         }
     }
 
+    // How strongly a member is favored as a second parent: fitter ones, and with a distance
+    // metric, ones unlike the first parent
+    fn partner_score(&self, first: &Genome, index: usize) -> f32 {
+        let member = &self.members[index];
+        let novelty = self.distance_metric.map_or(0.0, |m| m(first, &member.genome));
+        member.fitness/MAX_RATING as f32 + novelty
+    }
+
     pub fn breed(&self) -> Genome {
+        self.breed_recorded().0
+    }
+
+    // Like get_pair(), but the second parent is sometimes an immigrant
+    fn get_parents(&self) -> (Genome, Genome) {
         let (a, b) = self.get_pair().expect("Couldn't get breeding pair");
-        a.breed(&b)
+        let mut rng = rand::thread_rng();
+        if rng.gen_range(0.0, 1.0) < self.immigration_rate {
+            (a.clone(), self.immigrant(&mut rng))
+        } else {
+            (a.clone(), b.clone())
+        }
+    }
+
+    // Like breed(), but also returns a BreedRecord, which says who the parents were
+    pub fn breed_recorded(&self) -> (Genome, BreedRecord) {
+        self.breed_offspring(|| {
+            let (a, b) = self.get_parents();
+            a.breed_recorded(&b)
+        })
+    }
+
+    // Breeds keeper with a member of the population, passing keeper's locked genes down as-is.
+    // Without any locks, this is the same as breed_recorded().
+    pub fn breed_locked(&self, keeper: &Genome, locks: &GeneLocks) -> (Genome, BreedRecord) {
+        if locks.is_empty() {
+            return self.breed_recorded();
+        }
+        self.breed_offspring(|| {
+            let (a, b) = self.get_parents();
+            let partner = if a == *keeper { b } else { a };
+            keeper.breed_locked(&partner, locks)
+        })
+    }
+
+    fn breed_offspring<F: Fn() -> (Genome, BreedRecord)>(&self, breed: F) -> (Genome, BreedRecord) {
+        match self.distance_metric {
+            Some(metric) if self.novelty_search => self.breed_novel(metric, breed),
+            _ => self.breed_distinct(breed)
+        }
+    }
+
+    // Breeds a few candidates, and keeps the one that's farthest from its nearest neighbor
+    // among the members and the genomes seen so far
+    fn breed_novel<F>(&self, metric: DistanceMetric, breed: F) -> (Genome, BreedRecord)
+        where F: Fn() -> (Genome, BreedRecord) {
+        let novelty = |genome: &Genome| {
+            self.members.iter().map(|m| &m.genome).chain(self.archive.iter()).map(|g|
+                metric(g, genome)
+            ).fold(::std::f32::INFINITY, f32::min)
+        };
+        let mut best = breed();
+        let mut best_novelty = novelty(&best.0);
+        for _ in 1..NUM_NOVELTY_CANDIDATES {
+            let candidate = breed();
+            let candidate_novelty = novelty(&candidate.0);
+            if candidate_novelty > best_novelty {
+                best = candidate;
+                best_novelty = candidate_novelty;
+            }
+        }
+        best
+    }
+
+    // Keeps breeding until the offspring isn't a near-duplicate of an existing member.
+    // If the population is so uniform that this keeps failing, settle for a duplicate.
+    fn breed_distinct<F: Fn() -> (Genome, BreedRecord)>(&self, breed: F) -> (Genome, BreedRecord) {
+        let mut offspring = breed();
+        for _ in 1..MAX_BREED_ATTEMPTS {
+            if !self.is_duplicate(&offspring.0) {
+                break;
+            }
+            offspring = breed();
+        }
+        offspring
     }
 }
 
@@ -253,7 +766,9 @@ mod tests {
     use super::Gene;
     use super::Genome;
     use super::Chromosome;
+    use super::{ChromosomeLocks, GeneLocks};
     use super::Population;
+    use super::{DEFAULT_RATING, ELITE_SIZE, MAX_RATING, MIN_RATING};
     use super::MUTATION_RATE;
     use super::MUTATION_STD_DEV;
     use genetics::rustc_serialize::base64::{ToBase64, URL_SAFE};
@@ -343,6 +858,16 @@ mod tests {
         assert!(num_mutations < upper);
     }
 
+    #[test]
+    fn test_gene_mutating_clone_recorded() {
+        let g1 = Gene::rand(5000);
+        let (g2, mutations) = g1.mutating_clone_recorded();
+        assert_eq!(mutations.len(), g1.hamming(&g2));
+        for i in mutations {
+            assert!(g1.data[i] != g2.data[i]);
+        }
+    }
+
     #[test]
     fn test_chromosome_rand() {
         let num_genes = 8;
@@ -370,6 +895,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_chromosome_breed_unequal_lengths() {
+        // Extra genes come from whichever parent has them
+        let short = Chromosome::rand(2, 4);
+        let long = Chromosome::rand(3, 4);
+        for &(a, b) in [(&short, &long), (&long, &short)].iter() {
+            let (c, record) = a.breed_recorded(b, &ChromosomeLocks::default());
+            assert_eq!(c.genes.len(), 3);
+            assert_eq!(record.from_first_parent[2], a.genes.len() == 3);
+            assert!(record.replays(a, b, &c));
+        }
+    }
+
     #[test]
     fn test_genome_breed() {
         let a = Genome {
@@ -385,6 +923,220 @@ mod tests {
         assert!(c.pattern.genes.len() == 3);
     }
 
+    #[test]
+    fn test_genome_breed_recorded() {
+        let a = Genome::rand();
+        let b = Genome::rand();
+        let (c, record) = a.breed_recorded(&b);
+        assert_eq!(record.parents, (a.clone(), b.clone()));
+        assert!(record.replays(&c));
+    }
+
+    #[test]
+    fn test_genome_breed_locked() {
+        let a = Genome::rand();
+        let b = Genome::rand();
+        let mut locks = GeneLocks::default();
+        locks.add_spec("color").unwrap();
+        locks.add_spec("pattern:0,2").unwrap();
+        for _ in 0..100 {
+            let (c, record) = a.breed_locked(&b, &locks);
+            assert_eq!(c.color, a.color);
+            assert_eq!(c.pattern.genes[0], a.pattern.genes[0]);
+            assert_eq!(c.pattern.genes[2], a.pattern.genes[2]);
+            assert!(record.color.mutations.is_empty());
+            assert!(record.pattern.mutations.iter().all(|&(gene, _)| gene != 0 && gene != 2));
+            assert!(record.replays(&c));
+        }
+    }
+
+    #[test]
+    fn test_gene_locks_spec() {
+        let mut locks = GeneLocks::default();
+        assert!(locks.is_empty());
+        locks.add_spec("pattern").unwrap();
+        locks.add_spec("color:5, 2").unwrap();
+        locks.add_spec("color:2").unwrap();
+        assert_eq!(locks.pattern, ChromosomeLocks { whole: true, genes: vec![] });
+        assert_eq!(locks.color, ChromosomeLocks { whole: false, genes: vec![2, 5] });
+        assert_eq!(locks.describe(), "pattern color:2,5");
+        assert!(locks.color.is_locked(5) && !locks.color.is_locked(4));
+
+        locks.color.toggle_gene(2);
+        locks.color.toggle_gene(0);
+        assert_eq!(locks.color.genes, vec![0, 5]);
+
+        assert!(GeneLocks::default().add_spec("colour").is_err());
+        assert!(GeneLocks::default().add_spec("color:x").is_err());
+
+        // Genes past the end of the chromosome can't be locked
+        assert!(GeneLocks::default().add_spec("color:8").is_ok());
+        assert!(GeneLocks::default().add_spec("color:9").is_err());
+        assert_eq!(GeneLocks::default().add_spec("pattern:1,99"),
+                   Err("pattern genes are numbered from 0 to 2: 99".to_string()));
+    }
+
+    // A toy metric for testing: the fraction of bytes that differ
+    fn byte_distance(a: &Genome, b: &Genome) -> f32 {
+        let (a_bytes, b_bytes) = (a.to_bytes(), b.to_bytes());
+        let num_different = a_bytes.iter().zip(b_bytes.iter()).filter(|&(x, y)| x != y).count();
+        num_different as f32/a_bytes.len() as f32
+    }
+
+    #[test]
+    fn test_population_diversity() {
+        let mut p = Population::new(4);
+        assert_eq!(p.diversity(), None);
+        p.set_distance_metric(byte_distance);
+        assert_eq!(p.diversity(), Some(0.0));
+
+        let g = Genome::rand();
+        let mut h = g.clone();
+        h.color.genes[0] = Gene { data: h.color.genes[0].data.iter().map(|b| !b).collect() };
+        p.add(g.clone());
+        p.add(h.clone());
+        assert_eq!(p.diversity(), Some(byte_distance(&g, &h)));
+        assert!(p.diversity().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_population_prefers_dissimilar_parents() {
+        // A population of near-clones, plus one genome that's very different
+        let mut p = Population::new(8);
+        let g = Genome::rand();
+        for i in 0..7 {
+            let mut clone = g.clone();
+            clone.pattern.genes[0].data[0] = i;
+            p.add(clone);
+        }
+        let outsider = Genome::rand();
+        p.add(outsider.clone());
+
+        // Without a metric, every member is as good a partner as any other. With one, the
+        // outsider beats all of the clones.
+        let first = g.clone();
+        let scores = |p: &Population| (0..p.len()).map(|i| p.partner_score(&first, i)).collect();
+        let uniform_scores: Vec<f32> = scores(&p);
+        assert!(uniform_scores.iter().all(|&score| score == uniform_scores[0]));
+        p.set_distance_metric(byte_distance);
+        let diverse_scores: Vec<f32> = scores(&p);
+        let outsider_index = p.position(&outsider).unwrap();
+        for (i, &score) in diverse_scores.iter().enumerate() {
+            if i != outsider_index {
+                assert!(diverse_scores[outsider_index] > score);
+            }
+        }
+    }
+
+    #[test]
+    fn test_population_avoids_duplicates() {
+        // Breeding two identical parents mostly produces duplicates, so with a metric,
+        // breeding should retry until it gets one of the rare mutants
+        let mut p = Population::new(2);
+        let g = Genome::rand();
+        p.add(g.clone());
+        p.set_distance_metric(|a, b| if a == b { 0.0 } else { 1.0 });
+        let num_duplicates = (0..100).filter(|_| p.breed() == g).count();
+        assert!(num_duplicates < 50);
+    }
+
+    #[test]
+    fn test_population_add_preference() {
+        let mut p = Population::new(4);
+        let (a, b, c) = (Genome::rand(), Genome::rand(), Genome::rand());
+        p.add(a.clone());
+        p.add(b.clone());
+
+        // Evenly matched: the winner gains what the loser loses
+        p.add_preference(a.clone(), &b);
+        let gain = p.fitness(&a).unwrap() - DEFAULT_RATING as f32;
+        assert!(gain > 0.0);
+        assert!((p.fitness(&b).unwrap() - (DEFAULT_RATING as f32 - gain)).abs() < 1e-6);
+
+        // An upset changes things more than an expected result
+        p.add_preference(b.clone(), &a);
+        let upset_gain = p.fitness(&b).unwrap() - (DEFAULT_RATING as f32 - gain);
+        assert!(upset_gain > gain);
+
+        // Winners that weren't in the population are added to it
+        p.add_preference(c.clone(), &a);
+        assert!(p.fitness(&c).unwrap() > DEFAULT_RATING as f32);
+    }
+
+    #[test]
+    fn test_population_immigration() {
+        let g = Genome::rand();
+        let mut p = Population::new(1);
+        p.add(g.clone());
+
+        // Without immigration, a lone genome can only breed with itself
+        let (_, record) = p.breed_recorded();
+        assert_eq!(record.parents, (g.clone(), g.clone()));
+
+        // With nothing in the hall of fame, immigrants are random
+        p.set_immigration_rate(1.0);
+        let (_, record) = p.breed_recorded();
+        assert_eq!(record.parents.0, g);
+        assert!(record.parents.1 != g);
+        assert_eq!(record.parents.1.to_bytes().len(), g.to_bytes().len());
+    }
+
+    #[test]
+    fn test_population_hall_of_fame() {
+        let mut p = Population::new(ELITE_SIZE + 1);
+        let favorite = Genome::rand();
+        let forgettable = Genome::rand();
+        for _ in 0..ELITE_SIZE {
+            p.add_rated(Genome::rand(), MAX_RATING);
+        }
+        p.add_rated(favorite.clone(), 4);
+        p.add_rated(forgettable.clone(), 4); // Evicts favorite, since it's older
+        p.adjust_fitness(&forgettable, -2.0);
+        p.add_rated(Genome::rand(), MAX_RATING); // Evicts forgettable, which isn't fit enough
+        assert_eq!(p.fitness(&favorite), None);
+        assert_eq!(p.fitness(&forgettable), None);
+        assert_eq!(p.hall_of_fame.len(), 1);
+        assert_eq!(p.hall_of_fame[0].genome, favorite);
+
+        // Immigrants come from the hall of fame about half the time
+        p.set_immigration_rate(1.0);
+        let num_favorites = (0..200).filter(|_| p.breed_recorded().1.parents.1 == favorite).count();
+        assert!(num_favorites > 50 && num_favorites < 150);
+    }
+
+    #[test]
+    fn test_population_novelty_search() {
+        // Breeding a genome with itself only changes it by mutation. Novelty search should
+        // favor the offspring with the most mutations.
+        let g = Genome::rand();
+        let mut p = Population::new(1);
+        p.add(g.clone());
+        p.set_distance_metric(byte_distance);
+        p.remember(g.clone());
+        let mean_distance = |p: &Population| {
+            (0..100).map(|_| byte_distance(&g, &p.breed())).sum::<f32>()/100.0
+        };
+        let plain_distance = mean_distance(&p);
+        p.set_novelty_search(true);
+        let novel_distance = mean_distance(&p);
+        assert!(novel_distance > plain_distance);
+    }
+
+    #[test]
+    fn test_population_breed_locked() {
+        let mut p = Population::new(4);
+        for _ in 0..4 {
+            p.add(Genome::rand());
+        }
+        let keeper = Genome::rand();
+        let mut locks = GeneLocks::default();
+        locks.add_spec("pattern").unwrap();
+        let (child, record) = p.breed_locked(&keeper, &locks);
+        assert_eq!(child.pattern, keeper.pattern);
+        assert_eq!(record.parents.0, keeper);
+        assert!(p.fitness(&record.parents.1).is_some());
+    }
+
     #[test]
     fn test_genome_to_base64() {
         let g = Genome {
@@ -465,4 +1217,86 @@ mod tests {
             assert!(*g1 != g && *g2 != g); // Make sure original genomes were flushed out
         }
     }
+
+    #[test]
+    fn test_population_add_rated() {
+        let mut p = Population::new(5);
+        assert!(p.is_empty());
+        let g = Genome::rand();
+        p.add(g.clone());
+        assert!(!p.is_empty());
+        assert_eq!(p.fitness(&g), Some(DEFAULT_RATING as f32));
+
+        // Re-adding a genome updates its rating instead of duplicating it
+        p.add_rated(g.clone(), MAX_RATING);
+        assert_eq!(p.len(), 1);
+        assert_eq!(p.fitness(&g), Some(MAX_RATING as f32));
+
+        // Approving it again doesn't lower its rating, but does raise a low one
+        p.add(g.clone());
+        assert_eq!(p.fitness(&g), Some(MAX_RATING as f32));
+        p.add_rated(g.clone(), MIN_RATING);
+        p.add(g.clone());
+        assert_eq!(p.fitness(&g), Some(DEFAULT_RATING as f32));
+
+        // Ratings and adjustments are kept within the rating scale
+        p.add_rated(g.clone(), 0);
+        assert_eq!(p.fitness(&g), Some(MIN_RATING as f32));
+        p.adjust_fitness(&g, -1.0);
+        assert_eq!(p.fitness(&g), Some(MIN_RATING as f32));
+        p.adjust_fitness(&g, 0.5);
+        assert_eq!(p.fitness(&g), Some(MIN_RATING as f32 + 0.5));
+        assert_eq!(p.fitness(&Genome::rand()), None);
+    }
+
+    #[test]
+    fn test_population_evicts_least_fit() {
+        let mut p = Population::new(6);
+        let loved = Genome::rand();
+        let disliked = Genome::rand();
+        p.add_rated(loved.clone(), MAX_RATING);
+        p.add_rated(disliked.clone(), MIN_RATING);
+        for _ in 0..4 {
+            p.add(Genome::rand());
+        }
+
+        // The disliked genome goes first, even though it isn't the oldest
+        p.add(Genome::rand());
+        assert_eq!(p.len(), 6);
+        assert_eq!(p.fitness(&disliked), None);
+
+        // The loved genome is elite, and survives no matter how old it gets
+        for _ in 0..20 {
+            p.add(Genome::rand());
+        }
+        assert_eq!(p.fitness(&loved), Some(MAX_RATING as f32));
+
+        // A newcomer that is less fit than everyone else doesn't stick around
+        let newcomer = Genome::rand();
+        p.add_rated(newcomer.clone(), MIN_RATING);
+        assert_eq!(p.fitness(&newcomer), None);
+    }
+
+    #[test]
+    fn test_population_selection_pressure() {
+        let mut p = Population::new(8);
+        let best = Genome::rand();
+        p.add_rated(best.clone(), MAX_RATING);
+        for _ in 0..7 {
+            p.add_rated(Genome::rand(), MIN_RATING);
+        }
+
+        // With uniform selection, best would be picked about 2/8 of the time.
+        // With tournaments of 3, it should be picked well over half the time.
+        let num_pairs = 1000;
+        let mut num_picked = 0;
+        for _ in 0..num_pairs {
+            let (g1, g2) = p.get_pair().unwrap();
+            assert!(*g1 != *g2);
+            if *g1 == best || *g2 == best {
+                num_picked += 1;
+            }
+        }
+        assert!(num_picked > num_pairs/2);
+    }
 }
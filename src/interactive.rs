@@ -2,53 +2,378 @@ use asyncrenderer::AsyncRenderer;
 use color::colormapper::{NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE};
 use fastmath::FastMath;
 use formulas::{NUM_FORMULA_GENES, FORMULA_GENE_SIZE};
-use genetics::{Chromosome, Genome, Population};
+use genetics::{BreedRecord, Chromosome, GeneLocks, Genome, Population, DEFAULT_RATING, MAX_RATING};
+use lineage::{Lineage, Node};
+use preference::PreferenceModel;
 use sdl2;
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::render::Texture;
-use settings::PlasmaSettings;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use session::{Judgment, Session, Verdict};
+use settings::{Layout, PlasmaSettings, RenderingSettings};
 use std::f32;
+use std::io::Write;
 use std::time::Instant;
+use undo::UndoStack;
+
+// How much a parent's fitness changes when its offspring is approved or rejected
+const IMPLICIT_FEEDBACK: f32 = 0.25;
+
+// How often to save the session, if there have been any changes
+const AUTOSAVE_INTERVAL_SECONDS: u64 = 60;
+
+// How many approvals, rejections, etc. can be undone
+const MAX_UNDO_DEPTH: usize = 100;
+
+// A genome on screen, waiting to be judged
+#[derive(Clone)]
+struct Candidate {
+    genome: Genome,
+    record: Option<BreedRecord>
+}
+
+// A part of the window that shows one candidate. Every tile renders on its own thread.
+struct Tile<'a> {
+    candidate: Candidate,
+    frame_deadline_seconds: f64,
+    renderer: AsyncRenderer,
+    texture: Texture<'a>
+}
+
+// Everything that an approval, rejection or randomization can change, so that it can be undone.
+// The history and lineage only ever grow at the end, so we just remember how long they were.
+struct Snapshot {
+    candidates: Vec<Candidate>,
+    keeper: Genome,
+    population: Population,
+    num_judgments: usize,
+    num_lineage_nodes: usize
+}
 
 struct PlasmaState<'a> {
     clock_instant: Instant,
-    current_texture: Texture<'a>,
-    current_genome: Genome,
-    frame_deadline_seconds: f64,
+    diversity: Option<f32>, // Of the population, cached since it's slow to compute
+    history: Vec<Judgment>,
+    keeper: Genome, // Passes its locked genes down to new candidates
+    layout: Layout,
+    lineage: Lineage,
+    lineage_path: Option<String>,
+    locks: GeneLocks,
     population: Population,
-    renderer: AsyncRenderer,
+    preferences: PreferenceModel,
+    rendering_settings: RenderingSettings,
+    session_path: Option<String>,
+    session_changed: bool,
+    session_save_instant: Instant,
+    tiles: Vec<Tile<'a>>,
+    undo_stack: UndoStack<Snapshot>,
+    undone_judgments: Vec<Judgment>, // Removed from history by undo, put back by redo
+    undone_lineage_nodes: Vec<Node>, // Removed from lineage by undo, put back by redo
     width: u32,
     height: u32
 }
 
 impl<'a> PlasmaState<'a> {
+    // Only applies when a single plasma is on screen, since it's ambiguous otherwise
     fn approve_current_genome(&mut self) {
-        let old_genome = self.current_genome.clone();
-        self.population.add(old_genome);
-        let new_genome = self.population.breed();
-        self.set_genome(new_genome);
+        if self.tiles.len() == 1 {
+            self.rate_candidate(0, None);
+        }
+    }
+
+    // Adds a candidate to the population, and replaces all candidates with its offspring.
+    // Without a rating, it's a plain approval, which doesn't lower a rating it already has.
+    fn rate_candidate(&mut self, index: usize, rating: Option<u8>) {
+        if index >= self.tiles.len() {
+            return;
+        }
+        self.checkpoint();
+        let candidate = self.tiles[index].candidate.clone();
+        for tile in self.tiles.iter() {
+            self.population.remember(tile.candidate.genome.clone());
+        }
+        match rating {
+            Some(rating) => self.population.add_rated(candidate.genome.clone(), rating),
+            None => self.population.add(candidate.genome.clone())
+        }
+        self.diversity = self.population.diversity();
+        self.give_parents_feedback(&candidate, IMPLICIT_FEEDBACK);
+        let verdict = Verdict::Approved(rating.unwrap_or(DEFAULT_RATING));
+        self.record_judgments(vec![
+            Judgment { genome: candidate.genome.clone(), verdict: verdict }
+        ]);
+        self.lineage.add(candidate.genome.clone(), candidate.record);
+        self.keeper = candidate.genome;
+        self.breed_candidates();
+    }
+
+    // Rejects every candidate on screen, and breeds new ones
+    fn reject_candidates(&mut self) {
+        self.checkpoint();
+        let candidates: Vec<_> = self.tiles.iter().map(|t| t.candidate.clone()).collect();
+        let mut judgments = vec![];
+        // Tiles in a gallery often share parents, which only get penalized once per rejection
+        let mut parents: Vec<Genome> = vec![];
+        for candidate in candidates {
+            self.population.remember(candidate.genome.clone());
+            if let Some(ref record) = candidate.record {
+                for parent in [&record.parents.0, &record.parents.1].iter() {
+                    if !parents.contains(parent) {
+                        parents.push((*parent).clone());
+                    }
+                }
+            }
+            judgments.push(Judgment { genome: candidate.genome, verdict: Verdict::Rejected });
+        }
+        for parent in parents.iter() {
+            self.population.adjust_fitness(parent, -IMPLICIT_FEEDBACK);
+        }
+        self.record_judgments(judgments);
+        self.breed_candidates();
     }
 
-    fn reject_current_genome(&mut self) {
-        let genome = self.population.breed();
-        self.set_genome(genome);
+    // In a side-by-side comparison, the picked candidate stays on screen as the incumbent,
+    // and the other one is replaced by a new challenger
+    fn prefer_candidate(&mut self, index: usize) {
+        if index >= self.tiles.len() {
+            return;
+        }
+        self.checkpoint();
+        let winner = self.tiles[index].candidate.clone();
+        let loser = self.tiles[1 - index].candidate.clone();
+        self.population.remember(winner.genome.clone());
+        self.population.remember(loser.genome.clone());
+        self.population.add_preference(winner.genome.clone(), &loser.genome);
+        self.diversity = self.population.diversity();
+        self.record_judgments(vec![
+            Judgment { genome: winner.genome.clone(), verdict: Verdict::Preferred(loser.genome) }
+        ]);
+        self.lineage.add(winner.genome.clone(), winner.record.clone());
+        self.keeper = winner.genome.clone();
+
+        let mut candidates = vec![winner, self.breed_candidate()];
+        if index == 1 {
+            candidates.reverse();
+        }
+        self.set_candidates(candidates);
+    }
+
+    // Picks a candidate by clicking on it, or pressing its number key
+    fn pick_candidate(&mut self, index: usize) {
+        match self.layout {
+            Layout::Comparison => self.prefer_candidate(index),
+            _ => self.rate_candidate(index, None)
+        }
+    }
+
+    // Adds the judgments from one user action to the history, then retrains the preference model
+    // once for all of them
+    fn record_judgments(&mut self, judgments: Vec<Judgment>) {
+        self.history.extend(judgments);
+        self.preferences.train(&self.history);
+        self.session_changed = true;
+    }
+
+    fn give_parents_feedback(&mut self, candidate: &Candidate, delta: f32) {
+        if let Some(ref record) = candidate.record {
+            self.population.adjust_fitness(&record.parents.0, delta);
+            self.population.adjust_fitness(&record.parents.1, delta);
+        }
     }
 
-    fn randomize_current_genome(&mut self) {
-        self.set_genome(Genome {
-            pattern: Chromosome::rand(NUM_FORMULA_GENES, FORMULA_GENE_SIZE),
-            color: Chromosome::rand(NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE)
-        });
+    // Breeds an offspring of the keeper that the user will hopefully like
+    fn breed_candidate(&self) -> Candidate {
+        let (genome, record) = self.preferences.screen(||
+            self.population.breed_locked(&self.keeper, &self.locks)
+        );
+        Candidate { genome: genome, record: Some(record) }
     }
 
-    fn set_genome(&mut self, genome: Genome) {
-        self.current_genome = genome;
+    fn breed_candidates(&mut self) {
+        let candidates = (0..self.tiles.len()).map(|_| self.breed_candidate()).collect();
+        self.set_candidates(candidates);
+    }
+
+    fn randomize_candidates(&mut self) {
+        self.checkpoint();
+        let candidates: Vec<_> = (0..self.tiles.len()).map(|_| Candidate {
+            genome: Genome {
+                pattern: Chromosome::rand(NUM_FORMULA_GENES, FORMULA_GENE_SIZE),
+                color: Chromosome::rand(NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE)
+            },
+            record: None
+        }).collect();
+        self.keeper = candidates[0].genome.clone();
+        self.set_candidates(candidates);
+    }
+
+    // Candidate genomes are printed one per line, in tile order
+    fn print_candidates(&self) {
+        for tile in self.tiles.iter() {
+            println!("{}", tile.candidate.genome.to_base64());
+        }
+    }
+
+    // Number keys rate the plasma in single view, and pick a tile otherwise
+    fn press_number(&mut self, n: u8) {
+        match self.layout {
+            Layout::Single if n <= MAX_RATING => self.rate_candidate(0, Some(n)),
+            Layout::Single => (),
+            _ => self.pick_candidate(n as usize - 1)
+        }
+    }
+
+    // Where the tile at index goes in the window: tiles are arranged in a grid that's as close
+    // to square as possible, filled row by row
+    fn tile_rect(&self, index: usize) -> Rect {
+        let num_tiles = self.tiles.len() as u32;
+        let columns = (num_tiles as f32).sqrt().ceil() as u32;
+        let rows = (num_tiles + columns - 1)/columns;
+        let (column, row) = (index as u32 % columns, index as u32/columns);
+        let (tile_width, tile_height) = (self.width/columns, self.height/rows);
+        Rect::new((column*tile_width) as i32, (row*tile_height) as i32,
+                  tile_width.max(1), tile_height.max(1))
+    }
+
+    fn tile_at(&self, x: i32, y: i32) -> Option<usize> {
+        (0..self.tiles.len()).find(|&i| self.tile_rect(i).contains_point((x, y)))
+    }
+
+    // Toggles the lock on the nth gene, counting pattern genes first, then color genes
+    fn toggle_gene_lock(&mut self, n: usize) {
+        let num_pattern_genes = self.keeper.pattern.genes.len();
+        let num_color_genes = self.keeper.color.genes.len();
+        if n < num_pattern_genes {
+            self.locks.pattern.toggle_gene(n);
+        } else if n < num_pattern_genes + num_color_genes {
+            self.locks.color.toggle_gene(n - num_pattern_genes);
+        }
+    }
+
+    fn window_title(&self) -> String {
+        let mut title = "plasma".to_string();
+        if let Some(diversity) = self.diversity {
+            title.push_str(&format!(" [diversity: {:.2}]", diversity));
+        }
+        if self.population.novelty_search() {
+            title.push_str(" [novelty]");
+        }
+        if !self.locks.is_empty() {
+            title.push_str(&format!(" [locked: {}]", self.locks.describe()));
+        }
+        title
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            candidates: self.tiles.iter().map(|t| t.candidate.clone()).collect(),
+            keeper: self.keeper.clone(),
+            population: self.population.clone(),
+            num_judgments: self.history.len(),
+            num_lineage_nodes: self.lineage.len()
+        }
+    }
+
+    // Call before changing anything that should be undoable
+    fn checkpoint(&mut self) {
+        let snapshot = self.snapshot();
+        self.undo_stack.push(snapshot);
+        self.undone_judgments.clear();
+        self.undone_lineage_nodes.clear();
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        while self.history.len() > snapshot.num_judgments {
+            self.undone_judgments.push(self.history.pop().unwrap());
+        }
+        while self.history.len() < snapshot.num_judgments {
+            self.history.push(self.undone_judgments.pop().unwrap());
+        }
+        self.preferences.train(&self.history);
+        while self.lineage.len() > snapshot.num_lineage_nodes {
+            self.undone_lineage_nodes.push(self.lineage.pop().unwrap());
+        }
+        while self.lineage.len() < snapshot.num_lineage_nodes {
+            self.lineage.push(self.undone_lineage_nodes.pop().unwrap());
+        }
+        // Novelty mode is a preference, not part of what's being undone
+        let novelty_search = self.population.novelty_search();
+        self.population = snapshot.population;
+        self.population.set_novelty_search(novelty_search);
+        self.diversity = self.population.diversity();
+        self.keeper = snapshot.keeper;
+        self.set_candidates(snapshot.candidates);
+        self.session_changed = true;
+    }
+
+    fn undo(&mut self) {
+        let current = self.snapshot();
+        if let Some(previous) = self.undo_stack.undo(current) {
+            self.restore(previous);
+        }
+    }
+
+    fn redo(&mut self) {
+        let current = self.snapshot();
+        if let Some(next) = self.undo_stack.redo(current) {
+            self.restore(next);
+        }
+    }
+
+    fn set_candidates(&mut self, candidates: Vec<Candidate>) {
         self.clock_instant = Instant::now(); // Reset the clock
-        self.renderer.set_genome(&self.current_genome);
-        self.renderer.render(self.width as usize, self.height as usize, 0.0);
-        self.frame_deadline_seconds = 0.0;
+        for (index, candidate) in candidates.into_iter().enumerate() {
+            let rect = self.tile_rect(index);
+            let tile = &mut self.tiles[index];
+            tile.renderer.set_genome(&candidate.genome);
+            tile.renderer.render(rect.width() as usize, rect.height() as usize, 0.0);
+            tile.frame_deadline_seconds = 0.0;
+            tile.candidate = candidate;
+        }
+    }
+
+    fn save_session(&mut self) {
+        if let Some(ref path) = self.session_path {
+            let session = Session {
+                genome: self.tiles[0].candidate.genome.clone(),
+                population: self.population.clone(),
+                history: self.history.clone(),
+                lineage: self.lineage.clone(),
+                rendering: self.rendering_settings.clone()
+            };
+            if let Err(message) = session.save(path) {
+                writeln!(&mut ::std::io::stderr(), "{}", message).unwrap();
+            }
+        }
+        self.session_changed = false;
+        self.session_save_instant = Instant::now();
+    }
+
+    fn export_lineage(&self) {
+        if let Some(ref path) = self.lineage_path {
+            if let Err(message) = self.lineage.export(path, &self.rendering_settings) {
+                writeln!(&mut ::std::io::stderr(), "{}", message).unwrap();
+            }
+        }
+    }
+
+    fn autosave_session(&mut self) {
+        let autosave_due = self.session_save_instant.elapsed().as_secs() >= AUTOSAVE_INTERVAL_SECONDS;
+        if self.session_changed && autosave_due {
+            self.save_session();
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas<Window>) {
+        canvas.clear();
+        for (index, tile) in self.tiles.iter().enumerate() {
+            canvas.copy(&tile.texture, None, self.tile_rect(index)).unwrap();
+        }
+        canvas.present();
     }
 
     fn clock_seconds(&self) -> f64 {
@@ -70,54 +395,90 @@ pub fn run_interactive(settings: PlasmaSettings) {
     let texture_creator = canvas.texture_creator();
     let mut event_pump = sdl.event_pump().unwrap();
 
-    // Init screen to black via an initial 1x1 texture
-    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, 1, 1).unwrap();
-    texture.update(None, &[0, 0, 0], 3).unwrap();
-    canvas.copy(&texture, None, None).unwrap();
+    // Init screen to black
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
     canvas.present();
 
-    // Initialize plasma state
+    // Initialize plasma state, with one tile per candidate
+    let num_tiles = match settings.output.layout {
+        Layout::Single => 1,
+        Layout::Gallery { size } => size,
+        Layout::Comparison => 2
+    };
+    let starting_genome = settings.genetics.genome;
+    let rendering_settings = &settings.rendering;
+    let tiles = (0..num_tiles).map(|_| Tile {
+        candidate: Candidate { genome: starting_genome.clone(), record: None },
+        frame_deadline_seconds: 0.0,
+        renderer: AsyncRenderer::new(rendering_settings),
+        texture: create_texture(&texture_creator, 1, 1)
+    }).collect();
     let mut state = PlasmaState {
         clock_instant: Instant::now(),
-        current_texture: texture,
-        current_genome: settings.genetics.genome,
-        frame_deadline_seconds: 0.0,
+        diversity: settings.genetics.population.diversity(),
+        history: settings.genetics.history,
+        keeper: starting_genome.clone(),
+        layout: settings.output.layout,
+        lineage: settings.genetics.lineage,
+        lineage_path: settings.output.lineage_path,
+        locks: settings.genetics.locks,
         population: settings.genetics.population,
-        renderer: AsyncRenderer::new(&settings.rendering),
+        preferences: PreferenceModel::new(&settings.rendering, settings.genetics.exploration_rate),
+        rendering_settings: settings.rendering.clone(),
+        session_path: settings.genetics.session_path,
+        session_changed: false,
+        session_save_instant: Instant::now(),
+        tiles: tiles,
+        undo_stack: UndoStack::new(MAX_UNDO_DEPTH),
+        undone_judgments: vec![],
+        undone_lineage_nodes: vec![],
         width: settings.rendering.width as u32,
         height: settings.rendering.height as u32
     };
 
-    // Start an async render on the current_genome
-    state.renderer.set_genome(&state.current_genome);
-    state.renderer.render(state.width as usize, state.height as usize, 0.0);
+    // Start async renders: the starting genome, and with more than one tile, some of its offspring
+    let mut candidates = vec![Candidate { genome: starting_genome, record: None }];
+    state.preferences.train(&state.history);
+    for _ in 1..num_tiles {
+        candidates.push(state.breed_candidate());
+    }
+    state.set_candidates(candidates);
 
     // Calculate some useful constants
     let frame_delay_seconds = 1.0/(settings.rendering.frames_per_second as f64);
     let time_scale_factor = 1.0/settings.rendering.loop_duration as f64;
 
     loop {
-        // If a frame is due, put it on the screen
-        if state.frame_deadline_seconds <= state.clock_seconds() {
-            if let Some(image) = state.renderer.get_image() {
+        // If any tile has a frame that's due, put it on the screen
+        let now_seconds = state.clock_seconds();
+        let mut screen_changed = false;
+        for index in 0..state.tiles.len() {
+            let rect = state.tile_rect(index);
+            let tile = &mut state.tiles[index];
+            if tile.frame_deadline_seconds > now_seconds {
+                continue;
+            }
+            if let Some(image) = tile.renderer.get_image() {
                 // We have a frame, and it's due. Display it!
                 // But before we do, start a render of the next frame
-                state.frame_deadline_seconds = state.clock_seconds() + frame_delay_seconds;
-                let adj_time = ((state.frame_deadline_seconds*time_scale_factor) as f32).wrap();
-                state.renderer.render(state.width as usize, state.height as usize, adj_time);
+                tile.frame_deadline_seconds = now_seconds + frame_delay_seconds;
+                let adj_time = ((tile.frame_deadline_seconds*time_scale_factor) as f32).wrap();
+                tile.renderer.render(rect.width() as usize, rect.height() as usize, adj_time);
 
                 // Resize texture if necessary
-                let query = state.current_texture.query();
+                let query = tile.texture.query();
                 if (image.width, image.height) != (query.width as usize, query.height as usize) {
-                    state.current_texture = texture_creator.
-                        create_texture_streaming(PixelFormatEnum::RGB24, state.width, state.height).unwrap();
+                    tile.texture = create_texture(&texture_creator, image.width as u32,
+                                                  image.height as u32);
                 }
-                // Update texture, screen
-                state.current_texture.update(None, &image.pixel_data[..], image.width*3).unwrap();
-                canvas.copy(&state.current_texture, None, None).unwrap();
-                canvas.present();
+                tile.texture.update(None, &image.pixel_data[..], image.width*3).unwrap();
+                screen_changed = true;
             }
         }
+        if screen_changed {
+            state.draw(&mut canvas);
+        }
 
         // Calculate wait_time
         let wait_time_seconds = frame_delay_seconds.min(0.005);
@@ -140,17 +501,50 @@ pub fn run_interactive(settings: PlasmaSettings) {
                         Keycode::Equals | Keycode::Plus | Keycode::KpPlus => {
                             state.approve_current_genome();
                         }
-                        // User rejects current genome
+                        // User rates current genome from 1 to 5, or picks a gallery tile
+                        Keycode::Num1 | Keycode::Kp1 => state.press_number(1),
+                        Keycode::Num2 | Keycode::Kp2 => state.press_number(2),
+                        Keycode::Num3 | Keycode::Kp3 => state.press_number(3),
+                        Keycode::Num4 | Keycode::Kp4 => state.press_number(4),
+                        Keycode::Num5 | Keycode::Kp5 => state.press_number(5),
+                        Keycode::Num6 | Keycode::Kp6 => state.press_number(6),
+                        Keycode::Num7 | Keycode::Kp7 => state.press_number(7),
+                        Keycode::Num8 | Keycode::Kp8 => state.press_number(8),
+                        Keycode::Num9 | Keycode::Kp9 => state.press_number(9),
+                        // User rejects current genomes
                         Keycode::Minus | Keycode::Underscore | Keycode::KpMinus => {
-                            state.reject_current_genome();
+                            state.reject_candidates();
                         }
-                        // Export current genome
+                        // Export current genomes
                         Keycode::P => {
-                            println!("{}", state.current_genome.to_base64());
+                            state.print_candidates();
                         }
                         Keycode::R => {
-                            state.randomize_current_genome();
+                            state.randomize_candidates();
                         }
+                        // Lock parts of the genome, so that breeding leaves them alone
+                        Keycode::L => state.locks.pattern.whole = !state.locks.pattern.whole,
+                        Keycode::C => state.locks.color.whole = !state.locks.color.whole,
+                        // Toggle novelty mode
+                        Keycode::N => {
+                            let novelty_search = !state.population.novelty_search();
+                            state.population.set_novelty_search(novelty_search);
+                        }
+                        Keycode::F1 => state.toggle_gene_lock(0),
+                        Keycode::F2 => state.toggle_gene_lock(1),
+                        Keycode::F3 => state.toggle_gene_lock(2),
+                        Keycode::F4 => state.toggle_gene_lock(3),
+                        Keycode::F5 => state.toggle_gene_lock(4),
+                        Keycode::F6 => state.toggle_gene_lock(5),
+                        Keycode::F7 => state.toggle_gene_lock(6),
+                        Keycode::F8 => state.toggle_gene_lock(7),
+                        Keycode::F9 => state.toggle_gene_lock(8),
+                        Keycode::F10 => state.toggle_gene_lock(9),
+                        Keycode::F11 => state.toggle_gene_lock(10),
+                        Keycode::F12 => state.toggle_gene_lock(11),
+                        // Go back and forth through previously displayed genomes
+                        Keycode::Left | Keycode::Backspace => state.undo(),
+                        Keycode::Right => state.redo(),
                         _ => ()
                     }
                 }
@@ -160,15 +554,37 @@ pub fn run_interactive(settings: PlasmaSettings) {
                 } => {
                     state.width = new_width as u32;
                     state.height = new_height as u32;
-                    canvas.copy(&state.current_texture, None, None).unwrap();
-                    canvas.present();
+                    state.draw(&mut canvas);
+                }
+                // User picks a gallery tile, or one side of a comparison
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    if let Some(index) = state.tile_at(x, y) {
+                        state.pick_candidate(index);
+                    }
+                }
+                Event::Quit { .. } => {
+                    state.save_session();
+                    state.export_lineage();
+                    return;
                 }
-                Event::Quit { .. } => return,
                 _ => ()
             }
         }
         // End of event processing
 
+        // Keep the window title up to date with what's locked
+        let title = state.window_title();
+        if canvas.window().title() != title {
+            canvas.window_mut().set_title(&title).unwrap();
+        }
+
+        state.autosave_session();
+
         // End of main loop
     }
 }
+
+fn create_texture<'a>(texture_creator: &'a TextureCreator<WindowContext>,
+                      width: u32, height: u32) -> Texture<'a> {
+    texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width, height).unwrap()
+}
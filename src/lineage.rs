@@ -0,0 +1,297 @@
+extern crate rustc_serialize;
+
+use genetics::{BreedRecord, ChromosomeRecord, Genome};
+use png::encode_png;
+use renderer::{Image, PlasmaRenderer};
+use self::rustc_serialize::json::{Json, ToJson};
+use settings::RenderingSettings;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+/*
+ * Family trees of approved genomes.
+ *
+ * Each genome is identified by its Base64 string. A genome that was bred has a BreedRecord,
+ * which names its parents; a genome that came from somewhere else (random, or from the
+ * command line) is a founder, and has no parents.
+ */
+
+const THUMBNAIL_SIZE: usize = 64;
+
+#[derive(Clone)]
+pub struct Node {
+    pub genome: Genome,
+    pub record: Option<BreedRecord>
+}
+
+#[derive(Clone)]
+pub struct Lineage {
+    nodes: Vec<Node> // In the order they were added; parents always come before children
+}
+
+impl Lineage {
+    pub fn new() -> Lineage {
+        Lineage { nodes: vec![] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Removes the most recently added node, e.g., to undo an approval
+    pub fn pop(&mut self) -> Option<Node> {
+        self.nodes.pop()
+    }
+
+    // Puts back a node that was removed with pop()
+    pub fn push(&mut self, node: Node) {
+        self.nodes.push(node);
+    }
+
+    pub fn contains(&self, genome: &Genome) -> bool {
+        self.nodes.iter().any(|n| n.genome == *genome)
+    }
+
+    // Adds a genome to the family tree, along with any parents that aren't already in it
+    pub fn add(&mut self, genome: Genome, record: Option<BreedRecord>) {
+        if self.contains(&genome) {
+            return;
+        }
+        if let Some(ref r) = record {
+            for parent in [&r.parents.0, &r.parents.1].iter() {
+                if !self.contains(parent) {
+                    self.nodes.push(Node { genome: (*parent).clone(), record: None });
+                }
+            }
+        }
+        self.nodes.push(Node { genome: genome, record: record });
+    }
+
+    // Writes the family tree as Graphviz DOT or JSON, depending on the file extension.
+    // Thumbnails go in a directory next to the exported file.
+    pub fn export(&self, path: &str, settings: &RenderingSettings) -> Result<(), String> {
+        let path = Path::new(path);
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("lineage");
+        let thumbnail_dir_name = format!("{}_thumbnails", stem);
+        let thumbnail_dir = path.with_file_name(&thumbnail_dir_name);
+        let write_error = |e: ::std::io::Error| format!("Couldn't write lineage: {}", e);
+
+        // Render thumbnails
+        fs::create_dir_all(&thumbnail_dir).map_err(&write_error)?;
+        let mut thumbnail_settings = settings.clone();
+        thumbnail_settings.width = THUMBNAIL_SIZE;
+        thumbnail_settings.height = THUMBNAIL_SIZE;
+        let mut thumbnails = BTreeMap::new();
+        for node in self.nodes.iter() {
+            let id = node.genome.to_base64();
+            let file_name = format!("{}.png", id);
+            let thumbnail_path = thumbnail_dir.join(&file_name);
+            if !thumbnail_path.exists() {
+                let mut image = Image::new(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+                PlasmaRenderer::new(&node.genome, &thumbnail_settings).render(&mut image, 0.0);
+                File::create(&thumbnail_path).and_then(|mut file|
+                    file.write_all(&encode_png(&image))
+                ).map_err(&write_error)?;
+            }
+            thumbnails.insert(id, format!("{}/{}", thumbnail_dir_name, file_name));
+        }
+
+        let contents = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => format!("{}", self.to_json_with_thumbnails(Some(&thumbnails)).pretty()),
+            _ => self.to_dot(&thumbnails)
+        };
+        File::create(path).and_then(|mut file|
+            file.write_all(contents.as_bytes())
+        ).map_err(&write_error)
+    }
+
+    fn to_dot(&self, thumbnails: &BTreeMap<String, String>) -> String {
+        let mut dot = String::from("digraph lineage {\n    node [shape=box, label=\"\"];\n");
+        for node in self.nodes.iter() {
+            let id = node.genome.to_base64();
+            let image = thumbnails.get(&id).map_or(String::new(), |t| format!(", image=\"{}\"", t));
+            dot.push_str(&format!("    \"{}\" [tooltip=\"{}\"{}];\n", id, id, image));
+        }
+        for node in self.nodes.iter() {
+            if let Some(ref record) = node.record {
+                let id = node.genome.to_base64();
+                for parent in [&record.parents.0, &record.parents.1].iter() {
+                    dot.push_str(&format!("    \"{}\" -> \"{}\";\n", parent.to_base64(), id));
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn to_json_with_thumbnails(&self, thumbnails: Option<&BTreeMap<String, String>>) -> Json {
+        Json::Array(self.nodes.iter().map(|node| {
+            let id = node.genome.to_base64();
+            let mut json = BTreeMap::new();
+            if let Some(thumbnail) = thumbnails.and_then(|t| t.get(&id)) {
+                json.insert("thumbnail".to_string(), thumbnail.to_json());
+            }
+            json.insert("genome".to_string(), id.to_json());
+            if let Some(ref record) = node.record {
+                let parents = vec![record.parents.0.to_base64(), record.parents.1.to_base64()];
+                let mut crossover = BTreeMap::new();
+                let mut mutations = BTreeMap::new();
+                for &(name, c) in [("pattern", &record.pattern), ("color", &record.color)].iter() {
+                    let parent_indexes: Vec<u8> = c.from_first_parent.iter().map(|&from_first|
+                        if from_first { 0 } else { 1 }
+                    ).collect();
+                    let positions: Vec<Vec<usize>> = c.mutations.iter().map(|&(gene, byte)|
+                        vec![gene, byte]
+                    ).collect();
+                    crossover.insert(name.to_string(), parent_indexes.to_json());
+                    mutations.insert(name.to_string(), positions.to_json());
+                }
+                json.insert("parents".to_string(), parents.to_json());
+                json.insert("crossover".to_string(), Json::Object(crossover));
+                json.insert("mutations".to_string(), Json::Object(mutations));
+            }
+            Json::Object(json)
+        }).collect())
+    }
+
+    pub fn from_json(json: &Json) -> Result<Lineage, String> {
+        let genome_from_json = |json: Option<&Json>| -> Result<Genome, String> {
+            let genome_string = json.and_then(|g| g.as_string()).ok_or("missing genome")?;
+            Genome::from_base64(genome_string).map_err(|e| format!("{}: {}", e, genome_string))
+        };
+        let mut lineage = Lineage::new();
+        for node in json.as_array().ok_or("lineage is not a list")? {
+            let genome = genome_from_json(node.find("genome"))?;
+            let record = match node.find("parents").and_then(|p| p.as_array()) {
+                Some(parents) if parents.len() == 2 => {
+                    let chromosome_record = |name| -> Result<ChromosomeRecord, String> {
+                        let bad_record = || format!("bad breed record for {}", name);
+                        let mut from_first_parent = vec![];
+                        let parent_indexes = node.find_path(&["crossover", name]).
+                            and_then(|c| c.as_array()).ok_or_else(&bad_record)?;
+                        for parent_index in parent_indexes {
+                            match parent_index.as_u64() {
+                                Some(0) => from_first_parent.push(true),
+                                Some(1) => from_first_parent.push(false),
+                                _ => return Err(bad_record())
+                            }
+                        }
+                        let mut mutations = vec![];
+                        let positions = node.find_path(&["mutations", name]).
+                            and_then(|m| m.as_array()).ok_or_else(&bad_record)?;
+                        for position in positions {
+                            let p = position.as_array().ok_or_else(&bad_record)?;
+                            let gene = p.get(0).and_then(|i| i.as_u64()).ok_or_else(&bad_record)?;
+                            let byte = p.get(1).and_then(|i| i.as_u64()).ok_or_else(&bad_record)?;
+                            mutations.push((gene as usize, byte as usize));
+                        }
+                        Ok(ChromosomeRecord {
+                            from_first_parent: from_first_parent,
+                            mutations: mutations
+                        })
+                    };
+                    Some(BreedRecord {
+                        parents: (genome_from_json(parents.get(0))?, genome_from_json(parents.get(1))?),
+                        pattern: chromosome_record("pattern")?,
+                        color: chromosome_record("color")?
+                    })
+                }
+                Some(_) => return Err("genome must have exactly two parents".to_string()),
+                None => None
+            };
+            lineage.add(genome, record);
+        }
+        Ok(lineage)
+    }
+}
+
+impl ToJson for Lineage {
+    fn to_json(&self) -> Json {
+        self.to_json_with_thumbnails(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use genetics::Genome;
+    use std::collections::BTreeMap;
+    use super::rustc_serialize::json::{Json, ToJson};
+    use super::Lineage;
+
+    #[test]
+    fn test_lineage_add() {
+        let a = Genome::rand();
+        let b = Genome::rand();
+        let (c, record) = a.breed_recorded(&b);
+        let mut lineage = Lineage::new();
+        lineage.add(a.clone(), None);
+        lineage.add(c.clone(), Some(record.clone()));
+
+        // Parent b was missing, so it should have been added as a founder before c
+        let genomes: Vec<_> = lineage.nodes.iter().map(|n| n.genome.clone()).collect();
+        assert_eq!(genomes, vec![a.clone(), b, c.clone()]);
+
+        // Adding a genome twice doesn't change anything
+        lineage.add(c, None);
+        assert_eq!(lineage.nodes.len(), 3);
+        assert_eq!(lineage.nodes[2].record, Some(record));
+    }
+
+    #[test]
+    fn test_lineage_json_round_trip() {
+        let a = Genome::rand();
+        let b = Genome::rand();
+        let (c, c_record) = a.breed_recorded(&b);
+        let (d, d_record) = c.breed_recorded(&a);
+        let mut l1 = Lineage::new();
+        l1.add(c, Some(c_record));
+        l1.add(d, Some(d_record));
+
+        let text = l1.to_json().to_string();
+        let l2 = Lineage::from_json(&Json::from_str(&text).unwrap()).unwrap();
+        assert_eq!(l1.nodes.len(), l2.nodes.len());
+        for (n1, n2) in l1.nodes.iter().zip(l2.nodes.iter()) {
+            assert_eq!(n1.genome, n2.genome);
+            assert_eq!(n1.record, n2.record);
+        }
+    }
+
+    // Crossover entries say which parent each gene came from, so anything but 0 or 1 is an error
+    #[test]
+    fn test_lineage_json_bad_crossover() {
+        let a = Genome::rand();
+        let (c, record) = a.breed_recorded(&Genome::rand());
+        let mut lineage = Lineage::new();
+        lineage.add(c, Some(record));
+        for bad_index in [2.to_json(), "0".to_json(), Json::Null].iter() {
+            let mut json = lineage.to_json();
+            {
+                let nodes = json.as_array_mut().unwrap();
+                let node = nodes.iter_mut().find(|n| n.find("crossover").is_some()).unwrap();
+                let crossover = node.as_object_mut().unwrap().get_mut("crossover").unwrap();
+                let pattern = crossover.as_object_mut().unwrap().get_mut("pattern").unwrap();
+                pattern.as_array_mut().unwrap()[0] = bad_index.clone();
+            }
+            assert!(Lineage::from_json(&json).is_err());
+        }
+    }
+
+    #[test]
+    fn test_lineage_to_dot() {
+        let a = Genome::rand();
+        let b = Genome::rand();
+        let (c, record) = a.breed_recorded(&b);
+        let mut lineage = Lineage::new();
+        lineage.add(c.clone(), Some(record));
+        let mut thumbnails = BTreeMap::new();
+        thumbnails.insert(c.to_base64(), "thumbs/c.png".to_string());
+
+        let dot = lineage.to_dot(&thumbnails);
+        assert!(dot.starts_with("digraph"));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\"", a.to_base64(), c.to_base64())));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\"", b.to_base64(), c.to_base64())));
+        assert!(dot.contains("image=\"thumbs/c.png\""));
+    }
+}
@@ -5,28 +5,58 @@ extern crate ordered_float;
 extern crate sdl2;
 
 mod asyncrenderer;
+mod batch;
 mod color;
+mod commands;
+mod distance;
+mod evolve;
+mod export;
 mod fastmath;
 mod file;
 mod formulas;
 mod genetics;
 mod interactive;
+mod lineage;
+mod morph;
+mod playlist;
+mod png;
+mod preference;
 mod renderer;
+mod session;
 mod settings;
+mod undo;
 
-use color::colormapper::{NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE};
-use formulas::{NUM_FORMULA_GENES, FORMULA_GENE_SIZE};
+use color::{ColorSpace, TransferFunction};
+use color::palette::dither::Ditherer;
+use color::palette::fixed;
+use color::stops;
+use evolve::Fitness;
 use getopts::{Matches, Options};
-use genetics::{Chromosome, Genome, Population};
-use settings::{GeneticSettings, OutputMode, OutputSettings, PlasmaSettings, RenderingSettings};
+use genetics::{GeneLocks, Genome, Population};
+use lineage::Lineage;
+use session::Session;
+use settings::{BatchSettings, EvolveSettings, GeneticSettings, Layout, MorphSettings, OutputMode,
+               OutputSettings, PlasmaSettings, PlaylistSettings, RenderingSettings};
 use std::cmp::max;
 use std::env;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
 use std::process::exit;
 
 const STARTING_POPULATION_SIZE: usize = 8;
 const MAX_POPULATION_SIZE: usize = 32;
+const DEFAULT_IMMIGRATION_RATE: f64 = 0.0;
+const MAX_GALLERY_SIZE: usize = 9; // Number keys can only pick this many tiles
+const DEFAULT_EXPLORATION_RATE: f64 = 0.25;
+const DEFAULT_GENERATIONS: usize = 20;
+const DEFAULT_NUM_TOP_GENOMES: usize = 10;
+const DEFAULT_NAME_TEMPLATE: &str = "{genome}.gif";
+const DEFAULT_BATCH_THREADS: usize = 4;
+const DEFAULT_CROSSFADE_SECONDS: f32 = 2.0;
+
+// Commands that can be given before any genomes, like "plasma evolve"
+const COMMANDS: &[&str] = &["breed", "evolve", "export", "morph", "mutate", "play", "random"];
 
 macro_rules! errorln {
     ($x:expr, $($y:tt)*) => { writeln!(&mut std::io::stderr(), $x, $($y)*).unwrap() };
@@ -34,28 +64,83 @@ macro_rules! errorln {
 
 fn main() {
     let opts = create_options();
-    let matches = match opts.parse(env::args()) {
+    let mut matches = match opts.parse(env::args()) {
         Ok(m) => m,
         Err(e) => exit_with_error(&format!("{}", e), true)
     };
     if matches.opt_present("help") {
         exit_with_help();
     }
-    let params = match build_plasma_settings(matches) {
+
+    // The first free argument is the program name, and the second might be a command
+    let is_command = matches.free.get(1).map_or(false, |c| COMMANDS.contains(&c.as_str()));
+    let command = if is_command { Some(matches.free.remove(1)) } else { None };
+    match command.as_ref().map(|c| c.as_str()) {
+        Some("breed") | Some("mutate") | Some("random") => {
+            match run_breeding_command(command.as_ref().unwrap(), &matches) {
+                Ok(genomes) => for genome in genomes {
+                    println!("{}", genome.to_base64());
+                },
+                Err(message) => exit_with_error(&message, true)
+            }
+            return;
+        }
+        _ => ()
+    }
+
+    let params = match build_plasma_settings(matches, command) {
         Ok(params) => params,
         Err(message) => exit_with_error(&message, true)
     };
 
     match params.output.mode.clone() {
         OutputMode::File{ref path} => {
+            let gif_bytes = file::generate_gif_bytes(&params.genetics.genome, &params.rendering);
             File::create(path).and_then(|mut file| {
-                let gif_bytes = file::generate_gif_bytes(params);
                 file.write_all(&gif_bytes[..])
             }).unwrap_or_else(|e|
                 exit_with_error(&format!("Couldn't write to {}: {}", &path, e), false)
             );
+
+            // Show what temporal dithering saved, by encoding the GIF again without it
+            if params.output.stats {
+                let mut plain_settings = params.rendering.clone();
+                plain_settings.temporal_threshold = None;
+                let plain_size =
+                    file::generate_gif_bytes(&params.genetics.genome, &plain_settings).len();
+                errorln!("{}: {} bytes ({} bytes without temporal dithering)",
+                         path, gif_bytes.len(), plain_size);
+            }
+        },
+        OutputMode::Export{ref path} => {
+            export::run_export(&params.genetics.genome, &params.rendering, path)
+                .unwrap_or_else(|message| exit_with_error(&message, false));
+        },
+        OutputMode::Interactive => interactive::run_interactive(params),
+        OutputMode::Evolve(ref evolve_settings) => {
+            evolve::run_evolve(params, evolve_settings).unwrap_or_else(|message|
+                exit_with_error(&message, false)
+            );
+        },
+        OutputMode::Batch(ref batch_settings) => {
+            batch::run_batch(&params.genetics.genomes, &params.rendering, batch_settings)
+                .unwrap_or_else(|message| exit_with_error(&message, false));
+        },
+        OutputMode::Morph(ref morph_settings) => {
+            let genomes = &params.genetics.genomes;
+            morph::run_morph(&genomes[0], &genomes[1], &params.rendering, morph_settings)
+                .unwrap_or_else(|message| exit_with_error(&message, false));
         },
-        OutputMode::Interactive => interactive::run_interactive(params)
+        OutputMode::Playlist(ref playlist_settings) => {
+            let genomes = &params.genetics.genomes;
+            match playlist_settings.path {
+                Some(ref path) => {
+                    playlist::write_playlist(genomes, &params.rendering, playlist_settings, path)
+                        .unwrap_or_else(|message| exit_with_error(&message, false));
+                },
+                None => playlist::show_playlist(genomes, &params.rendering, playlist_settings)
+            }
+        }
     };
 }
 
@@ -76,9 +161,23 @@ fn exit_with_help() -> ! {
     let program_name = get_program_name();
     let header = format!(
         "\
-            Usage: {program} [OPTION]... [GENOME]...\n\
+            Usage: {program} [OPTION]... [GENOME]...\n       \
+                   {program} evolve [OPTION]... [GENOME]...\n       \
+                   {program} export [OPTION]... GENOME\n       \
+                   {program} morph [OPTION]... GENOME GENOME\n       \
+                   {program} breed [OPTION]... GENOME GENOME\n       \
+                   {program} mutate [OPTION]... GENOME\n       \
+                   {program} play [OPTION]... [GENOME]...\n       \
+                   {program} random [OPTION]...\n\
             GENOME is a Base64 string that represents a plasma's pattern and color.\n\
-            More than one genome can be specified.\
+            More than one genome can be specified.\n\
+            With --output-dir, every genome is rendered to its own GIF.\n\
+            The evolve command breeds genomes without a human, using --fitness to score them.\n\
+            The export command writes a genome's colors as CSS, a GIMP gradient and palette, \
+            and a PNG.\n\
+            The morph command renders a transition from one genome to another to a GIF.\n\
+            The play command shows genomes one after another, or writes them to one GIF.\n\
+            The breed, mutate and random commands print new genomes, one per line.\
         ",
         program = program_name
     );
@@ -90,19 +189,66 @@ fn exit_with_help() -> ! {
 fn create_options() -> Options {
     let mut opts = Options::new();
     opts.optflag("d", "dithering", "Force dithering");
+    opts.optopt("", "dither", "Dither with bayer, blue-noise, floyd-steinberg, atkinson \
+                               or sierra (default bayer)", "KIND");
+    opts.optopt("", "temporal-dither", "Dither so pixels keep their color from the frame before \
+                                        when it's within DISTANCE of their new color, which makes \
+                                        smaller GIFs (try 0.05)", "DISTANCE");
+    opts.optflag("", "stats", "With -o and --temporal-dither, also print how big the GIF would \
+                               be without temporal dithering");
     opts.optopt("p", "palette", "Render using a color palette of a given size", "N");
+    opts.optopt("", "gradient", "Use these colors instead of the genome's: stops like \
+                                 ff0000@0,0000ff@0.5, a CSS linear-gradient(), \
+                                 or a file of either, or a GIMP .ggr file", "STOPS");
+    opts.optopt("", "palette-file", "Render using the colors in a .gpl, .hex, .act or .pal file, \
+                                     or a built-in palette (pico-8, c64, ega or gameboy)", "FILE");
     opts.optopt("f", "fps", "Frames per second", "N");
     opts.optopt("l", "loop-duration", "Seconds until the animation loops", "N");
     opts.optopt("i", "input", "Read genomes from file, one genome per line", "FILE");
     opts.optopt("o", "output", "Output to a file (GIF) instead of to a window", "FILE");
+    opts.optopt("", "output-dir", "Output every genome to its own GIF in a directory", "DIR");
+    opts.optopt("", "name", "With --output-dir: file name for each genome, where {genome} and \
+                             {index} are filled in (default {genome}.gif)", "TEMPLATE");
+    opts.optopt("", "threads", "With --output-dir: how many GIFs to render at once (default 4)", "N");
+    opts.optopt("s", "session", "Resume a breeding session from a file, and save it on exit", "FILE");
+    opts.optmulti("", "lock", "Keep part of the genome when breeding: pattern, color, \
+                               or single genes like color:0,3", "PART");
+    opts.optopt("", "lineage", "On exit, write the family tree of approved genomes (.dot or .json)", "FILE");
+    opts.optopt("", "immigration", "Probability that a parent comes from outside the population \
+                                    (default 0, try 0.1)", "P");
+    opts.optopt("", "exploration", "Probability of showing offspring without checking whether \
+                                    you're likely to like them (default 0.25)", "P");
+    opts.optflag("", "novelty", "Start in novelty mode, favoring offspring unlike any seen so far");
+    opts.optopt("g", "gallery", "Show a grid of N candidates at a time, and click the best one", "N");
+    opts.optflag("c", "compare", "Show two candidates side by side, and pick the better one");
+    opts.optopt("", "generations", "With evolve: how many generations to breed (default 20)", "N");
+    opts.optmulti("", "fitness", "With evolve: what to optimize for: variety, hue=DEGREES, \
+                                  smoothness or gif-size (default variety)", "NAME");
+    opts.optopt("", "top", "With evolve: how many of the fittest genomes to write (default 10)", "N");
+    opts.optflag("", "round-trip", "With morph: morph back to the first genome, so the GIF loops");
+    opts.optopt("", "loops", "With play: how many times each genome loops before the next \
+                              (default 1)", "N");
+    opts.optopt("", "crossfade", "With play: seconds to fade from one genome to the next \
+                                  (default 2)", "SECONDS");
+    opts.optflag("", "fullscreen", "With play: fill the screen");
+    opts.optopt("n", "count", "With breed, mutate or random: how many genomes to print \
+                               (default 1)", "N");
+    opts.optflag("", "color-cycling", "Animate by cycling the colors through a pattern that \
+                                       stands still, like classic plasmas");
+    opts.optflag("", "local-palettes", "With -o or --output-dir: give each GIF frame a \
+                                        palette of its own, chosen for its colors");
+    opts.optopt("", "color-space", "Mix and compare colors in linear RGB or in OKLab, \
+                                    which looks more even (default linear)", "NAME");
+    opts.optopt("", "transfer", "Gamma encode colors with the sRGB curve, or with a plain 2.2 \
+                                 gamma like older versions (srgb or gamma, default srgb)", "NAME");
     opts.optopt("w", "width", "Width, in pixels", "X");
     opts.optopt("h", "height", "Height, in pixels", "Y");
     opts.optflag("", "help", "Show this help text");
     opts
 }
 
-fn build_plasma_settings(matches: Matches) -> Result<PlasmaSettings, String> {
-    // Read genomes from free arguments
+// Reads genomes from free arguments, and then from the --input file
+fn read_genomes(matches: &Matches) -> Result<Vec<Genome>, String> {
     let genome_strings = &matches.free[1..];
     let mut genomes = vec![];
     for genome_string in genome_strings {
@@ -112,7 +258,6 @@ fn build_plasma_settings(matches: Matches) -> Result<PlasmaSettings, String> {
         };
     }
 
-    // Read additional genomes from file
     if let Some(filename) = matches.opt_str("i") {
         File::open(&filename).and_then(|file| {
             for line_result in BufReader::new(file).lines() {
@@ -122,65 +267,259 @@ fn build_plasma_settings(matches: Matches) -> Result<PlasmaSettings, String> {
                 }
             }
             Ok(())
-        }).unwrap_or_else(
-            |e| exit_with_error(&format!("Couldn't read from {}: {}", &filename, e), false)
-        );
+        }).map_err(|e| format!("Couldn't read from {}: {}", &filename, e))?;
+    }
+    Ok(genomes)
+}
+
+fn read_locks(matches: &Matches) -> Result<GeneLocks, String> {
+    let mut locks = GeneLocks::default();
+    for spec in matches.opt_strs("lock") {
+        locks.add_spec(&spec)?;
+    }
+    Ok(locks)
+}
+
+fn run_breeding_command(command: &str, matches: &Matches) -> Result<Vec<Genome>, String> {
+    let count = match matches.opt_str("n") {
+        Some(count_str) => match count_str.parse() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("Not a positive integer: {}", count_str))
+        },
+        None => 1
+    };
+    let genomes = read_genomes(matches)?;
+    let locks = read_locks(matches)?;
+    match (command, genomes.len()) {
+        ("breed", 2) => Ok(commands::breed(&genomes[0], &genomes[1], &locks, count)),
+        ("breed", _) => Err("breed needs exactly two genomes".to_string()),
+        ("mutate", 1) => Ok(commands::mutate(&genomes[0], &locks, count)),
+        ("mutate", _) => Err("mutate needs exactly one genome".to_string()),
+        ("random", 0) => Ok(commands::random(count)),
+        ("random", _) => Err("random doesn't take any genomes".to_string()),
+        _ => Err(format!("Unknown command {}", command))
     }
+}
+
+fn build_plasma_settings(matches: Matches, command: Option<String>) -> Result<PlasmaSettings, String> {
+    let mut genomes = read_genomes(&matches)?;
+
+    // Resume a previous session, if there is one
+    let session_path = matches.opt_str("s");
+    let session = match session_path {
+        Some(ref path) if Path::new(path).exists() => Some(Session::load(path)?),
+        _ => None
+    };
 
     // Set up genetic settings
-    if genomes.len() == 0 {
-        for _ in 0..STARTING_POPULATION_SIZE {
-            genomes.push(Genome {
-                pattern: Chromosome::rand(NUM_FORMULA_GENES, FORMULA_GENE_SIZE),
-                color: Chromosome::rand(NUM_COLOR_GENES, CONTROL_POINT_GENE_SIZE)
-            });
+    let given_genomes = genomes.clone();
+    if genomes.len() == 0 && session.is_none() {
+        genomes = commands::random(STARTING_POPULATION_SIZE);
+    }
+    let (starting_genome, history, lineage, saved_population, saved_rendering_settings) =
+        match session {
+            Some(s) => (genomes.get(0).cloned().unwrap_or(s.genome), s.history, s.lineage,
+                        Some(s.population), Some(s.rendering)),
+            None => (genomes[0].clone(), vec![], Lineage::new(), None, None)
+        };
+    let saved_population_size = saved_population.as_ref().map_or(0, |p| p.len());
+    let mut population = Population::new(
+        max(MAX_POPULATION_SIZE, genomes.len() + saved_population_size)
+    );
+    population.set_distance_metric(distance::genome_distance);
+    population.set_immigration_rate(match matches.opt_str("immigration") {
+        Some(rate_str) => match rate_str.parse() {
+            Ok(r) if 0.0 <= r && r <= 1.0 => r,
+            _ => return Err(format!("Not a number from 0 to 1: {}", rate_str))
+        },
+        None => DEFAULT_IMMIGRATION_RATE
+    });
+    population.set_novelty_search(matches.opt_present("novelty"));
+    if let Some(ref p) = saved_population {
+        for (genome, fitness) in p.iter() {
+            population.add_with_fitness(genome.clone(), fitness);
+        }
+        for (genome, fitness) in p.hall_of_fame() {
+            population.add_to_hall_of_fame(genome.clone(), fitness);
+        }
+        for genome in p.remembered() {
+            population.remember(genome.clone());
         }
     }
-    let starting_genome = genomes[0].clone();
-    let mut population = Population::new(max(MAX_POPULATION_SIZE, genomes.len()));
     for genome in genomes {
         population.add(genome);
     }
+    let locks = read_locks(&matches)?;
+    let exploration_rate = match matches.opt_str("exploration") {
+        Some(rate_str) => match rate_str.parse() {
+            Ok(r) if 0.0 <= r && r <= 1.0 => r,
+            _ => return Err(format!("Not a number from 0 to 1: {}", rate_str))
+        },
+        None => DEFAULT_EXPLORATION_RATE
+    };
     let genetic_settings = GeneticSettings {
         genome: starting_genome,
-        population: population
+        genomes: given_genomes,
+        population: population,
+        history: history,
+        lineage: lineage,
+        locks: locks,
+        exploration_rate: exploration_rate,
+        session_path: session_path
     };
 
     // Set up output settings
-    let output_mode = if let Some(path) = matches.opt_str("o") {
+    let output_mode = if command.as_ref().map(|c| c.as_str()) == Some("evolve") {
+        OutputMode::Evolve(build_evolve_settings(&matches)?)
+    } else if command.as_ref().map(|c| c.as_str()) == Some("morph") {
+        if genetic_settings.genomes.len() != 2 {
+            return Err("morph needs exactly two genomes".to_string());
+        }
+        match matches.opt_str("o") {
+            Some(path) => OutputMode::Morph(MorphSettings {
+                path: path,
+                round_trip: matches.opt_present("round-trip")
+            }),
+            None => return Err("morph needs an output file (-o)".to_string())
+        }
+    } else if command.as_ref().map(|c| c.as_str()) == Some("export") {
+        if genetic_settings.genomes.len() != 1 {
+            return Err("export needs exactly one genome".to_string());
+        }
+        match matches.opt_str("o") {
+            Some(path) => OutputMode::Export { path },
+            None => return Err("export needs an output name (-o), \
+                                which gets .css, .ggr, .gpl and .png added to it".to_string())
+        }
+    } else if command.as_ref().map(|c| c.as_str()) == Some("play") {
+        if genetic_settings.genomes.is_empty() {
+            return Err("play needs at least one genome".to_string());
+        }
+        OutputMode::Playlist(build_playlist_settings(&matches)?)
+    } else if matches.opt_present("output-dir") {
+        if matches.opt_present("o") {
+            return Err("Can't use --output and --output-dir together".to_string());
+        }
+        OutputMode::Batch(build_batch_settings(&matches)?)
+    } else if let Some(path) = matches.opt_str("o") {
         OutputMode::File { path }
     } else {
         OutputMode::Interactive
     };
+    let lineage_path = matches.opt_str("lineage");
+    if let Some(ref path) = lineage_path {
+        if !(path.ends_with(".dot") || path.ends_with(".gv") || path.ends_with(".json")) {
+            return Err(format!("Lineage must be a .dot, .gv or .json file: {}", path));
+        }
+    }
+    let layout = match (matches.opt_str("g"), matches.opt_present("c")) {
+        (Some(_), true) => return Err("Can't use --gallery and --compare together".to_string()),
+        (Some(size_str), false) => match size_str.parse() {
+            Ok(n) if 2 <= n && n <= MAX_GALLERY_SIZE => Layout::Gallery { size: n },
+            _ => return Err(format!("Not an integer from 2 to {}: {}", MAX_GALLERY_SIZE, size_str))
+        },
+        (None, true) => Layout::Comparison,
+        (None, false) => Layout::Single
+    };
     let output_settings = OutputSettings {
-        mode: output_mode
+        mode: output_mode,
+        layout: layout,
+        lineage_path: lineage_path,
+        stats: matches.opt_present("stats")
     };
 
     // Set up rendering settings
     let mut rendering_settings = match output_settings.mode {
-        OutputMode::Interactive => RenderingSettings {
-            dithering: false,
-            frames_per_second: 16.0,
+        OutputMode::Interactive if saved_rendering_settings.is_some() => {
+            saved_rendering_settings.unwrap()
+        }
+        OutputMode::Interactive | OutputMode::Playlist(PlaylistSettings { path: None, .. }) => {
+            RenderingSettings {
+                color_cycling: false,
+                color_space: ColorSpace::LinearRgb,
+                ditherer: Ditherer::Bayer,
+                dithering: false,
+                frames_per_second: 16.0,
+                gradient: None,
+                local_palettes: false,
+                loop_duration: 60.0,
+                palette: None,
+                palette_size: None,
+                temporal_threshold: None,
+                transfer_function: TransferFunction::Srgb,
+                width: 640,
+                height: 480
+            }
+        }
+        // Evolution renders lots of plasmas, so keep them small
+        OutputMode::Evolve(..) => RenderingSettings {
+            color_cycling: false,
+            color_space: ColorSpace::LinearRgb,
+            ditherer: Ditherer::Bayer,
+            dithering: true,
+            frames_per_second: 10.0,
+            gradient: None,
+            local_palettes: false,
             loop_duration: 60.0,
-            palette_size: None,
-            width: 640,
-            height: 480
+            palette: None,
+            palette_size: Some(64),
+            temporal_threshold: None,
+            transfer_function: TransferFunction::Srgb,
+            width: 64,
+            height: 48
         },
-        OutputMode::File{..} => RenderingSettings {
+        OutputMode::File{..} | OutputMode::Export{..} | OutputMode::Batch(..) |
+        OutputMode::Morph(..) | OutputMode::Playlist(..) => RenderingSettings {
+            color_cycling: false,
+            color_space: ColorSpace::LinearRgb,
+            ditherer: Ditherer::Bayer,
             dithering: true,
             frames_per_second: 10.0,
+            gradient: None,
+            local_palettes: false,
             loop_duration: 60.0,
+            palette: None,
             palette_size: Some(64),
+            temporal_threshold: None,
+            transfer_function: TransferFunction::Srgb,
             width: 320,
             height: 240
         }
     };
-    if matches.opt_present("d") {
+    if let Some(name) = matches.opt_str("dither") {
+        rendering_settings.ditherer = Ditherer::from_name(&name)?;
+    }
+    if let Some(threshold_str) = matches.opt_str("temporal-dither") {
+        rendering_settings.temporal_threshold = match threshold_str.parse() {
+            Ok(t) if t >= 0.0 => Some(t),
+            _ => return Err(format!("Not a distance of 0 or more: {}", threshold_str))
+        };
+    }
+    if matches.opt_present("stats") {
+        match (&output_settings.mode, rendering_settings.temporal_threshold) {
+            (&OutputMode::File{..}, Some(_)) => (),
+            _ => return Err("--stats only works with -o and --temporal-dither".to_string())
+        }
+    }
+    if matches.opt_present("d") || matches.opt_present("dither") ||
+            matches.opt_present("temporal-dither") {
         rendering_settings.dithering = true;
         if rendering_settings.palette_size.is_none() {
             rendering_settings.palette_size = Some(255);
         }
     }
+    if matches.opt_present("color-cycling") {
+        rendering_settings.color_cycling = true;
+    }
+    if matches.opt_present("local-palettes") {
+        rendering_settings.local_palettes = true;
+    }
+    if let Some(name) = matches.opt_str("color-space") {
+        rendering_settings.color_space = ColorSpace::from_name(&name)?;
+    }
+    if let Some(name) = matches.opt_str("transfer") {
+        rendering_settings.transfer_function = TransferFunction::from_name(&name)?;
+    }
     if let Some(fps_str) = matches.opt_str("f") {
         rendering_settings.frames_per_second = match fps_str.parse() {
             Ok(f) if f > 0.0 => f,
@@ -200,6 +539,36 @@ fn build_plasma_settings(matches: Matches) -> Result<PlasmaSettings, String> {
             _ => return Err(format!("Not an integer from 2 to 255: {}", palette_size_str))
         };
     }
+    if let Some(gradient) = matches.opt_str("gradient") {
+        rendering_settings.gradient = Some(stops::load(&gradient)?);
+    }
+    if let Some(palette_path) = matches.opt_str("palette-file") {
+        if matches.opt_present("p") {
+            return Err("--palette and --palette-file can't be used together".to_string());
+        }
+        rendering_settings.palette = Some(fixed::load(&palette_path)?);
+    }
+    // Color cycling makes its own palettes, and every pixel keeps its color from frame to frame
+    if rendering_settings.color_cycling {
+        if rendering_settings.palette.is_some() {
+            return Err("--color-cycling and --palette-file can't be used together".to_string());
+        }
+        if rendering_settings.temporal_threshold.is_some() {
+            return Err("--color-cycling and --temporal-dither can't be used together".to_string());
+        }
+    }
+    if matches.opt_present("local-palettes") {
+        if rendering_settings.palette.is_some() {
+            return Err("--local-palettes and --palette-file can't be used together".to_string());
+        }
+        if rendering_settings.color_cycling {
+            return Err("--local-palettes and --color-cycling can't be used together".to_string());
+        }
+        match output_settings.mode {
+            OutputMode::File{..} | OutputMode::Batch(..) => (),
+            _ => return Err("--local-palettes only works with -o or --output-dir".to_string())
+        }
+    }
     if let (Some(width_str), Some(height_str)) = (matches.opt_str("w"), matches.opt_str("h")) {
         rendering_settings.width = match width_str.parse() {
             Ok(w) if w > 0 => w,
@@ -213,9 +582,105 @@ fn build_plasma_settings(matches: Matches) -> Result<PlasmaSettings, String> {
         return Err("Width and height must both be specified".to_string());
     }
 
+    if let OutputMode::Playlist(ref playlist_settings) = output_settings.mode {
+        let show_seconds = rendering_settings.loop_duration*playlist_settings.loops as f32;
+        if playlist_settings.crossfade_seconds > show_seconds {
+            return Err(format!("Crossfade can't be longer than each genome is shown ({} seconds)",
+                               show_seconds));
+        }
+    }
+
     Ok(PlasmaSettings {
         genetics: genetic_settings,
         rendering: rendering_settings,
         output: output_settings
     })
 }
+
+fn build_evolve_settings(matches: &Matches) -> Result<EvolveSettings, String> {
+    let parse_count = |name: &str, default| match matches.opt_str(name) {
+        Some(n_str) => match n_str.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("Not a positive integer: {}", n_str))
+        },
+        None => Ok(default)
+    };
+    let mut fitness = vec![];
+    for spec in matches.opt_strs("fitness") {
+        fitness.push(Fitness::from_spec(&spec)?);
+    }
+    if fitness.is_empty() {
+        fitness.push(Fitness::Variety);
+    }
+    Ok(EvolveSettings {
+        generations: parse_count("generations", DEFAULT_GENERATIONS)?,
+        fitness: fitness,
+        num_top: parse_count("top", DEFAULT_NUM_TOP_GENOMES)?,
+        path: matches.opt_str("o")
+    })
+}
+
+fn build_batch_settings(matches: &Matches) -> Result<BatchSettings, String> {
+    let directory = matches.opt_str("output-dir").unwrap();
+    if !Path::new(&directory).is_dir() {
+        return Err(format!("Not a directory: {}", directory));
+    }
+    let name_template = matches.opt_str("name").unwrap_or(DEFAULT_NAME_TEMPLATE.to_string());
+    if !name_template.contains("{genome}") && !name_template.contains("{index}") {
+        return Err(format!("Name must contain {{genome}} or {{index}}: {}", name_template));
+    }
+    let num_threads = match matches.opt_str("threads") {
+        Some(n_str) => match n_str.parse() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("Not a positive integer: {}", n_str))
+        },
+        None => DEFAULT_BATCH_THREADS
+    };
+    Ok(BatchSettings {
+        directory: directory,
+        name_template: name_template,
+        num_threads: num_threads
+    })
+}
+
+fn build_playlist_settings(matches: &Matches) -> Result<PlaylistSettings, String> {
+    let loops = match matches.opt_str("loops") {
+        Some(n_str) => match n_str.parse() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("Not a positive integer: {}", n_str))
+        },
+        None => 1
+    };
+    let crossfade_seconds = match matches.opt_str("crossfade") {
+        Some(seconds_str) => match seconds_str.parse() {
+            Ok(s) if s > 0.0 => s,
+            _ => return Err(format!("Not a positive number: {}", seconds_str))
+        },
+        None => DEFAULT_CROSSFADE_SECONDS
+    };
+    Ok(PlaylistSettings {
+        loops: loops,
+        crossfade_seconds: crossfade_seconds,
+        fullscreen: matches.opt_present("fullscreen"),
+        path: matches.opt_str("o")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_plasma_settings, create_options};
+
+    fn build(args: &[&str]) -> Result<(), String> {
+        let matches = create_options().parse(args).unwrap();
+        build_plasma_settings(matches, None).map(|_| ())
+    }
+
+    #[test]
+    fn test_color_cycling_conflicts() {
+        assert!(build(&["plasma", "-o", "x.gif", "--color-cycling"]).is_ok());
+        assert_eq!(build(&["plasma", "-o", "x.gif", "--color-cycling", "--palette-file", "pico-8"]),
+                   Err("--color-cycling and --palette-file can't be used together".to_string()));
+        assert_eq!(build(&["plasma", "-o", "x.gif", "--color-cycling", "--temporal-dither", "0.1"]),
+                   Err("--color-cycling and --temporal-dither can't be used together".to_string()));
+    }
+}
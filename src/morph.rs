@@ -0,0 +1,142 @@
+use color::{Color, ColorSpace, LinearColor};
+use color::colormapper::{build_palette, sample_gradient, ColorMapper};
+use file::encode_frames;
+use formulas::PlasmaFormulas;
+use genetics::Genome;
+use renderer::{render_frame, HeldPixels, Image};
+use settings::{MorphSettings, RenderingSettings};
+use std::fs::File;
+use std::io::Write;
+
+/*
+ * Animated transitions from one genome to another.
+ *
+ * Genomes aren't blended byte by byte, which would jump around as bytes cross thresholds.
+ * Instead, the decoded formula parameters are interpolated, and the two gradients are blended in
+ * the color space from the settings. Every frame shares one palette, which covers all of the
+ * blended gradients.
+ */
+
+// How many blends of the two gradients to precompute. Each frame uses the nearest one.
+const NUM_COLOR_STEPS: usize = 32;
+
+pub struct MorphRenderer {
+    from: PlasmaFormulas,
+    to: PlasmaFormulas,
+    color_mappers: Vec<ColorMapper>, // One per color step, from the first gradient to the second
+    dithering: bool
+}
+
+impl MorphRenderer {
+    pub fn new(from: &Genome, to: &Genome, settings: &RenderingSettings) -> MorphRenderer {
+        let from_samples = sample_gradient(&from.color, settings);
+        let to_samples = sample_gradient(&to.color, settings);
+        let blended_samples = blend_samples(&from_samples, &to_samples, settings.color_space);
+        let all_samples: Vec<_> = blended_samples.iter().flat_map(|s| s.iter().cloned()).collect();
+        let palette = build_palette(&all_samples, settings);
+        MorphRenderer {
+            from: PlasmaFormulas::from_chromosome(&from.pattern),
+            to: PlasmaFormulas::from_chromosome(&to.pattern),
+            color_mappers: blended_samples.iter().map(|samples|
+                ColorMapper::with_palette(samples, &palette, settings)
+            ).collect(),
+            dithering: settings.dithering
+        }
+    }
+
+    // Renders the plasma at a given time, partway from the first genome (amount = 0.0) to the
+    // second (amount = 1.0). For temporal dithering, give the pixels held from earlier frames.
+    pub fn render(&self, image: &mut Image, time: f32, amount: f32,
+                  held_pixels: Option<&mut HeldPixels>) {
+        let mut formulas = self.from.lerp(&self.to, amount);
+        let step = (amount*NUM_COLOR_STEPS as f32).round() as usize;
+        render_frame(image, time, &mut formulas, &self.color_mappers[step], self.dithering,
+                     held_pixels);
+    }
+
+    pub fn get_palette(&self) -> Vec<Color> {
+        self.color_mappers[0].get_palette()
+    }
+}
+
+// Blends two genomes' gradient samples, one blend per color step
+fn blend_samples(from: &[LinearColor], to: &[LinearColor],
+                 color_space: ColorSpace) -> Vec<Vec<LinearColor>> {
+    (0..NUM_COLOR_STEPS + 1).map(|step| {
+        let amount = step as f32/NUM_COLOR_STEPS as f32;
+        from.iter().zip(to.iter()).map(|(a, b)| a.mix(*b, amount, color_space)).collect()
+    }).collect()
+}
+
+// Renders a morph to a GIF file
+pub fn run_morph(from: &Genome, to: &Genome, settings: &RenderingSettings,
+                 morph_settings: &MorphSettings) -> Result<(), String> {
+    let renderer = MorphRenderer::new(from, to, settings);
+    let num_frames = (settings.frames_per_second*settings.loop_duration).round() as usize;
+    let mut held_pixels = settings.temporal_threshold.map(|_| HeldPixels::new());
+    let frames: Vec<Image> = (0..num_frames).map(|i| {
+        let mut image = Image::new(settings.width, settings.height);
+        let amount = morph_amount(i, num_frames, morph_settings.round_trip);
+        renderer.render(&mut image, i as f32/num_frames as f32, amount, held_pixels.as_mut());
+        image
+    }).collect();
+    let gif_bytes = encode_frames(&frames[..], renderer.get_palette(), settings);
+    File::create(&morph_settings.path).and_then(|mut file|
+        file.write_all(&gif_bytes[..])
+    ).map_err(|e| format!("Couldn't write to {}: {}", morph_settings.path, e))
+}
+
+/*
+ * How far along the morph a frame is, eased in and out so that it starts and stops gently.
+ *
+ * One way, the first frame is the first genome and the last frame is the second. A round trip
+ * reaches the second genome halfway through, then comes back, so that the GIF loops seamlessly.
+ */
+fn morph_amount(frame: usize, num_frames: usize, round_trip: bool) -> f32 {
+    let linear = if round_trip {
+        let position = frame as f32/num_frames as f32;
+        1.0 - (1.0 - 2.0*position).abs()
+    } else if num_frames > 1 {
+        frame as f32/(num_frames - 1) as f32
+    } else {
+        0.0
+    };
+    linear*linear*(3.0 - 2.0*linear)
+}
+
+#[cfg(test)]
+mod tests {
+    use color::{ColorSpace, LinearColor};
+    use super::{blend_samples, morph_amount, NUM_COLOR_STEPS};
+
+    #[test]
+    fn test_blend_samples() {
+        let black = LinearColor::new(0, 0, 0);
+        let white = LinearColor::new(65535, 65535, 65535);
+        for &color_space in [ColorSpace::LinearRgb, ColorSpace::Oklab].iter() {
+            let blends = blend_samples(&[black], &[white], color_space);
+            assert_eq!(blends.len(), NUM_COLOR_STEPS + 1);
+            assert_eq!(blends[0], vec![black]);
+            assert_eq!(blends[NUM_COLOR_STEPS], vec![white]);
+            assert_eq!(blends[NUM_COLOR_STEPS/2], vec![black.mix(white, 0.5, color_space)]);
+        }
+
+        // OKLab's halfway point looks halfway between black and white, so it's darker in linear
+        let oklab = blend_samples(&[black], &[white], ColorSpace::Oklab);
+        assert!(oklab[NUM_COLOR_STEPS/2][0].r < black.lerp(white, 0.5).r);
+    }
+
+    #[test]
+    fn test_morph_amount() {
+        // One way
+        assert_eq!(morph_amount(0, 10, false), 0.0);
+        assert_eq!(morph_amount(9, 10, false), 1.0);
+        assert!(morph_amount(2, 10, false) < morph_amount(3, 10, false));
+        assert_eq!(morph_amount(0, 1, false), 0.0);
+
+        // Round trip
+        assert_eq!(morph_amount(0, 10, true), 0.0);
+        assert_eq!(morph_amount(5, 10, true), 1.0);
+        assert!((morph_amount(2, 10, true) - morph_amount(8, 10, true)).abs() < 0.001);
+    }
+}
@@ -0,0 +1,375 @@
+use asyncrenderer::AsyncRenderer;
+use color::{Color, LinearColor, TransferFunction};
+use color::colormapper::fixed_palette;
+use color::palette::Palette;
+use color::palette::dither::{diffuse_errors, DitherPattern};
+use file::encode_indexed_frames;
+use genetics::Genome;
+use renderer::{Image, PlasmaRenderer};
+use sdl2;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::{self, PixelFormatEnum};
+use settings::{PlaylistSettings, RenderingSettings};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::time::Instant;
+
+/*
+ * Playing a list of genomes back to back.
+ *
+ * Each genome is shown for a few loops, then crossfades into the next one. The last genome fades
+ * back into the first, so the playlist repeats seamlessly, whether it's on screen or in a GIF.
+ */
+
+// How many frames to look at when choosing a GIF's palette, and how many pixels from them
+const NUM_PALETTE_FRAMES: usize = 16;
+const MAX_PALETTE_SAMPLES: usize = 16384;
+
+// When each genome is on screen
+struct Schedule {
+    num_genomes: usize,
+    show_seconds: f32, // How long each genome is shown, including its crossfade into the next
+    crossfade_seconds: f32
+}
+
+// What's on screen at one moment
+#[derive(Debug,PartialEq)]
+struct Slot {
+    genome: usize,
+    next: Option<(usize, f32)> // Genome being faded into, and how far along the crossfade is
+}
+
+impl Schedule {
+    fn new(num_genomes: usize, rendering_settings: &RenderingSettings,
+           playlist_settings: &PlaylistSettings) -> Schedule {
+        Schedule {
+            num_genomes: num_genomes,
+            show_seconds: rendering_settings.loop_duration*playlist_settings.loops as f32,
+            crossfade_seconds: playlist_settings.crossfade_seconds
+        }
+    }
+
+    fn duration(&self) -> f32 {
+        self.show_seconds*self.num_genomes as f32
+    }
+
+    fn at(&self, seconds: f32) -> Slot {
+        let position = seconds % self.duration();
+        let genome = ((position/self.show_seconds) as usize).min(self.num_genomes - 1);
+        let fade_position = position - genome as f32*self.show_seconds -
+            (self.show_seconds - self.crossfade_seconds);
+        let next = if self.num_genomes > 1 && fade_position >= 0.0 {
+            Some(((genome + 1) % self.num_genomes, fade_position/self.crossfade_seconds))
+        } else {
+            None
+        };
+        Slot { genome: genome, next: next }
+    }
+}
+
+// Blends two images in linear light, so that the midpoint isn't darker than either end
+fn crossfade(from: &Image, to: &Image, amount: f32, transfer_function: TransferFunction) -> Image {
+    let to_linear = |c: &[u8]| Color::new(c[0], c[1], c[2]).to_linear(transfer_function);
+    let mut image = Image::new(from.width, from.height);
+    for (i, (a, b)) in from.pixel_data.chunks(3).zip(to.pixel_data.chunks(3)).enumerate() {
+        let color = to_linear(a).lerp(to_linear(b), amount.min(1.0)).to_gamma(transfer_function);
+        image.plot(i % from.width, i / from.width, color);
+    }
+    image
+}
+
+// Renders a playlist to one GIF
+pub fn write_playlist(genomes: &[Genome], settings: &RenderingSettings,
+                      playlist_settings: &PlaylistSettings, path: &str) -> Result<(), String> {
+    let schedule = Schedule::new(genomes.len(), settings, playlist_settings);
+    let num_frames = (schedule.duration()*settings.frames_per_second).round() as usize;
+    let transfer_function = settings.transfer_function;
+
+    // Render in full color, and reduce the colors afterwards, since crossfades make new ones
+    let mut full_color_settings = settings.clone();
+    full_color_settings.dithering = false;
+    full_color_settings.palette_size = None;
+    let mut renderers: Vec<PlasmaRenderer> = genomes.iter().map(|genome|
+        PlasmaRenderer::new(genome, &full_color_settings)
+    ).collect();
+    let mut render = |frame: usize| {
+        let seconds = frame as f32*schedule.duration()/num_frames as f32;
+        let time = seconds/settings.loop_duration;
+        let slot = schedule.at(seconds);
+        let mut image = Image::new(settings.width, settings.height);
+        renderers[slot.genome].render(&mut image, time);
+        if let Some((next, amount)) = slot.next {
+            let mut next_image = Image::new(settings.width, settings.height);
+            renderers[next].render(&mut next_image, time);
+            image = crossfade(&image, &next_image, amount, transfer_function);
+        }
+        image
+    };
+
+    // Choose a palette from a sample of the frames, unless the settings give one
+    let palette = match fixed_palette(settings) {
+        Some(palette) => palette,
+        None => {
+            let mut samples = vec![];
+            let frame_step = (num_frames/NUM_PALETTE_FRAMES).max(1);
+            let pixel_step =
+                (settings.width*settings.height*NUM_PALETTE_FRAMES/MAX_PALETTE_SAMPLES).max(1);
+            for frame in (0..num_frames).filter(|i| i % frame_step == 0) {
+                let image = render(frame);
+                for pixel in image.pixel_data.chunks(3).step_by(pixel_step) {
+                    let color = Color::new(pixel[0], pixel[1], pixel[2]);
+                    samples.push(color.to_linear(transfer_function));
+                }
+            }
+            Palette::new(settings.palette_size.unwrap_or(255), &samples, settings.dithering,
+                         settings.color_space)
+        }
+    };
+
+    // Convert every frame to palette indexes, remembering what each color turned into
+    let mut dither_patterns: HashMap<(u8, u8, u8), DitherPattern> = HashMap::new();
+    let mut nearest_indexes: HashMap<(u8, u8, u8), usize> = HashMap::new();
+    let diffuses_errors = settings.dithering && settings.ditherer.diffuses_errors();
+    let (mut held_indexes, mut held_colors): (Vec<usize>, Vec<LinearColor>) = (vec![], vec![]);
+    let indexed_frames: Vec<Vec<u8>> = (0..num_frames).map(|frame| {
+        let image = render(frame);
+        let colors: Vec<_> = image.pixel_data.chunks(3).map(|pixel|
+            Color::new(pixel[0], pixel[1], pixel[2]).to_linear(transfer_function)
+        ).collect();
+        let mut indexes = if diffuses_errors {
+            diffuse_errors(&colors, image.width, &palette, settings.ditherer)
+        } else {
+            image.pixel_data.chunks(3).zip(colors.iter()).enumerate().map(|(i, (pixel, &color))| {
+                let rgb = (pixel[0], pixel[1], pixel[2]);
+                if settings.dithering {
+                    let pattern = *dither_patterns.entry(rgb).or_insert_with(||
+                        palette.get_dither_pattern(color)
+                    );
+                    pattern.get_palette_index(i % image.width, i / image.width, settings.ditherer)
+                } else {
+                    *nearest_indexes.entry(rgb).or_insert_with(|| palette.get_nearest_index(color))
+                }
+            }).collect()
+        };
+
+        // With temporal dithering, pixels keep their colors until the colors they were chosen for
+        // are too far from the ones they should be now
+        if let Some(threshold) = settings.temporal_threshold {
+            if held_colors.is_empty() {
+                held_colors = colors.clone();
+            } else {
+                for i in 0..indexes.len() {
+                    if held_colors[i].distance(colors[i], settings.color_space) <= threshold {
+                        indexes[i] = held_indexes[i];
+                    } else {
+                        held_colors[i] = colors[i];
+                    }
+                }
+            }
+            held_indexes = indexes.clone();
+        }
+        indexes.into_iter().map(|i| i as u8).collect()
+    }).collect();
+
+    // The GIF's frame delay is based on its loop duration, which is now the whole playlist
+    let mut gif_settings = settings.clone();
+    gif_settings.loop_duration = schedule.duration();
+    let gamma_palette = palette.colors.iter().map(|c| c.to_gamma(transfer_function)).collect();
+    let gif_bytes = encode_indexed_frames(indexed_frames, vec![gamma_palette], &gif_settings);
+    File::create(path).and_then(|mut file|
+        file.write_all(&gif_bytes[..])
+    ).map_err(|e| format!("Couldn't write to {}: {}", path, e))
+}
+
+// A renderer for one of the genomes, and the latest frame it has finished
+struct Player {
+    genome: Option<usize>,
+    renderer: AsyncRenderer,
+    image: Option<Image>
+}
+
+impl Player {
+    fn new(settings: &RenderingSettings) -> Player {
+        Player {
+            genome: None,
+            renderer: AsyncRenderer::new(settings),
+            image: None
+        }
+    }
+
+    fn load(&mut self, genomes: &[Genome], genome: usize) {
+        self.genome = Some(genome);
+        self.renderer.set_genome(&genomes[genome]);
+        self.image = None;
+    }
+}
+
+// Shows a playlist in a window, until the user quits
+pub fn show_playlist(genomes: &[Genome], settings: &RenderingSettings,
+                     playlist_settings: &PlaylistSettings) {
+    // Initialize SDL structs
+    let sdl = sdl2::init().unwrap();
+    let video = sdl.video().unwrap();
+    let mut window_builder = video.window(
+        "plasma",
+        settings.width as u32,
+        settings.height as u32
+    );
+    window_builder.resizable();
+    if playlist_settings.fullscreen {
+        window_builder.fullscreen_desktop();
+        sdl.mouse().show_cursor(false);
+    }
+    let window = window_builder.build().unwrap();
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGB24, settings.width as u32, settings.height as u32
+    ).unwrap();
+    let mut event_pump = sdl.event_pump().unwrap();
+
+    // Init screen to black
+    canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.present();
+
+    // Two players: one for the genome on screen, and one for the genome it's fading into
+    let schedule = Schedule::new(genomes.len(), settings, playlist_settings);
+    let mut players = [Player::new(settings), Player::new(settings)];
+    let frame_delay_seconds = 1.0/settings.frames_per_second;
+    let clock_instant = Instant::now();
+    let mut frame_seconds = 0.0;
+    let mut frame_slot = schedule.at(frame_seconds);
+    let mut needs_request = true;
+
+    loop {
+        // Make sure each genome that's needed for the next frame has a player
+        if needs_request {
+            let mut wanted = vec![frame_slot.genome];
+            if let Some((next, _)) = frame_slot.next {
+                wanted.push(next);
+            }
+            for &genome in wanted.iter() {
+                if !players.iter().any(|p| p.genome == Some(genome)) {
+                    let free_player = players.iter_mut().find(|p|
+                        p.genome.map_or(true, |g| !wanted.contains(&g))
+                    ).unwrap();
+                    free_player.load(genomes, genome);
+                }
+            }
+            let time = frame_seconds/settings.loop_duration;
+            for player in players.iter_mut() {
+                if player.genome.map_or(false, |g| wanted.contains(&g)) {
+                    player.image = None;
+                    player.renderer.render(settings.width, settings.height, time);
+                }
+            }
+            needs_request = false;
+        }
+
+        // Collect finished frames. Once every frame that's needed is in, and it's due, show it.
+        for player in players.iter_mut() {
+            if let Some(image) = player.renderer.get_image() {
+                player.image = Some(image);
+            }
+        }
+        let now_seconds = {
+            let duration = clock_instant.elapsed();
+            duration.as_secs() as f32 + (duration.subsec_nanos() as f32/1_000_000_000.0)
+        };
+        let image = {
+            let image_of = |genome| players.iter().find(|p| p.genome == Some(genome))
+                .and_then(|p| p.image.as_ref());
+            match (image_of(frame_slot.genome), frame_slot.next) {
+                (Some(image), None) if now_seconds >= frame_seconds => Some(image.clone()),
+                (Some(image), Some((next, amount))) if now_seconds >= frame_seconds => {
+                    image_of(next).map(|next_image|
+                        crossfade(image, next_image, amount, settings.transfer_function)
+                    )
+                },
+                _ => None
+            }
+        };
+        if let Some(image) = image {
+            texture.update(None, &image.pixel_data[..], image.width*3).unwrap();
+            canvas.clear();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
+
+            // Start on the next frame, skipping ahead if we've fallen behind
+            frame_seconds = (frame_seconds + frame_delay_seconds).max(now_seconds);
+            frame_slot = schedule.at(frame_seconds);
+            needs_request = true;
+        }
+
+        // Process events, waiting a little while if there aren't any
+        let event_vec = if let Some(event) = event_pump.wait_event_timeout(5) {
+            vec![event]
+        } else {
+            vec![]
+        };
+        for event in event_vec.into_iter().chain(event_pump.poll_iter()) {
+            match event {
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } | Event::Quit { .. } => {
+                    return;
+                }
+                _ => ()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color::{Color, TransferFunction};
+    use renderer::Image;
+    use settings::{PlaylistSettings, RenderingSettings};
+    use super::{crossfade, Schedule, Slot};
+
+    #[test]
+    fn test_schedule() {
+        let mut rendering_settings = RenderingSettings::for_test();
+        rendering_settings.frames_per_second = 10.0;
+        rendering_settings.loop_duration = 5.0;
+        let playlist_settings = PlaylistSettings {
+            loops: 2,
+            crossfade_seconds: 2.0,
+            fullscreen: false,
+            path: None
+        };
+        let schedule = Schedule::new(3, &rendering_settings, &playlist_settings);
+        assert_eq!(schedule.duration(), 30.0);
+        assert_eq!(schedule.at(0.0), Slot { genome: 0, next: None });
+        assert_eq!(schedule.at(7.9), Slot { genome: 0, next: None });
+        assert_eq!(schedule.at(9.0), Slot { genome: 0, next: Some((1, 0.5)) });
+        assert_eq!(schedule.at(10.0), Slot { genome: 1, next: None });
+        assert_eq!(schedule.at(29.0), Slot { genome: 2, next: Some((0, 0.5)) }); // Wraps around
+        assert_eq!(schedule.at(30.0), Slot { genome: 0, next: None });
+
+        // There's nothing to fade into with only one genome
+        let schedule = Schedule::new(1, &rendering_settings, &playlist_settings);
+        assert_eq!(schedule.at(9.0), Slot { genome: 0, next: None });
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let solid_image = |color: Color| {
+            let mut image = Image::new(2, 2);
+            for (x, y) in vec![(0, 0), (0, 1), (1, 0), (1, 1)] {
+                image.plot(x, y, color);
+            }
+            image
+        };
+        let black = solid_image(Color::new(0, 0, 0));
+        let white = solid_image(Color::new(255, 255, 255));
+        let srgb = TransferFunction::Srgb;
+        assert_eq!(crossfade(&black, &white, 0.0, srgb).pixel_data, black.pixel_data);
+        assert_eq!(crossfade(&black, &white, 1.0, srgb).pixel_data, white.pixel_data);
+
+        // Halfway between black and white is brighter than 50% gray, in linear light
+        let gray = crossfade(&black, &white, 0.5, srgb);
+        assert!(gray.pixel_data.iter().all(|&c| c > 180 && c < 200));
+    }
+}
@@ -0,0 +1,118 @@
+use renderer::Image;
+
+/*
+ * A minimal PNG encoder for still images, like thumbnails and swatches.
+ *
+ * Pixel data is stored in uncompressed deflate blocks. This makes for bigger files than a real
+ * compressor would, but the images we write this way are small, and it saves us a dependency.
+ */
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const MAX_STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+pub fn encode_png(image: &Image) -> Vec<u8> {
+    let mut output = SIGNATURE.to_vec();
+
+    // Header: dimensions, 8 bits per channel, RGB, no interlacing
+    let mut header = vec![];
+    push_u32(&mut header, image.width as u32);
+    push_u32(&mut header, image.height as u32);
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut output, b"IHDR", &header);
+
+    // Image data: each row of pixels is preceded by a filter type byte (0 = unfiltered)
+    let mut raw = Vec::with_capacity((image.width*3 + 1)*image.height);
+    for row in image.pixel_data.chunks(image.width*3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut output, b"IDAT", &zlib_store(&raw));
+
+    write_chunk(&mut output, b"IEND", &[]);
+    output
+}
+
+fn push_u32(output: &mut Vec<u8>, value: u32) {
+    output.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+fn write_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    push_u32(output, data.len() as u32);
+    let crc_start = output.len();
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(data);
+    let crc = crc32(&output[crc_start..]);
+    push_u32(output, crc);
+}
+
+// Wraps data in a zlib stream without compressing it
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut output = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        output.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]); // A single, empty, final block
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let len = block.len() as u16;
+        output.push(if is_final { 1 } else { 0 });
+        output.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        output.extend_from_slice(block);
+    }
+    push_u32(&mut output, adler32(data));
+    output
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use renderer::Image;
+    use super::{adler32, crc32, encode_png, zlib_store, SIGNATURE};
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn test_zlib_store() {
+        let data: Vec<u8> = (0..70000).map(|i| i as u8).collect();
+        let stream = zlib_store(&data);
+
+        // Two blocks: a full one, then a final one with the remainder
+        assert_eq!(stream.len(), 2 + (5 + 65535) + (5 + 4465) + 4);
+        assert_eq!(&stream[2..7], &[0, 0xFF, 0xFF, 0, 0]);
+        assert_eq!(&stream[65542..65547], &[1, 0x71, 0x11, 0x8E, 0xEE]);
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let mut image = Image::new(3, 2);
+        image.pixel_data[0] = 255;
+        let png = encode_png(&image);
+        assert_eq!(&png[..8], &SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 3, 0, 0, 0, 2]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+}
@@ -0,0 +1,334 @@
+extern crate rand;
+
+use color::colormapper::{lookup_index, sample_gradient};
+use distance::Features;
+use formulas::PlasmaFormulas;
+use genetics::Genome;
+use renderer::{get_positions, Image};
+use self::rand::Rng;
+use session::{Judgment, Verdict};
+use settings::RenderingSettings;
+use std::collections::HashMap;
+
+/*
+ * A model of what the user likes, learned from their approvals and rejections.
+ *
+ * Each genome is described by a handful of numbers: its formula parameters, some statistics of
+ * its gradient, and some statistics of a tiny rendering (how bright, contrasty, colorful and busy
+ * it is). A logistic regression on those numbers predicts how likely the user is to approve it.
+ * The model is used to pre-screen offspring: breed a few, and show the one it likes best. The
+ * tiny rendering looks colors up straight from the gradient, without building a palette, so that
+ * screening a whole gallery of offspring stays quick.
+ */
+
+// The model isn't trusted until it has seen this many judgments, including both likes and dislikes
+const MIN_TRAINING_EXAMPLES: usize = 10;
+
+// How many offspring to breed for each one that's shown
+const NUM_SCREENED_CANDIDATES: usize = 8;
+
+const THUMBNAIL_SIZE: usize = 16;
+
+const LEARNING_RATE: f32 = 0.1;
+const NUM_TRAINING_ITERATIONS: usize = 300;
+const REGULARIZATION: f32 = 0.01;
+
+// Logistic regression on standardized features
+pub struct LogisticRegression {
+    means: Vec<f32>,
+    std_devs: Vec<f32>,
+    weights: Vec<f32>,
+    bias: f32
+}
+
+impl LogisticRegression {
+    // Fits the model to (features, label) examples with batch gradient descent.
+    // Labels range from 0.0 (disliked) to 1.0 (liked).
+    pub fn fit(examples: &[(Vec<f32>, f32)]) -> LogisticRegression {
+        let num_features = examples[0].0.len();
+        let num_examples = examples.len() as f32;
+
+        // Standardize features, so they all get a fair share of the regularization
+        let means: Vec<f32> = (0..num_features).map(|i|
+            examples.iter().map(|e| e.0[i]).sum::<f32>()/num_examples
+        ).collect();
+        let std_devs: Vec<f32> = (0..num_features).map(|i| {
+            let squared_errors = examples.iter().map(|e| (e.0[i] - means[i]).powi(2));
+            (squared_errors.sum::<f32>()/num_examples).sqrt().max(1e-6)
+        }).collect();
+        let mut model = LogisticRegression {
+            means: means,
+            std_devs: std_devs,
+            weights: vec![0.0; num_features],
+            bias: 0.0
+        };
+        let standardized: Vec<(Vec<f32>, f32)> = examples.iter().map(|&(ref features, label)|
+            (model.standardize(features), label)
+        ).collect();
+
+        for _ in 0..NUM_TRAINING_ITERATIONS {
+            let mut weight_gradients: Vec<f32> = model.weights.iter().map(|w|
+                REGULARIZATION*w
+            ).collect();
+            let mut bias_gradient = 0.0;
+            for &(ref features, label) in standardized.iter() {
+                let error = model.predict_standardized(features) - label;
+                for (gradient, x) in weight_gradients.iter_mut().zip(features.iter()) {
+                    *gradient += error*x/num_examples;
+                }
+                bias_gradient += error/num_examples;
+            }
+            for (w, gradient) in model.weights.iter_mut().zip(weight_gradients.iter()) {
+                *w -= LEARNING_RATE*gradient;
+            }
+            model.bias -= LEARNING_RATE*bias_gradient;
+        }
+        model
+    }
+
+    // Probability that something with these features is liked
+    pub fn predict(&self, features: &[f32]) -> f32 {
+        self.predict_standardized(&self.standardize(features))
+    }
+
+    fn standardize(&self, features: &[f32]) -> Vec<f32> {
+        let scales = self.means.iter().zip(self.std_devs.iter());
+        features.iter().zip(scales).map(|(x, (mean, std_dev))| (x - mean)/std_dev).collect()
+    }
+
+    fn predict_standardized(&self, features: &[f32]) -> f32 {
+        let weighted_sum: f32 = self.weights.iter().zip(features.iter()).map(|(w, x)| w*x).sum();
+        1.0/(1.0 + (-(weighted_sum + self.bias)).exp())
+    }
+}
+
+pub struct PreferenceModel {
+    exploration_rate: f64,
+    feature_cache: HashMap<String, Vec<f32>>, // Keyed by genome string, for judged genomes only
+    regression: Option<LogisticRegression>,
+    settings: RenderingSettings // For looking up gradients
+}
+
+impl PreferenceModel {
+    // exploration_rate is the probability of showing an offspring without pre-screening it,
+    // so that the user keeps seeing things the model wouldn't have picked
+    pub fn new(settings: &RenderingSettings, exploration_rate: f64) -> PreferenceModel {
+        PreferenceModel {
+            exploration_rate: exploration_rate,
+            feature_cache: HashMap::new(),
+            regression: None,
+            settings: settings.clone()
+        }
+    }
+
+    // Retrains from scratch on every judgment so far. Since features are cached, this is cheap
+    // enough to do after every judgment, and it keeps the model in sync with undo and redo.
+    // Genomes that are no longer judged, like ones whose judgments were undone, leave the cache.
+    pub fn train(&mut self, history: &[Judgment]) {
+        let mut labeled = vec![];
+        for judgment in history {
+            match judgment.verdict {
+                Verdict::Approved(_) => labeled.push((&judgment.genome, 1.0)),
+                Verdict::Rejected => labeled.push((&judgment.genome, 0.0)),
+                Verdict::Preferred(ref loser) => {
+                    labeled.push((&judgment.genome, 1.0));
+                    labeled.push((loser, 0.0));
+                }
+            }
+        }
+        let has_both_labels = labeled.iter().any(|&(_, l)| l == 1.0) &&
+                              labeled.iter().any(|&(_, l)| l == 0.0);
+        if labeled.len() < MIN_TRAINING_EXAMPLES || !has_both_labels {
+            self.regression = None;
+            return;
+        }
+        let mut feature_cache = HashMap::new();
+        let examples: Vec<(Vec<f32>, f32)> = labeled.into_iter().map(|(genome, label)| {
+            let id = genome.to_base64();
+            let features = feature_cache.get(&id).or_else(|| self.feature_cache.get(&id)).cloned()
+                .unwrap_or_else(|| self.features(genome));
+            feature_cache.insert(id, features.clone());
+            (features, label)
+        }).collect();
+        self.feature_cache = feature_cache;
+        self.regression = Some(LogisticRegression::fit(&examples));
+    }
+
+    // Probability that the user will like a genome, or None if there isn't enough to go on yet
+    #[cfg(test)]
+    pub fn predict(&self, genome: &Genome) -> Option<f32> {
+        self.regression.as_ref().map(|r| r.predict(&self.features(genome)))
+    }
+
+    // Breeds a few offspring, and returns the one the model likes best. Sometimes, and always
+    // while the model is untrained, returns the first one instead.
+    pub fn screen<T, F: FnMut() -> (Genome, T)>(&self, mut breed: F) -> (Genome, T) {
+        let first = breed();
+        let exploring = rand::thread_rng().gen_range(0.0, 1.0) < self.exploration_rate;
+        let regression = match self.regression {
+            Some(ref r) if !exploring => r,
+            _ => return first
+        };
+        let mut best_score = regression.predict(&self.features(&first.0));
+        let mut best = first;
+        for _ in 1..NUM_SCREENED_CANDIDATES {
+            let candidate = breed();
+            let score = regression.predict(&self.features(&candidate.0));
+            if score > best_score {
+                best = candidate;
+                best_score = score;
+            }
+        }
+        best
+    }
+
+    fn features(&self, genome: &Genome) -> Vec<f32> {
+        let mut features = vec![];
+
+        // Genes, decoded
+        let decoded = Features::new(genome);
+        for formula in decoded.formulas.iter() {
+            features.extend_from_slice(formula);
+        }
+        let num_colors = decoded.colors.len() as f32;
+        for channel in 0..3 {
+            let mean = decoded.colors.iter().map(|c| c[channel]).sum::<f32>()/num_colors;
+            let squared_errors = decoded.colors.iter().map(|c| (c[channel] - mean).powi(2));
+            features.push(mean);
+            features.push((squared_errors.sum::<f32>()/num_colors).sqrt());
+        }
+
+        // What it actually looks like
+        let mut formulas = PlasmaFormulas::from_chromosome(&genome.pattern);
+        formulas.set_time(0.0);
+        let positions = get_positions(&formulas, THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+        let gradient_samples = sample_gradient(&genome.color, &self.settings);
+        let mut image = Image::new(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+        for (i, &position) in positions.iter().enumerate() {
+            let color = gradient_samples[lookup_index(position)];
+            image.plot(i % THUMBNAIL_SIZE, i / THUMBNAIL_SIZE,
+                       color.to_gamma(self.settings.transfer_function));
+        }
+        features.extend_from_slice(&image_statistics(&image));
+        features
+    }
+}
+
+// Brightness, contrast, colorfulness and busyness of an image, each from 0.0 to 1.0
+fn image_statistics(image: &Image) -> [f32; 4] {
+    let pixels: Vec<&[u8]> = image.pixel_data.chunks(3).collect();
+    let luma: Vec<f32> = pixels.iter().map(|p|
+        (0.299*p[0] as f32 + 0.587*p[1] as f32 + 0.114*p[2] as f32)/255.0
+    ).collect();
+    let num_pixels = pixels.len() as f32;
+    let brightness = luma.iter().sum::<f32>()/num_pixels;
+    let contrast = (luma.iter().map(|l| (l - brightness).powi(2)).sum::<f32>()/num_pixels).sqrt();
+    let colorfulness = pixels.iter().map(|p| {
+        let max = *p.iter().max().unwrap() as f32;
+        let min = *p.iter().min().unwrap() as f32;
+        (max - min)/255.0
+    }).sum::<f32>()/num_pixels;
+
+    // Average difference between horizontally neighboring pixels
+    let mut total_difference = 0.0;
+    let mut num_differences = 0;
+    for row in luma.chunks(image.width) {
+        for pair in row.windows(2) {
+            total_difference += (pair[1] - pair[0]).abs();
+            num_differences += 1;
+        }
+    }
+    let busyness = if num_differences > 0 { total_difference/num_differences as f32 } else { 0.0 };
+
+    [brightness, contrast, colorfulness, busyness]
+}
+
+#[cfg(test)]
+mod tests {
+    use color::colormapper::NUM_COLOR_GENES;
+    use genetics::{Gene, Genome};
+    use session::{Judgment, Verdict};
+    use settings::RenderingSettings;
+    use super::{LogisticRegression, PreferenceModel};
+
+    // A random genome whose colors are all about as bright as brightness (0-255)
+    fn rand_genome_with_brightness(brightness: u8) -> Genome {
+        let mut genome = Genome::rand();
+        for (i, gene) in genome.color.genes.iter_mut().enumerate() {
+            let b = brightness.saturating_add(gene.data[1] % 16);
+            *gene = Gene { data: vec![255, b, b, b, (i*32) as u8] };
+        }
+        genome
+    }
+
+    #[test]
+    fn test_logistic_regression() {
+        // Liked if x + y > 1, with an irrelevant third feature
+        let examples: Vec<(Vec<f32>, f32)> = (0..100).map(|i| {
+            let (x, y, z) = ((i % 10) as f32/10.0, (i/10) as f32/10.0, (i % 7) as f32);
+            (vec![x, y, z], if x + y > 1.0 { 1.0 } else { 0.0 })
+        }).collect();
+        let model = LogisticRegression::fit(&examples);
+        assert!(model.predict(&[0.9, 0.9, 3.0]) > 0.8);
+        assert!(model.predict(&[0.1, 0.2, 3.0]) < 0.2);
+        assert!(model.predict(&[0.8, 0.8, 0.0]) > model.predict(&[0.5, 0.6, 0.0]));
+    }
+
+    #[test]
+    fn test_preference_model_untrained() {
+        let mut model = PreferenceModel::new(&RenderingSettings::for_test(), 0.0);
+        let genome = rand_genome_with_brightness(128);
+        assert_eq!(model.predict(&genome), None);
+
+        // Too few judgments, or only one kind, aren't enough to learn from
+        let approvals: Vec<_> = (0..20).map(|_| Judgment {
+            genome: rand_genome_with_brightness(128),
+            verdict: Verdict::Approved(3)
+        }).collect();
+        model.train(&approvals[..3]);
+        assert_eq!(model.predict(&genome), None);
+        model.train(&approvals);
+        assert_eq!(model.predict(&genome), None);
+
+        // An untrained model doesn't screen anything out
+        let mut count = 0;
+        model.screen(|| { count += 1; (genome.clone(), ()) });
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_preference_model_learns() {
+        // The user likes bright plasmas, and dislikes dark ones
+        let mut history = vec![];
+        for _ in 0..15 {
+            history.push(Judgment {
+                genome: rand_genome_with_brightness(200),
+                verdict: Verdict::Approved(4)
+            });
+            history.push(Judgment {
+                genome: rand_genome_with_brightness(20),
+                verdict: Verdict::Rejected
+            });
+        }
+        let mut model = PreferenceModel::new(&RenderingSettings::for_test(), 0.0);
+        model.train(&history);
+        let bright = model.predict(&rand_genome_with_brightness(190)).unwrap();
+        let dark = model.predict(&rand_genome_with_brightness(30)).unwrap();
+        assert!(bright > 0.5 && dark < 0.5);
+
+        // Screening should pick out a bright plasma from mostly dark ones
+        let mut count = 0;
+        let (picked, brightness) = model.screen(|| {
+            count += 1;
+            let brightness = if count == 5 { 190 } else { 30 };
+            (rand_genome_with_brightness(brightness), brightness)
+        });
+        assert_eq!(brightness, 190);
+        assert_eq!(picked.color.genes.len(), NUM_COLOR_GENES);
+
+        // Undoing judgments drops their genomes' features
+        assert_eq!(model.feature_cache.len(), 30);
+        model.train(&history[..20]);
+        assert_eq!(model.feature_cache.len(), 20);
+    }
+}
@@ -6,6 +6,7 @@ use genetics::Genome;
 use settings::RenderingSettings;
 use std::f32;
 
+#[derive(Clone)]
 pub struct Image {
     pub width: usize,
     pub height: usize,
@@ -15,7 +16,21 @@ pub struct Image {
 pub struct PlasmaRenderer {
     dithering: bool,
     color_mapper: ColorMapper,
-    formulas: PlasmaFormulas
+    formulas: PlasmaFormulas,
+    held_pixels: Option<HeldPixels> // Only for temporal dithering
+}
+
+/*
+ * Pixels held over from earlier frames, for temporal dithering.
+ *
+ * Dithering patterns shift whenever the colors under them do, so pixels flicker even where the
+ * plasma barely changes, and every frame of a GIF has to be stored almost in full. Instead, each
+ * pixel keeps its color until the gradient position under it has moved to a color that's more
+ * than the temporal threshold away from the one the pixel's color was chosen for.
+ */
+pub struct HeldPixels {
+    image: Image,
+    positions: Vec<f32> // The gradient position each pixel's color was chosen for
 }
 
 impl Image {
@@ -35,6 +50,34 @@ impl Image {
     }
 }
 
+impl HeldPixels {
+    pub fn new() -> HeldPixels {
+        HeldPixels {
+            image: Image::new(0, 0),
+            positions: vec![]
+        }
+    }
+
+    // Replaces the pixels of a new frame with held ones, where they're close enough, and holds
+    // the rest
+    fn apply(&mut self, image: &mut Image, positions: &[f32], color_mapper: &ColorMapper) {
+        if self.image.width != image.width || self.image.height != image.height {
+            self.image = image.clone();
+            self.positions = positions.to_vec();
+            return;
+        }
+        for (i, &position) in positions.iter().enumerate() {
+            let pixel = i*3..(i + 1)*3;
+            if color_mapper.is_close(position, self.positions[i]) {
+                image.pixel_data[pixel.clone()].copy_from_slice(&self.image.pixel_data[pixel]);
+            } else {
+                self.image.pixel_data[pixel.clone()].copy_from_slice(&image.pixel_data[pixel]);
+                self.positions[i] = position;
+            }
+        }
+    }
+}
+
 impl PlasmaRenderer {
     pub fn new(genome: &Genome, settings: &RenderingSettings) -> PlasmaRenderer {
         let color_mapper = ColorMapper::new(&genome.color, &settings);
@@ -42,34 +85,66 @@ impl PlasmaRenderer {
         PlasmaRenderer {
             color_mapper: color_mapper,
             dithering: settings.dithering,
-            formulas: formulas
+            formulas: formulas,
+            held_pixels: settings.temporal_threshold.map(|_| HeldPixels::new())
         }
     }
 
     pub fn render(&mut self, image: &mut Image, time: f32) {
-        // Scale screen coordinates so the smaller dimension ranges from -1.0 to 1.0
-        let scale_mul = 2.0/((image.width as f32).min(image.height as f32));
-        let scale_x_offset = -(image.width as f32)/2.0*scale_mul;
-        let scale_y_offset = -(image.height as f32)/2.0*scale_mul;
-        let adj_time = time.wrap();
-        self.formulas.set_time(adj_time);
-        for y in 0..image.height {
-            for x in 0..image.width {
-                let value = self.formulas.get_value(
-                    scale_mul*(x as f32) + scale_x_offset,
-                    scale_mul*(y as f32) + scale_y_offset
-                );
-                let color = if self.dithering {
-                    self.color_mapper.get_dithered_color(value, x, y)
-                } else {
-                    self.color_mapper.get_nearest_color(value)
-                };
-                image.plot(x, y, color);
-            }
-        }
+        render_frame(image, time, &mut self.formulas, &self.color_mapper, self.dithering,
+                     self.held_pixels.as_mut());
     }
 
     pub fn get_palette(&self) -> Vec<Color> {
         self.color_mapper.get_palette()
     }
 }
+
+// Evaluates a plasma's formulas for every pixel, row by row, giving positions along its gradient
+pub fn get_positions(formulas: &PlasmaFormulas, width: usize, height: usize) -> Vec<f32> {
+    // Scale screen coordinates so the smaller dimension ranges from -1.0 to 1.0
+    let scale_mul = 2.0/((width as f32).min(height as f32));
+    let scale_x_offset = -(width as f32)/2.0*scale_mul;
+    let scale_y_offset = -(height as f32)/2.0*scale_mul;
+    (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| formulas.get_value(
+        scale_mul*(x as f32) + scale_x_offset,
+        scale_mul*(y as f32) + scale_y_offset
+    )).collect()
+}
+
+// Draws one frame of a plasma, given its formulas and colors. For temporal dithering, give the
+// pixels held from earlier frames.
+pub fn render_frame(image: &mut Image, time: f32, formulas: &mut PlasmaFormulas,
+                    color_mapper: &ColorMapper, dithering: bool,
+                    held_pixels: Option<&mut HeldPixels>) {
+    // With color cycling, the pattern stays where it is at time 0, and the gradient moves instead
+    let adj_time = time.wrap();
+    let values: Vec<f32> = if color_mapper.cycles_colors() {
+        formulas.set_time(0.0);
+        get_positions(formulas, image.width, image.height).iter().map(|v| v + adj_time).collect()
+    } else {
+        formulas.set_time(adj_time);
+        get_positions(formulas, image.width, image.height)
+    };
+    let width = image.width;
+
+    if dithering && color_mapper.diffuses_errors() {
+        // Error diffusion needs the whole frame at once
+        for (i, color) in color_mapper.get_diffused_colors(&values, width).into_iter().enumerate() {
+            image.plot(i % width, i / width, color);
+        }
+    } else {
+        for (i, &value) in values.iter().enumerate() {
+            let (x, y) = (i % width, i / width);
+            let color = if dithering {
+                color_mapper.get_dithered_color(value, x, y)
+            } else {
+                color_mapper.get_nearest_color(value)
+            };
+            image.plot(x, y, color);
+        }
+    }
+    if let Some(held_pixels) = held_pixels {
+        held_pixels.apply(image, &values, color_mapper);
+    }
+}
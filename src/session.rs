@@ -0,0 +1,470 @@
+extern crate rustc_serialize;
+
+use color::{Color, ColorSpace, TransferFunction};
+use color::palette::dither::Ditherer;
+use color::palette::fixed::{color_from_hex, color_to_hex};
+use color::stops;
+use genetics::{Genome, Population};
+use lineage::Lineage;
+use self::rustc_serialize::json::{Json, ToJson};
+use settings::RenderingSettings;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+
+/*
+ * Saving and resuming breeding sessions.
+ *
+ * A session is everything needed to pick up where the user left off: the population and its
+ * ratings, the genome on screen, what the user has approved or rejected so far, the family tree
+ * of approved genomes, and the rendering settings. Sessions are stored as JSON, with genomes in
+ * their usual Base64 form.
+ */
+
+// Bumped whenever the format changes, so that older builds refuse sessions they would misread.
+// Newer builds read older sessions, filling in whatever they're missing with the old behavior.
+// 1: genome, population, history and rendering settings
+// 2: lineage
+// 3: hall of fame and novelty search archive
+// 4: side-by-side preferences in the history
+// 5: color space, transfer function, palette, gradient, ditherer, temporal dithering, color
+//    cycling and local palette settings
+const SESSION_VERSION: u64 = 5;
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Verdict {
+    Approved(u8), // Rating from 1 to 5
+    Rejected,
+    Preferred(Genome) // Picked over the given genome in a side-by-side comparison
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Judgment {
+    pub genome: Genome,
+    pub verdict: Verdict
+}
+
+pub struct Session {
+    pub genome: Genome,
+    pub population: Population,
+    pub history: Vec<Judgment>,
+    pub lineage: Lineage,
+    pub rendering: RenderingSettings
+}
+
+impl Session {
+    pub fn load(path: &str) -> Result<Session, String> {
+        let mut text = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut text)).map_err(|e|
+            format!("Couldn't read from {}: {}", path, e)
+        )?;
+        let json = Json::from_str(&text).map_err(|e|
+            format!("Couldn't parse session {}: {}", path, e)
+        )?;
+        Session::from_json(&json).map_err(|e| format!("Bad session {}: {}", path, e))
+    }
+
+    // Writes to a temporary file first, so that a crash can't leave a half-written session
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let temp_path = format!("{}.tmp", path);
+        File::create(&temp_path).and_then(|mut file| {
+            write!(file, "{}", self.to_json().pretty())
+        }).and_then(|_| {
+            fs::rename(&temp_path, path)
+        }).map_err(|e| format!("Couldn't write to {}: {}", path, e))
+    }
+
+    fn from_json(json: &Json) -> Result<Session, String> {
+        match json.find("version").and_then(|v| v.as_u64()) {
+            Some(v) if 1 <= v && v <= SESSION_VERSION => (),
+            Some(v) => return Err(format!("unsupported version {} (this build reads up to {})",
+                                          v, SESSION_VERSION)),
+            None => return Err("missing version".to_string())
+        }
+        let genome = genome_from_json(json.find("genome"))?;
+
+        let members = json.find("population").and_then(|p| p.as_array()).ok_or(
+            "missing population"
+        )?;
+        let mut population = Population::new(members.len());
+        for member in members {
+            let genome = genome_from_json(member.find("genome"))?;
+            let fitness = member.find("fitness").and_then(|f| f.as_f64()).ok_or(
+                "population member is missing fitness"
+            )?;
+            population.add_with_fitness(genome, fitness as f32);
+        }
+
+        // Sessions saved before immigration and novelty search existed don't have these
+        if let Some(members) = json.find("hall_of_fame") {
+            let members = members.as_array().ok_or("hall of fame must be a list")?;
+            for member in members {
+                let genome = genome_from_json(member.find("genome"))?;
+                let fitness = member.find("fitness").and_then(|f| f.as_f64()).ok_or(
+                    "hall of fame member is missing fitness"
+                )?;
+                population.add_to_hall_of_fame(genome, fitness as f32);
+            }
+        }
+        if let Some(genomes) = json.find("archive") {
+            let genomes = genomes.as_array().ok_or("archive must be a list")?;
+            for genome in genomes {
+                population.remember(genome_from_json(Some(genome))?);
+            }
+        }
+
+        let judgments = json.find("history").and_then(|h| h.as_array()).ok_or(
+            "missing history"
+        )?;
+        let mut history = vec![];
+        for judgment in judgments {
+            let genome = genome_from_json(judgment.find("genome"))?;
+            let verdict = match judgment.find("verdict").and_then(|v| v.as_string()) {
+                Some("approved") => {
+                    let rating = judgment.find("rating").and_then(|r| r.as_u64()).ok_or(
+                        "approval is missing rating"
+                    )?;
+                    Verdict::Approved(rating as u8)
+                }
+                Some("rejected") => Verdict::Rejected,
+                Some("preferred") => Verdict::Preferred(genome_from_json(judgment.find("over"))?),
+                _ => return Err("bad verdict in history".to_string())
+            };
+            history.push(Judgment { genome: genome, verdict: verdict });
+        }
+
+        // Sessions saved before lineage tracking existed don't have a lineage
+        let lineage = match json.find("lineage") {
+            Some(l) => Lineage::from_json(l)?,
+            None => Lineage::new()
+        };
+
+        let settings = json.find("settings").ok_or("missing settings")?;
+        let rendering = rendering_settings_from_json(settings)?;
+
+        Ok(Session {
+            genome: genome,
+            population: population,
+            history: history,
+            lineage: lineage,
+            rendering: rendering
+        })
+    }
+}
+
+impl ToJson for Session {
+    fn to_json(&self) -> Json {
+        let member_to_json = |(genome, fitness): (&Genome, f32)| {
+            let mut member = BTreeMap::new();
+            member.insert("genome".to_string(), genome.to_base64().to_json());
+            member.insert("fitness".to_string(), fitness.to_json());
+            Json::Object(member)
+        };
+        let population = self.population.iter().map(&member_to_json).collect();
+        let hall_of_fame = self.population.hall_of_fame().map(&member_to_json).collect();
+        let archive = self.population.remembered().map(|genome|
+            genome.to_base64().to_json()
+        ).collect();
+
+        let history = self.history.iter().map(|judgment| {
+            let mut entry = BTreeMap::new();
+            entry.insert("genome".to_string(), judgment.genome.to_base64().to_json());
+            match judgment.verdict {
+                Verdict::Approved(rating) => {
+                    entry.insert("verdict".to_string(), "approved".to_json());
+                    entry.insert("rating".to_string(), rating.to_json());
+                }
+                Verdict::Rejected => {
+                    entry.insert("verdict".to_string(), "rejected".to_json());
+                }
+                Verdict::Preferred(ref loser) => {
+                    entry.insert("verdict".to_string(), "preferred".to_json());
+                    entry.insert("over".to_string(), loser.to_base64().to_json());
+                }
+            }
+            Json::Object(entry)
+        }).collect();
+
+        let mut session = BTreeMap::new();
+        session.insert("version".to_string(), SESSION_VERSION.to_json());
+        session.insert("genome".to_string(), self.genome.to_base64().to_json());
+        session.insert("population".to_string(), Json::Array(population));
+        session.insert("hall_of_fame".to_string(), Json::Array(hall_of_fame));
+        session.insert("archive".to_string(), Json::Array(archive));
+        session.insert("history".to_string(), Json::Array(history));
+        session.insert("lineage".to_string(), self.lineage.to_json());
+        session.insert("settings".to_string(), rendering_settings_to_json(&self.rendering));
+        Json::Object(session)
+    }
+}
+
+fn genome_from_json(json: Option<&Json>) -> Result<Genome, String> {
+    let genome_string = json.and_then(|g| g.as_string()).ok_or("missing genome")?;
+    Genome::from_base64(genome_string).map_err(|e| format!("{}: {}", e, genome_string))
+}
+
+fn rendering_settings_to_json(settings: &RenderingSettings) -> Json {
+    let mut json = BTreeMap::new();
+    json.insert("color_cycling".to_string(), settings.color_cycling.to_json());
+    json.insert("color_space".to_string(), settings.color_space.name().to_json());
+    json.insert("ditherer".to_string(), settings.ditherer.name().to_json());
+    json.insert("dithering".to_string(), settings.dithering.to_json());
+    json.insert("frames_per_second".to_string(), settings.frames_per_second.to_json());
+    json.insert("local_palettes".to_string(), settings.local_palettes.to_json());
+    json.insert("loop_duration".to_string(), settings.loop_duration.to_json());
+    json.insert("gradient".to_string(), settings.gradient.as_ref().map(|gradient|
+        gradient.iter().map(stops::format_stop).collect::<Vec<String>>().join(", ")
+    ).to_json());
+    json.insert("palette".to_string(), settings.palette.as_ref().map(|colors|
+        colors.iter().map(|&c| color_to_hex(c)).collect::<Vec<String>>()
+    ).to_json());
+    json.insert("palette_size".to_string(), settings.palette_size.to_json());
+    json.insert("temporal_threshold".to_string(), settings.temporal_threshold.to_json());
+    json.insert("transfer_function".to_string(), settings.transfer_function.name().to_json());
+    json.insert("width".to_string(), settings.width.to_json());
+    json.insert("height".to_string(), settings.height.to_json());
+    Json::Object(json)
+}
+
+fn rendering_settings_from_json(json: &Json) -> Result<RenderingSettings, String> {
+    let get_f32 = |key| json.find(key).and_then(|v| v.as_f64()).map(|v| v as f32).ok_or(
+        format!("missing setting {}", key)
+    );
+    let get_usize = |key| json.find(key).and_then(|v| v.as_u64()).map(|v| v as usize).ok_or(
+        format!("missing setting {}", key)
+    );
+    Ok(RenderingSettings {
+        color_cycling: json.find("color_cycling").and_then(|v| v.as_boolean()).unwrap_or(false),
+        // Older sessions don't have a color space, and were all rendered in linear RGB
+        color_space: match json.find("color_space").and_then(|v| v.as_string()) {
+            Some(name) => ColorSpace::from_name(name)?,
+            None => ColorSpace::LinearRgb
+        },
+        // Older sessions don't have a ditherer, and could only use Bayer dithering
+        ditherer: match json.find("ditherer").and_then(|v| v.as_string()) {
+            Some(name) => Ditherer::from_name(name)?,
+            None => Ditherer::Bayer
+        },
+        dithering: json.find("dithering").and_then(|v| v.as_boolean()).ok_or(
+            "missing setting dithering"
+        )?,
+        frames_per_second: get_f32("frames_per_second")?,
+        local_palettes: json.find("local_palettes").and_then(|v| v.as_boolean()).unwrap_or(false),
+        loop_duration: get_f32("loop_duration")?,
+        gradient: match json.find("gradient") {
+            Some(&Json::String(ref gradient)) => Some(stops::parse(gradient)?),
+            Some(&Json::Null) | None => None,
+            Some(_) => return Err("gradient must be a string of color stops".to_string())
+        },
+        palette: match json.find("palette") {
+            Some(&Json::Array(ref colors)) => Some(colors.iter().map(|c|
+                c.as_string().ok_or("palette colors must be strings".to_string())
+                    .and_then(color_from_hex)
+            ).collect::<Result<Vec<Color>, String>>()?),
+            Some(&Json::Null) | None => None,
+            Some(_) => return Err("palette must be a list of colors".to_string())
+        },
+        palette_size: match json.find("palette_size") {
+            Some(&Json::Null) | None => None,
+            Some(_) => Some(get_usize("palette_size")?)
+        },
+        temporal_threshold: match json.find("temporal_threshold") {
+            Some(&Json::Null) | None => None,
+            Some(_) => Some(get_f32("temporal_threshold")?)
+        },
+        // Older sessions don't have a transfer function, and were all rendered with a 2.2 gamma
+        transfer_function: match json.find("transfer_function").and_then(|v| v.as_string()) {
+            Some(name) => TransferFunction::from_name(name)?,
+            None => TransferFunction::Gamma22
+        },
+        width: get_usize("width")?,
+        height: get_usize("height")?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use color::{Color, ColorSpace, TransferFunction};
+    use color::palette::dither::Ditherer;
+    use color::stops::ColorStop;
+    use genetics::{Genome, Population};
+    use lineage::Lineage;
+    use settings::RenderingSettings;
+    use super::rustc_serialize::json::{Json, ToJson};
+    use super::{Judgment, Session, Verdict, SESSION_VERSION};
+
+    fn dummy_session() -> Session {
+        let mut population = Population::new(4);
+        population.add_rated(Genome::rand(), 5);
+        population.add_with_fitness(Genome::rand(), 2.75);
+        population.add_to_hall_of_fame(Genome::rand(), 4.5);
+        population.add_to_hall_of_fame(Genome::rand(), 5.0);
+        population.remember(Genome::rand());
+        population.remember(Genome::rand());
+        let genome = Genome::rand();
+        let mut lineage = Lineage::new();
+        let (child, record) = genome.breed_recorded(&Genome::rand());
+        lineage.add(child, Some(record));
+
+        // Every setting differs from the defaults, so the round trip test can tell if it's lost
+        let mut rendering = RenderingSettings::for_test();
+        rendering.color_cycling = true;
+        rendering.color_space = ColorSpace::Oklab;
+        rendering.ditherer = Ditherer::Sierra;
+        rendering.dithering = true;
+        rendering.frames_per_second = 12.5;
+        rendering.gradient = Some(vec![
+            ColorStop { color: Color::new(255, 0, 0), position: 0.0 },
+            ColorStop { color: Color::new(0, 0, 255), position: 0.75 }
+        ]);
+        rendering.local_palettes = true;
+        rendering.loop_duration = 30.0;
+        rendering.palette = Some(vec![Color::new(255, 0, 77), Color::new(0, 135, 81)]);
+        rendering.palette_size = Some(64);
+        rendering.temporal_threshold = Some(0.25);
+        rendering.width = 320;
+        rendering.height = 240;
+        Session {
+            genome: genome.clone(),
+            population: population,
+            history: vec![
+                Judgment { genome: Genome::rand(), verdict: Verdict::Approved(4) },
+                Judgment { genome: genome.clone(), verdict: Verdict::Rejected },
+                Judgment { genome: genome, verdict: Verdict::Preferred(Genome::rand()) }
+            ],
+            lineage: lineage,
+            rendering: rendering
+        }
+    }
+
+    #[test]
+    fn test_session_json_round_trip() {
+        let s1 = dummy_session();
+        let text = s1.to_json().pretty().to_string();
+        let s2 = Session::from_json(&Json::from_str(&text).unwrap()).unwrap();
+        assert_eq!(s1.genome, s2.genome);
+        assert_eq!(s1.history, s2.history);
+        assert_eq!(format!("{:?}", s1.rendering), format!("{:?}", s2.rendering));
+        let members1: Vec<_> = s1.population.iter().collect();
+        let members2: Vec<_> = s2.population.iter().collect();
+        assert_eq!(members1, members2);
+        let famous1: Vec<_> = s1.population.hall_of_fame().collect();
+        let famous2: Vec<_> = s2.population.hall_of_fame().collect();
+        assert_eq!(famous1, famous2);
+        assert_eq!(famous2.len(), 2);
+        let archive1: Vec<_> = s1.population.remembered().collect();
+        let archive2: Vec<_> = s2.population.remembered().collect();
+        assert_eq!(archive1, archive2);
+        assert_eq!(archive2.len(), 2);
+        assert!(s2.lineage.contains(&s1.genome));
+    }
+
+    #[test]
+    fn test_session_json_no_palette() {
+        let mut s1 = dummy_session();
+        s1.rendering.palette = None;
+        s1.rendering.palette_size = None;
+        let s2 = Session::from_json(&s1.to_json()).unwrap();
+        assert_eq!(s2.rendering.palette, None);
+        assert_eq!(s2.rendering.palette_size, None);
+    }
+
+    #[test]
+    fn test_session_json_no_color_space() {
+        let mut json = dummy_session().to_json();
+        let settings = json.as_object_mut().unwrap().get_mut("settings").unwrap();
+        settings.as_object_mut().unwrap().remove("color_space");
+        let s = Session::from_json(&json).unwrap();
+        assert_eq!(s.rendering.color_space, ColorSpace::LinearRgb);
+    }
+
+    #[test]
+    fn test_session_json_no_transfer_function() {
+        let mut json = dummy_session().to_json();
+        let settings = json.as_object_mut().unwrap().get_mut("settings").unwrap();
+        settings.as_object_mut().unwrap().remove("transfer_function");
+        let s = Session::from_json(&json).unwrap();
+        assert_eq!(s.rendering.transfer_function, TransferFunction::Gamma22);
+    }
+
+    #[test]
+    fn test_session_json_no_ditherer() {
+        let mut json = dummy_session().to_json();
+        let settings = json.as_object_mut().unwrap().get_mut("settings").unwrap();
+        settings.as_object_mut().unwrap().remove("ditherer");
+        let s = Session::from_json(&json).unwrap();
+        assert_eq!(s.rendering.ditherer, Ditherer::Bayer);
+    }
+
+    #[test]
+    fn test_session_json_no_local_palettes() {
+        let mut json = dummy_session().to_json();
+        let settings = json.as_object_mut().unwrap().get_mut("settings").unwrap();
+        settings.as_object_mut().unwrap().remove("local_palettes");
+        let s = Session::from_json(&json).unwrap();
+        assert!(!s.rendering.local_palettes);
+    }
+
+    #[test]
+    fn test_session_json_no_hall_of_fame() {
+        let mut json = dummy_session().to_json();
+        json.as_object_mut().unwrap().remove("hall_of_fame");
+        let s = Session::from_json(&json).unwrap();
+        assert_eq!(s.population.hall_of_fame().count(), 0);
+        assert_eq!(s.population.remembered().count(), 2);
+    }
+
+    #[test]
+    fn test_session_json_no_archive() {
+        let mut json = dummy_session().to_json();
+        json.as_object_mut().unwrap().remove("archive");
+        let s = Session::from_json(&json).unwrap();
+        assert_eq!(s.population.remembered().count(), 0);
+        assert_eq!(s.population.hall_of_fame().count(), 2);
+    }
+
+    #[test]
+    fn test_session_json_no_lineage() {
+        let mut json = dummy_session().to_json();
+        json.as_object_mut().unwrap().remove("lineage");
+        let s = Session::from_json(&json).unwrap();
+        assert!(!s.lineage.contains(&s.genome));
+    }
+
+    // A session from the first version, which only has the keys it had then
+    #[test]
+    fn test_session_json_version_1() {
+        let genome = Genome::rand();
+        let text = format!("{{\"version\": 1, \"genome\": \"{}\", \
+                            \"population\": [{{\"genome\": \"{}\", \"fitness\": 4.0}}], \
+                            \"history\": [{{\"genome\": \"{}\", \"verdict\": \"rejected\"}}], \
+                            \"settings\": {{\"dithering\": true, \"frames_per_second\": 10.0, \
+                            \"loop_duration\": 5.0, \"palette_size\": null, \
+                            \"width\": 64, \"height\": 48}}}}",
+                           genome.to_base64(), genome.to_base64(), genome.to_base64());
+        let s = Session::from_json(&Json::from_str(&text).unwrap()).unwrap();
+        assert_eq!(s.genome, genome);
+        assert_eq!(s.population.len(), 1);
+        assert_eq!(s.history, vec![Judgment { genome: genome, verdict: Verdict::Rejected }]);
+        assert_eq!(s.population.hall_of_fame().count(), 0);
+        assert_eq!(s.rendering.transfer_function, TransferFunction::Gamma22);
+        assert_eq!((s.rendering.width, s.rendering.height), (64, 48));
+    }
+
+    #[test]
+    fn test_session_json_bad_data() {
+        assert!(Session::from_json(&Json::from_str("{}").unwrap()).is_err());
+        assert!(Session::from_json(&Json::from_str("{\"version\": 999}").unwrap()).is_err());
+
+        // Sessions from newer builds might have things this one would silently drop
+        let mut json = dummy_session().to_json();
+        let newer_version = (SESSION_VERSION + 1).to_json();
+        json.as_object_mut().unwrap().insert("version".to_string(), newer_version);
+        assert!(Session::from_json(&json).is_err());
+
+        // Sessions with unparseable genomes should be rejected
+        let mut json = dummy_session().to_json();
+        json.as_object_mut().unwrap().insert("genome".to_string(), "!@#$".to_json());
+        assert!(Session::from_json(&json).is_err());
+    }
+}
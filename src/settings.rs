@@ -1,4 +1,10 @@
-use genetics::{Genome, Population};
+use color::{Color, ColorSpace, TransferFunction};
+use color::palette::dither::Ditherer;
+use color::stops::ColorStop;
+use evolve::Fitness;
+use genetics::{GeneLocks, Genome, Population};
+use lineage::Lineage;
+use session::Judgment;
 
 pub struct PlasmaSettings {
     pub genetics: GeneticSettings,
@@ -8,26 +14,108 @@ pub struct PlasmaSettings {
 
 pub struct GeneticSettings {
     pub genome: Genome,
-    pub population: Population
+    pub genomes: Vec<Genome>, // Every genome given on the command line, in order
+    pub population: Population,
+    pub history: Vec<Judgment>,
+    pub lineage: Lineage,
+    pub locks: GeneLocks,
+    pub exploration_rate: f64, // How often to skip pre-screening offspring with the PreferenceModel
+    pub session_path: Option<String>
 }
 
 #[derive(Clone,Debug)]
 pub struct RenderingSettings {
+    pub color_cycling: bool, // Animate by moving the colors through a still pattern
+    pub color_space: ColorSpace, // For mixing gradient colors and choosing palettes
+    pub ditherer: Ditherer, // How to dither, when dithering
     pub dithering: bool,
     pub frames_per_second: f32,
+    pub gradient: Option<Vec<ColorStop>>, // Colors to use instead of the genome's
+    pub local_palettes: bool, // Give each GIF frame a palette of its own
     pub loop_duration: f32,
+    pub palette: Option<Vec<Color>>, // Fixed colors to use, instead of ones chosen for each plasma
     pub palette_size: Option<usize>,
+    pub temporal_threshold: Option<f32>, // Keep pixels' previous colors when they're this close
+    pub transfer_function: TransferFunction, // For gamma encoding the output
     pub width: usize,
     pub height: usize
 }
 
+#[cfg(test)]
+impl RenderingSettings {
+    // A small, undithered plasma in its own colors. Tests change whatever they need to.
+    pub fn for_test() -> RenderingSettings {
+        RenderingSettings {
+            color_cycling: false,
+            color_space: ColorSpace::LinearRgb,
+            ditherer: Ditherer::Bayer,
+            dithering: false,
+            frames_per_second: 16.0,
+            gradient: None,
+            local_palettes: false,
+            loop_duration: 60.0,
+            palette: None,
+            palette_size: None,
+            temporal_threshold: None,
+            transfer_function: TransferFunction::Srgb,
+            width: 32,
+            height: 32
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct OutputSettings {
-    pub mode: OutputMode
+    pub mode: OutputMode,
+    pub layout: Layout,
+    pub lineage_path: Option<String>,
+    pub stats: bool // Print how much temporal dithering shrinks the GIF, at the cost of a rerender
 }
 
 #[derive(Clone,Debug)]
 pub enum OutputMode {
     File { path: String },
-    Interactive
+    Export { path: String }, // Without an extension, since there's one file for each format
+    Interactive,
+    Evolve(EvolveSettings),
+    Batch(BatchSettings),
+    Morph(MorphSettings),
+    Playlist(PlaylistSettings)
+}
+
+#[derive(Clone,Debug)]
+pub struct EvolveSettings {
+    pub generations: usize,
+    pub fitness: Vec<Fitness>,
+    pub num_top: usize, // How many of the fittest genomes to write
+    pub path: Option<String> // Where to write them, or None for stdout
+}
+
+#[derive(Clone,Debug)]
+pub struct BatchSettings {
+    pub directory: String,
+    pub name_template: String, // File name for each genome, with {genome} and {index} filled in
+    pub num_threads: usize
+}
+
+#[derive(Clone,Debug)]
+pub struct MorphSettings {
+    pub path: String,
+    pub round_trip: bool // Morph back to the first genome, so the GIF loops
+}
+
+#[derive(Clone,Debug)]
+pub struct PlaylistSettings {
+    pub loops: usize, // How many times each genome's animation plays before the next one
+    pub crossfade_seconds: f32,
+    pub fullscreen: bool,
+    pub path: Option<String> // Where to write the playlist as one GIF, or None to show it
+}
+
+// How candidates are shown in the interactive window
+#[derive(Clone,Copy,Debug)]
+pub enum Layout {
+    Single,
+    Gallery { size: usize },
+    Comparison // Two candidates side by side
 }
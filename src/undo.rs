@@ -0,0 +1,95 @@
+/*
+ * Undo and redo stacks.
+ *
+ * This module doesn't know what's being undone: callers hand it snapshots of their state,
+ * and get back the snapshots that they should restore.
+ */
+
+pub struct UndoStack<T> {
+    undo: Vec<T>,
+    redo: Vec<T>,
+    max_depth: usize
+}
+
+impl<T> UndoStack<T> {
+    pub fn new(max_depth: usize) -> UndoStack<T> {
+        UndoStack {
+            undo: vec![],
+            redo: vec![],
+            max_depth: max_depth
+        }
+    }
+
+    // Call before doing something undoable, with a snapshot of the state before the change.
+    // This forgets anything that could have been redone.
+    pub fn push(&mut self, before: T) {
+        self.push_undo(before);
+        self.redo.clear();
+    }
+
+    // Returns the state to go back to, if any. current is kept around so it can be redone.
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let previous = self.undo.pop();
+        if previous.is_some() {
+            self.redo.push(current);
+        }
+        previous
+    }
+
+    // Returns the state to go forward to, if any. current is kept around so it can be undone.
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let next = self.redo.pop();
+        if next.is_some() {
+            self.push_undo(current);
+        }
+        next
+    }
+
+    // Adds to the undo stack, forgetting the oldest state if it's full
+    fn push_undo(&mut self, state: T) {
+        self.undo.push(state);
+        if self.undo.len() > self.max_depth {
+            self.undo.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UndoStack;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut stack = UndoStack::new(10);
+        assert_eq!(stack.undo(0), None);
+        assert_eq!(stack.redo(0), None);
+
+        // 0 -> 1 -> 2, then go back to 0 and forward to 2 again
+        stack.push(0);
+        stack.push(1);
+        assert_eq!(stack.undo(2), Some(1));
+        assert_eq!(stack.undo(1), Some(0));
+        assert_eq!(stack.undo(0), None);
+        assert_eq!(stack.redo(0), Some(1));
+        assert_eq!(stack.redo(1), Some(2));
+        assert_eq!(stack.redo(2), None);
+
+        // Doing something new after an undo means there's nothing left to redo
+        assert_eq!(stack.undo(2), Some(1));
+        stack.push(1);
+        assert_eq!(stack.redo(3), None);
+        assert_eq!(stack.undo(3), Some(1));
+        assert_eq!(stack.undo(1), Some(0));
+    }
+
+    #[test]
+    fn test_undo_max_depth() {
+        let mut stack = UndoStack::new(2);
+        stack.push(0);
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.undo(3), Some(2));
+        assert_eq!(stack.undo(2), Some(1));
+        assert_eq!(stack.undo(1), None);
+    }
+}